@@ -0,0 +1,214 @@
+//! Splitting python code into its top-level chunks (functions, assignments, and other statements along with the comments directly above them).
+
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use compact_str::{CompactString, format_compact};
+use regex::Regex;
+
+static ASSIGN_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([_\w][\w.]*)\s*(?::[^=]*)?=[^=]").unwrap());
+static DEF_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(?:async\s+)?(def|class)\s+(\w+)").unwrap());
+
+/// The lexical state carried from one line of python code to the next.
+#[derive(Default)]
+struct LineState {
+    /// The number of open brackets.
+    depth: usize,
+    /// The delimiter of the triple-quoted string which is still open, if any.
+    string: Option<&'static str>,
+    /// If true, the last line ended with a backslash.
+    continued: bool,
+}
+impl LineState {
+    /// Checks if a line would start a new top-level statement (or comment), and then updates the state past the end of the line.
+    fn scan(&mut self, line: &str) -> bool {
+        let top = self.depth == 0 && self.string.is_none() && !self.continued && line.starts_with(|ch: char| !ch.is_whitespace());
+        self.continued = false;
+
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, ch)) = chars.next() {
+            if let Some(delim) = self.string {
+                match ch {
+                    '\\' => { chars.next(); }
+                    _ if line[i..].starts_with(delim) => {
+                        self.string = None;
+                        chars.nth(1);
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            match ch {
+                '#' => break,
+                '(' | '[' | '{' => self.depth += 1,
+                ')' | ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                '\\' if chars.peek().is_none() => self.continued = true,
+                '\'' | '"' => {
+                    let triple = if ch == '\'' { "'''" } else { "\"\"\"" };
+                    if line[i..].starts_with(triple) {
+                        self.string = Some(triple);
+                        chars.nth(1);
+                        continue;
+                    }
+                    while let Some((_, x)) = chars.next() {
+                        match x {
+                            '\\' => { chars.next(); }
+                            _ if x == ch => break,
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        top
+    }
+}
+
+/// A top-level statement in an editor, along with the comments and decorators directly above it.
+pub(crate) struct Chunk {
+    /// The name used to match chunks between editors, such as `def foo` or `foo` for an assignment.
+    pub key: CompactString,
+    /// The first line of the chunk (zero-based), which may be a comment.
+    pub start: usize,
+    /// The first line after any leading comments.
+    pub code_start: usize,
+    /// The line after the last non-blank line of the chunk.
+    pub end: usize,
+    /// The number of blank lines after the chunk.
+    pub gap: usize,
+    /// The name of the function or class, if the chunk is a definition.
+    pub def_name: Option<CompactString>,
+}
+impl Chunk {
+    pub fn text<'a>(&self, lines: &[&'a str]) -> Vec<&'a str> {
+        lines[self.start..self.end].to_vec()
+    }
+}
+
+/// Gets the key of a top-level statement from its first line, along with its name if it is a definition.
+fn chunk_key(line: &str) -> (CompactString, Option<CompactString>) {
+    if let Some(def) = DEF_REGEX.captures(line) {
+        return (format_compact!("{} {}", &def[1], &def[2]), Some(def[2].into()));
+    }
+    if let Some(assign) = ASSIGN_REGEX.captures(line) {
+        return (assign[1].into(), None);
+    }
+    // other statements are matched by their first line, up to the first argument of a call (e.g., `nb.message_type('foo'`)
+    (line.split(',').next().unwrap_or_default().trim_end().into(), None)
+}
+
+/// Hashes some lines of code, so that a merge can tell if a generated chunk was edited by hand.
+/// This uses 64-bit FNV-1a rather than the standard hasher, whose output may change between versions of Rust.
+pub(crate) fn hash_lines(lines: &[&str]) -> CompactString {
+    let hash = lines.join("\n").bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    format_compact!("{hash:016x}")
+}
+
+/// Hashes each top-level statement in some code which is not a definition (such as an assignment), keyed by its chunk key.
+/// Like [`hash_lines`], this does not count the comments above each statement.
+pub(crate) fn statement_hashes(code: &str) -> BTreeMap<CompactString, CompactString> {
+    let lines: Vec<_> = code.lines().collect();
+    let mut res = BTreeMap::new();
+    for chunk in split_chunks(&lines).into_iter().filter(|x| x.def_name.is_none()) {
+        res.entry(chunk.key).or_insert_with(|| hash_lines(&lines[chunk.code_start..chunk.end]));
+    }
+    res
+}
+
+/// Splits python code into its top-level chunks.
+pub(crate) fn split_chunks(lines: &[&str]) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = vec![];
+    let mut state = LineState::default();
+    let mut pending: Option<(usize, Option<usize>)> = None; // the first comment or decorator line, and the first decorator line
+
+    let flush_comments = |chunks: &mut Vec<Chunk>, pending: &mut Option<(usize, Option<usize>)>, end: usize| {
+        if let Some((start, None)) = *pending {
+            *pending = None;
+            let key = format_compact!("{}", lines[start..end].join("\n"));
+            chunks.push(Chunk { key, start, code_start: start, end, gap: 0, def_name: None });
+        }
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        let top = state.scan(line);
+        if line.trim().is_empty() {
+            flush_comments(&mut chunks, &mut pending, i);
+            if let Some(chunk) = chunks.last_mut() {
+                if pending.is_none() && chunk.end + chunk.gap == i { chunk.gap += 1 }
+            }
+            continue;
+        }
+
+        if !top {
+            // comments at the start of a line inside a function are part of the function
+            if matches!(pending, Some((_, None))) { pending = None }
+            if let Some(chunk) = chunks.last_mut() {
+                chunk.end = i + 1;
+                chunk.gap = 0;
+            }
+            continue;
+        }
+        match line.chars().next() {
+            Some('#') => { pending.get_or_insert((i, None)); }
+            Some('@') => {
+                let entry = pending.get_or_insert((i, Some(i)));
+                entry.1.get_or_insert(i);
+            }
+            _ if pending.is_none() && ["else", "elif", "except", "finally"].iter().any(|x| line.starts_with(x)) => if let Some(chunk) = chunks.last_mut() {
+                chunk.end = i + 1;
+                chunk.gap = 0;
+            }
+            _ => {
+                let (key, def_name) = chunk_key(line);
+                let (start, decorator) = pending.take().unwrap_or((i, None));
+                chunks.push(Chunk { key, start, code_start: decorator.unwrap_or(i), end: i + 1, gap: 0, def_name });
+            }
+        }
+    }
+    flush_comments(&mut chunks, &mut pending, lines.len());
+
+    chunks
+}
+
+#[test]
+fn test_split_chunks() {
+    let code = r#"from netsblox import snap
+
+x = snap.wrap([
+    1,
+])
+y: int = 2
+nb.message_type('msg', ['a'])
+
+# a free comment
+
+# hat comment
+@onstart()
+def on_start(self):
+    s = """
+def not_a_function():
+"""
+# odd comment
+    if x == '#':
+        pass
+
+    return 1
+if x:
+    pass
+else:
+    pass
+"#;
+    let lines: Vec<_> = code.lines().collect();
+    let chunks: Vec<_> = split_chunks(&lines).into_iter().map(|x| (x.key, x.start, x.code_start, x.end, x.gap, x.def_name)).collect();
+    assert_eq!(chunks, [
+        ("from netsblox import snap".into(), 0, 0, 1, 1, None),
+        ("x".into(), 2, 2, 5, 0, None),
+        ("y".into(), 5, 5, 6, 0, None),
+        ("nb.message_type('msg'".into(), 6, 6, 7, 1, None),
+        ("# a free comment".into(), 8, 8, 9, 1, None),
+        ("def on_start".into(), 10, 11, 21, 0, Some("on_start".into())),
+        ("if x:".into(), 21, 21, 25, 0, None),
+    ]);
+}
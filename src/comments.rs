@@ -0,0 +1,144 @@
+//! Recovery of the comments (and other details of custom blocks) which the project parser does not keep.
+//!
+//! Comments attached to blocks are part of the AST, but comments on custom block definitions and free-floating comments in the scripting area are dropped,
+//! as are the type, category, and input slots of custom blocks and the message types which no script uses. These are found by scanning the project XML directly.
+
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use compact_str::CompactString;
+use netsblox_ast::Function;
+use netsblox_ast::util::xml_unescape;
+use regex::Regex;
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+static BLOCK_PARAM_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"%'[^']*'").unwrap());
+
+/// The comments of a single role which are not attached to any block.
+#[derive(Debug, Default)]
+pub(crate) struct RoleComments {
+    /// Comments on custom block definitions, keyed by the name of the owning entity (or `None` for global blocks) and the name of the block.
+    funcs: BTreeMap<(Option<CompactString>, CompactString), CompactString>,
+    /// Free-floating comments in the scripting area of each entity, in document order.
+    free: BTreeMap<CompactString, Vec<CompactString>>,
+    /// The definitions of custom blocks, keyed the same way as [`RoleComments::funcs`].
+    blocks: BTreeMap<(Option<CompactString>, CompactString), BlockDef>,
+    /// The declared message types and their fields, in document order.
+    msg_types: Vec<(CompactString, Vec<CompactString>)>,
+}
+impl RoleComments {
+    /// Gets the comment on the definition of a custom block, which is owned by the named entity (or `None` for global blocks).
+    pub(crate) fn func(&self, entity: Option<&str>, func: &Function) -> Option<&str> {
+        self.funcs.get(&(entity.map(Into::into), func.name.clone())).map(CompactString::as_str)
+    }
+    /// Gets the definition of a custom block, which is owned by the named entity (or `None` for global blocks).
+    pub(crate) fn block(&self, entity: Option<&str>, func: &Function) -> Option<&BlockDef> {
+        self.blocks.get(&(entity.map(Into::into), func.name.clone()))
+    }
+    /// Gets the free-floating comments in the scripting area of the named entity.
+    pub(crate) fn free(&self, entity: &str) -> &[CompactString] {
+        self.free.get(entity).map(Vec::as_slice).unwrap_or_default()
+    }
+    /// Gets the message types declared by the role, along with their fields.
+    pub(crate) fn msg_types(&self) -> &[(CompactString, Vec<CompactString>)] {
+        &self.msg_types
+    }
+}
+
+/// The details of a custom block definition which are not part of the AST.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct BlockDef {
+    /// The shape of the block: `command`, `reporter`, or `predicate`.
+    pub ty: CompactString,
+    pub category: CompactString,
+    /// The slot type of each input, such as `%n` for numbers, or empty for the default (any type).
+    pub inputs: Vec<CompactString>,
+}
+
+/// Gets the name the parser gives to a custom block, which replaces each parameter in its spec with a tab.
+fn block_name(spec: &str) -> CompactString {
+    BLOCK_PARAM_REGEX.replace_all(spec, "\t").into()
+}
+
+struct Element<'a> {
+    name: &'a str,
+    /// The `name` of an entity or the spec (`s`) of a custom block definition.
+    label: Option<CompactString>,
+    /// The details of a custom block definition, which are only filled in for `block-definition` elements.
+    def: BlockDef,
+}
+
+/// Collects the comments of each role in a project, in the same order as the parsed roles.
+///
+/// This assumes the source has already been parsed successfully, so malformed XML simply ends the scan.
+pub(crate) fn collect(source: &str) -> Vec<RoleComments> {
+    let mut roles: Vec<RoleComments> = vec![];
+    let mut stack: Vec<Element> = vec![];
+    let mut text = String::new();
+
+    for token in Tokenizer::from(source) {
+        let Ok(token) = token else { break };
+        match token {
+            Token::ElementStart { local, .. } => {
+                if local.as_str() == "project" { roles.push(RoleComments::default()) }
+                if matches!(local.as_str(), "comment" | "name" | "field") { text.clear() }
+                stack.push(Element { name: local.as_str(), label: None, def: BlockDef::default() });
+            }
+            Token::Attribute { local, value, .. } => {
+                let value = xml_unescape(value.as_str()).ok();
+                // the slot types of a custom block are in its <inputs> list, in the same order as its parameters
+                if let [.., def, inputs, input] = stack.as_mut_slice() {
+                    if (def.name, inputs.name, input.name, local.as_str()) == ("block-definition", "inputs", "input", "type") {
+                        def.def.inputs.push(value.clone().unwrap_or_default());
+                    }
+                }
+                let Some(top) = stack.last_mut() else { continue };
+                match (top.name, local.as_str()) {
+                    ("stage" | "sprite", "name") | ("block-definition", "s") => top.label = value,
+                    ("block-definition", "type") => top.def.ty = value.unwrap_or_default(),
+                    ("block-definition", "category") => top.def.category = value.unwrap_or_default(),
+                    _ => (),
+                }
+            }
+            Token::Text { text: x } | Token::Cdata { text: x, .. } if stack.last().is_some_and(|x| matches!(x.name, "comment" | "name" | "field")) => {
+                text.push_str(&xml_unescape(x.as_str()).unwrap_or_else(|_| x.as_str().into()));
+            }
+            Token::ElementEnd { end: ElementEnd::Open, .. } => (),
+            Token::ElementEnd { .. } => {
+                let Some(element) = stack.pop() else { break };
+                if let ("block-definition", Some(spec), Some(role)) = (element.name, &element.label, roles.last_mut()) {
+                    let entity = stack.iter().rev().find(|x| matches!(x.name, "stage" | "sprite")).and_then(|x| x.label.clone());
+                    role.blocks.insert((entity, block_name(spec)), element.def);
+                    continue;
+                }
+                // a message type is <messageType><name>...</name><fields><field>...</field>...</fields></messageType>
+                match (element.name, stack.as_slice(), roles.last_mut()) {
+                    ("name", [.., parent], Some(role)) if parent.name == "messageType" => {
+                        role.msg_types.push((text.as_str().into(), vec![]));
+                        continue;
+                    }
+                    ("field", [.., msg_type, fields], Some(role)) if (msg_type.name, fields.name) == ("messageType", "fields") => {
+                        if let Some(x) = role.msg_types.last_mut() { x.1.push(text.as_str().into()) }
+                        continue;
+                    }
+                    _ => (),
+                }
+                if element.name != "comment" || text.trim().is_empty() { continue }
+                let (Some(role), Some(parent)) = (roles.last_mut(), stack.last()) else { continue };
+                let entity = stack.iter().rev().find(|x| matches!(x.name, "stage" | "sprite")).and_then(|x| x.label.clone());
+                match (parent.name, &parent.label) {
+                    ("scripts", _) => if let Some(entity) = entity {
+                        role.free.entry(entity).or_default().push(text.as_str().into());
+                    }
+                    ("block-definition", Some(spec)) => {
+                        role.funcs.insert((entity, block_name(spec)), text.as_str().into());
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    roles
+}
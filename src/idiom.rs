@@ -0,0 +1,257 @@
+//! The idiomatic output style, which rewrites the faithful translation into plain Python wherever possible.
+//!
+//! Faithful code keeps Snap's semantics by wrapping values and calling `snap` helpers.
+//! This pass removes the wrappers and swaps the helpers for their closest Python equivalents (builtins, `math`, `random`, and native list operations).
+//! Anything which has no plain equivalent is kept as-is and flagged with a warning comment on its statement.
+
+use compact_str::{CompactString, format_compact};
+
+use crate::numeric_literal;
+use crate::ir::{Arg, BinOp, CmpOp, Expr, Stmt, StmtKind, UnaryOp};
+
+/// Methods and properties which only exist on Snap's wrapped lists.
+const SNAP_LIST_ATTRS: &[&str] = &["rand", "insert_rand", "flat", "T", "shape", "reshaped", "csv", "json", "index_where", "fold"];
+/// Sprite properties which PyBlox always stores as numbers.
+const NUMBER_ATTRS: &[&str] = &["self.x_pos", "self.y_pos", "self.heading", "self.pen_size", "self.scale"];
+/// Functions which always return plain numbers (given plain numbers).
+const NUMBER_FUNCS: &[&str] = &["len", "abs", "round", "float", "int", "ord", "random.randint"];
+
+/// Rewrites a sequence of statements into the idiomatic style.
+pub(crate) fn idiomatize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(stmt).collect()
+}
+
+fn stmt(stmt: Stmt) -> Stmt {
+    let Stmt { kind, comment, source } = stmt;
+    let kind = match kind {
+        StmtKind::Expr(x) => StmtKind::Expr(expr(x)),
+        StmtKind::Assign(Expr::Attr(x, attr), v) if attr == "rand" => StmtKind::Assign(Expr::Attr(Box::new(expr(*x)), attr), expr(v)), // not a valid target for random.choice
+        StmtKind::Assign(Expr::Name(t), v) if NUMBER_ATTRS.contains(&t.as_str()) => StmtKind::Assign(Expr::Name(t), number(&expr(v))),
+        StmtKind::Assign(t, v) => StmtKind::Assign(expr(t), expr(v)),
+        StmtKind::AugAssign(t, op, v) => StmtKind::AugAssign(expr(t), op, numeric(expr(v))), // only used for arithmetic, such as "change by"
+        StmtKind::Del(x) => StmtKind::Del(expr(x)),
+        StmtKind::Raise(x) => StmtKind::Raise(expr(x)),
+        StmtKind::Return(x) => StmtKind::Return(expr(x)),
+        StmtKind::If { condition, then, otherwise } => StmtKind::If { condition: expr(condition), then: idiomatize(then), otherwise: idiomatize(otherwise) },
+        StmtKind::While { condition, body } => StmtKind::While { condition: expr(condition), body: idiomatize(body) },
+        StmtKind::For { target, iter, body } => StmtKind::For { target, iter: expr(iter), body: idiomatize(body) },
+        StmtKind::With { context, body } => StmtKind::With { context: expr(context), body: idiomatize(body) },
+        StmtKind::Try { body, var, handler } => StmtKind::Try { body: idiomatize(body), var, handler: idiomatize(handler) },
+        StmtKind::FunctionDef(mut def) => {
+            def.decorators = def.decorators.into_iter().map(expr).collect();
+            def.body = idiomatize(def.body);
+            StmtKind::FunctionDef(def)
+        }
+        x @ (StmtKind::Import(_) | StmtKind::Pass | StmtKind::Blank | StmtKind::Docstring(_)) => x,
+    };
+
+    let mut fallbacks: Vec<CompactString> = vec![];
+    for x in kind.exprs() {
+        x.walk(&mut |x| {
+            let fallback = match x {
+                Expr::Name(name) if name.starts_with("snap.") => name.clone(),
+                Expr::Attr(_, attr) if SNAP_LIST_ATTRS.contains(&attr.as_str()) => format_compact!(".{attr}"),
+                _ => return,
+            };
+            if !fallbacks.contains(&fallback) { fallbacks.push(fallback) }
+        });
+    }
+    let comment = match fallbacks.is_empty() {
+        true => comment,
+        false => {
+            let warning = format_compact!("warning: no plain python equivalent for {}", fallbacks.join(", "));
+            Some(match comment {
+                Some(comment) => format_compact!("{comment}\n{warning}"),
+                None => warning,
+            })
+        }
+    };
+    Stmt { kind, comment, source }
+}
+
+fn expr(expr: Expr) -> Expr {
+    expr.map(&mut rewrite)
+}
+
+/// Converts numeric text into a number literal, for use where the python equivalent of a helper only accepts numbers.
+/// Text elsewhere (such as message fields, dictionary keys, and decorator arguments) must keep its type, so this is never applied in general.
+fn number(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Str(x) => numeric_literal(x).map(Expr::Num).unwrap_or_else(|| expr.clone()),
+        _ => expr.clone(),
+    }
+}
+/// Gets the value of a number literal which holds an integer.
+fn int_value(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Num(x) if x.fract() == 0.0 => Some(*x),
+        _ => None,
+    }
+}
+/// Checks if an expression always evaluates to a plain python number.
+fn is_number(expr: &Expr) -> bool {
+    match expr {
+        Expr::Num(_) => true,
+        Expr::Name(name) => NUMBER_ATTRS.contains(&name.as_str()) || name == "math.pi" || name == "math.e",
+        Expr::Unary(UnaryOp::Neg | UnaryOp::Pos, x) => is_number(x),
+        Expr::Bin(_, a, b) => is_number(a) && is_number(b),
+        Expr::Call(f, _) => match &**f {
+            Expr::Name(name) => name.starts_with("math.") || NUMBER_FUNCS.contains(&name.as_str()),
+            Expr::Attr(_, method) => method == "index",
+            _ => false,
+        }
+        _ => false,
+    }
+}
+/// Checks if an expression is a call to a `snap` helper, whose result already has Snap's semantics.
+fn is_snap(expr: &Expr) -> bool {
+    matches!(expr, Expr::Call(f, _) if matches!(&**f, Expr::Name(name) if name.starts_with("snap.")))
+}
+/// Checks if an expression is generated text, so that `+` on it is string concatenation (e.g., from "join") rather than Snap's addition.
+fn is_text(expr: &Expr) -> bool {
+    matches!(expr, Expr::FStr(_)) || matches!(expr, Expr::Call(f, _) if matches!(&**f, Expr::Name(name) if name == "str"))
+}
+/// Prepares an operand of a block which Snap treats as numeric.
+/// Numeric text becomes a number literal, and anything else which can't be proven to be a number is wrapped to keep Snap's semantics
+/// (e.g., text inputs, lists for element-wise arithmetic, and text which Snap would treat as zero).
+fn numeric(expr: Expr) -> Expr {
+    match number(&expr) {
+        x if is_number(&x) || is_snap(&x) => x,
+        x => Expr::name("snap.wrap").call(vec![x]),
+    }
+}
+/// Prepares both operands of a numeric operator with [`numeric`].
+/// Only one of them needs to be wrapped, since a single wrapped operand gives Snap's semantics.
+fn operands(a: Expr, b: Expr) -> (Expr, Expr) {
+    let (a, b) = (numeric(a), numeric(b));
+    match b {
+        Expr::Call(f, mut args) if is_snap(&a) && matches!(&*f, Expr::Name(name) if name == "snap.wrap") => match args.pop() {
+            Some(Arg::Pos(b)) => (a, b),
+            _ => unreachable!(),
+        }
+        b => (a, b),
+    }
+}
+/// Translates an inclusive Snap range with constant bounds into a python `range`, which can count in either direction.
+fn range(start: f64, stop: f64) -> Expr {
+    let mut args = vec![Expr::Num(start)];
+    match start <= stop {
+        true => args.push(Expr::Num(stop + 1.0)),
+        false => args.extend([Expr::Num(stop - 1.0), Expr::Num(-1.0)]),
+    }
+    Expr::name("range").call(args)
+}
+
+/// Rewrites a single expression whose sub-expressions have already been rewritten.
+fn rewrite(expr: Expr) -> Expr {
+    match expr {
+        Expr::Attr(x, attr) if attr == "last" => x.index(Expr::Num(-1.0)),
+        Expr::Attr(x, attr) if attr == "rand" => Expr::name("random.choice").call(vec![*x]),
+        Expr::Bin(BinOp::Add, a, b) if is_text(&a) || is_text(&b) => Expr::Bin(BinOp::Add, a, b),
+        Expr::Bin(op, a, b) => {
+            let (a, b) = operands(*a, *b);
+            Expr::bin(op, a, b)
+        }
+        Expr::Cmp(op, a, b) if op != CmpOp::In => {
+            let (a, b) = operands(*a, *b);
+            Expr::cmp(op, a, b)
+        }
+        Expr::Unary(op @ (UnaryOp::Neg | UnaryOp::Pos), x) => Expr::unary(op, numeric(*x)),
+        Expr::Call(f, args) => {
+            let mut pos = vec![];
+            for arg in args.iter() {
+                match arg {
+                    Arg::Pos(x) => pos.push(x.clone()),
+                    _ => return Expr::Call(f, args),
+                }
+            }
+            match (&*f, pos.as_slice()) {
+                (Expr::Name(name), _) => match rewrite_helper(name, pos) {
+                    Some(x) => x,
+                    None => Expr::Call(f, args),
+                }
+                (Expr::Attr(list, method), [index, value]) if method == "insert" => {
+                    (**list).clone().method("insert", vec![Expr::bin(BinOp::Sub, number(index), Expr::Num(1.0)), value.clone()])
+                }
+                _ => Expr::Call(f, args),
+            }
+        }
+        x => x,
+    }
+}
+
+/// Gets the plain python equivalent of a call to a `snap` helper, if there is one.
+fn rewrite_helper(name: &str, args: Vec<Expr>) -> Option<Expr> {
+    let call = |f: &str, args: Vec<Expr>| Expr::name(f).call(args);
+    let degrees = |x: Expr| call("math.degrees", vec![x]);
+    match (name, args.as_slice()) {
+        ("round" | "abs", [x]) => return Some(call(name, vec![numeric(x.clone())])),
+        ("min" | "max", [Expr::List(items)]) if items.iter().all(|x| is_number(&number(x))) => {
+            return Some(call(name, vec![Expr::List(items.iter().map(number).collect())]));
+        }
+        ("min" | "max", [x]) => return Some(call(name, vec![Expr::name("snap.wrap").call(vec![x.clone()])])),
+        _ => (),
+    }
+    let helper = name.strip_prefix("snap.")?;
+    let args = match helper {
+        "sqrt" | "get_chr" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "atan2" | "log" | "rand" | "sxrange" | "srange" => args.iter().map(number).collect(),
+        _ => args,
+    };
+    Some(match (helper, args.as_slice()) {
+        ("wrap", [x]) => x.clone(),
+        ("sqrt", _) => call("math.sqrt", args),
+        ("prod", _) => call("math.prod", args),
+        ("get_ord", _) => call("ord", args),
+        ("get_chr", _) => call("chr", args),
+        ("sin" | "cos" | "tan", [x]) => call(&format!("math.{helper}"), vec![call("math.radians", vec![x.clone()])]),
+        ("asin" | "acos" | "atan" | "atan2", _) => degrees(call(&format!("math.{helper}"), args)),
+        ("log", [x, base]) => match base {
+            Expr::Name(b) if b == "math.e" => call("math.log", vec![x.clone()]),
+            Expr::Num(b) if *b == 10.0 => call("math.log10", vec![x.clone()]),
+            Expr::Num(b) if *b == 2.0 => call("math.log2", vec![x.clone()]),
+            _ => call("math.log", args),
+        }
+        ("lnot", [x]) => Expr::unary(UnaryOp::Not, x.clone()),
+        ("is_number", [Expr::Str(x)]) => Expr::Bool(numeric_literal(x).is_some()),
+        ("is_number", [x]) if is_number(x) => Expr::Bool(true),
+        ("is_number", _) => return None, // text may hold a number
+        ("is_text", [Expr::Str(x)]) => Expr::Bool(numeric_literal(x).is_none()),
+        ("is_text", [x]) if is_number(x) => Expr::Bool(false),
+        ("is_text", _) => return None,
+        ("is_bool", [x]) => call("isinstance", vec![x.clone(), Expr::name("bool")]),
+        ("is_list", [x]) => call("isinstance", vec![x.clone(), Expr::name("list")]),
+        ("split", [x, Expr::Str(sep)]) if sep.is_empty() => call("list", vec![x.clone()]),
+        ("split", [x, sep @ Expr::Str(_)]) => x.clone().method("split", vec![sep.clone()]),
+        ("split_words", [x]) => x.clone().method("split", vec![]),
+        ("rand", [a, b]) => {
+            let (a, b) = (int_value(a)?, int_value(b)?);
+            call("random.randint", vec![Expr::Num(a.min(b)), Expr::Num(a.max(b))])
+        }
+        ("sxrange", [a, b]) => range(int_value(a)?, int_value(b)?),
+        ("srange", [a, b]) => call("list", vec![range(int_value(a)?, int_value(b)?)]),
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_idiomatize() {
+    let snap = |f: &str, args: Vec<Expr>| Expr::name(&format!("snap.{f}")).call(args);
+    let x = Expr::name("x");
+    let stmts = vec![
+        Stmt::from(StmtKind::Assign(x.clone(), snap("wrap", vec![Expr::List(vec![Expr::str("1"), Expr::str("007")])]))),
+        StmtKind::For { target: "i".into(), iter: snap("sxrange", vec![Expr::Num(10.0), Expr::Num(1.0)]), body: vec![
+            Stmt::from(x.clone().method("insert", vec![Expr::Num(1.0), snap("sin", vec![Expr::name("i")])])),
+        ] }.into(),
+        StmtKind::Assign(x.clone().attr("last"), snap("split_csv", vec![Expr::str("a,b")])).into(),
+        StmtKind::Assign(x.clone(), Expr::bin(BinOp::Mul, snap("wrap", vec![x.clone()]), snap("wrap", vec![Expr::str("3")]))).into(),
+    ];
+    assert_eq!(crate::ir::print_stmts(&idiomatize(stmts), 88).0, r#"
+x = ['1', '007']
+for i in range(10, 0, -1):
+    x.insert(1 - 1, math.sin(math.radians(i)))
+# warning: no plain python equivalent for snap.split_csv
+x[-1] = snap.split_csv('a,b')
+# warning: no plain python equivalent for snap.wrap
+x = snap.wrap(x) * 3
+"#.trim());
+}
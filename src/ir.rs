@@ -0,0 +1,801 @@
+//! A small typed model of the Python code we generate, along with the pretty-printer which turns it into source code.
+//!
+//! The translators build these trees rather than formatting strings directly.
+//! The printer is then the only place which has to care about operator precedence, parentheses, and indentation.
+
+use std::fmt::{self, Write};
+use std::iter;
+
+use compact_str::CompactString;
+use netsblox_ast::util::escape;
+
+mod layout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnaryOp {
+    Neg, Pos, Not,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinOp {
+    Add, Sub, Mul, Div, Mod, Pow,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CmpOp {
+    Less, LessEq, Eq, Neq, Greater, GreaterEq, In,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogicOp {
+    And, Or,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompKind {
+    List, Gen,
+}
+
+impl UnaryOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Pos => "+",
+            UnaryOp::Not => "not ",
+        }
+    }
+}
+impl BinOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Pow => "**",
+        }
+    }
+}
+impl CmpOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            CmpOp::Less => "<",
+            CmpOp::LessEq => "<=",
+            CmpOp::Eq => "==",
+            CmpOp::Neq => "!=",
+            CmpOp::Greater => ">",
+            CmpOp::GreaterEq => ">=",
+            CmpOp::In => "in",
+        }
+    }
+}
+impl LogicOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogicOp::And => "and",
+            LogicOp::Or => "or",
+        }
+    }
+}
+
+/// A piece of an f-string.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FStrPart {
+    /// Raw (unescaped) literal text.
+    Lit(CompactString),
+    /// An interpolated expression.
+    Expr(Expr),
+}
+
+/// An argument in a function call.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Arg {
+    Pos(Expr),
+    Kw(CompactString, Expr),
+    Star(Expr),
+    DoubleStar(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    /// A (possibly dotted) name such as `x`, `self.x_pos`, or `snap.wrap`.
+    Name(CompactString),
+    Num(f64),
+    /// A string literal holding the raw (unescaped) value.
+    Str(CompactString),
+    FStr(Vec<FStrPart>),
+    Bool(bool),
+    None,
+
+    List(Vec<Expr>),
+    Tuple(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    /// An unpacked value (`*x`), which is only valid inside list and tuple literals.
+    Starred(Box<Expr>),
+
+    Attr(Box<Expr>, CompactString),
+    Call(Box<Expr>, Vec<Arg>),
+    Index(Box<Expr>, Box<Expr>),
+    /// A slice (`a:b:c`), which is only valid as the index of an [`Expr::Index`].
+    Slice(Option<Box<Expr>>, Option<Box<Expr>>, Option<Box<Expr>>),
+
+    Unary(UnaryOp, Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+    Logic(LogicOp, Box<Expr>, Box<Expr>),
+    IfElse { then: Box<Expr>, condition: Box<Expr>, otherwise: Box<Expr> },
+
+    Lambda(Vec<CompactString>, Box<Expr>),
+    /// A list comprehension or generator expression with one or more `for` clauses and an optional `if` filter.
+    Comp { kind: CompKind, item: Box<Expr>, clauses: Vec<(CompactString, Expr)>, condition: Option<Box<Expr>> },
+}
+impl Expr {
+    pub(crate) fn name(name: &str) -> Self {
+        Expr::Name(name.into())
+    }
+    pub(crate) fn str(value: &str) -> Self {
+        Expr::Str(value.into())
+    }
+    pub(crate) fn attr(self, attr: &str) -> Self {
+        Expr::Attr(Box::new(self), attr.into())
+    }
+    pub(crate) fn call(self, args: Vec<Expr>) -> Self {
+        Expr::Call(Box::new(self), args.into_iter().map(Arg::Pos).collect())
+    }
+    pub(crate) fn call_args(self, args: Vec<Arg>) -> Self {
+        Expr::Call(Box::new(self), args)
+    }
+    pub(crate) fn method(self, method: &str, args: Vec<Expr>) -> Self {
+        self.attr(method).call(args)
+    }
+    pub(crate) fn index(self, index: Expr) -> Self {
+        Expr::Index(Box::new(self), Box::new(index))
+    }
+    pub(crate) fn unary(op: UnaryOp, value: Expr) -> Self {
+        Expr::Unary(op, Box::new(value))
+    }
+    pub(crate) fn bin(op: BinOp, left: Expr, right: Expr) -> Self {
+        Expr::Bin(op, Box::new(left), Box::new(right))
+    }
+    pub(crate) fn cmp(op: CmpOp, left: Expr, right: Expr) -> Self {
+        Expr::Cmp(op, Box::new(left), Box::new(right))
+    }
+    pub(crate) fn logic(op: LogicOp, left: Expr, right: Expr) -> Self {
+        Expr::Logic(op, Box::new(left), Box::new(right))
+    }
+    /// Folds a non-empty sequence of operands into a left-associative chain of binary operations.
+    pub(crate) fn bin_chain(op: BinOp, values: Vec<Expr>) -> Self {
+        let mut values = values.into_iter();
+        let first = values.next().expect("empty operator chain");
+        values.fold(first, |a, b| Expr::bin(op, a, b))
+    }
+
+    /// Gets the direct sub-expressions of this expression.
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Name(_) | Expr::Num(_) | Expr::Str(_) | Expr::Bool(_) | Expr::None => vec![],
+            Expr::FStr(parts) => parts.iter().filter_map(|x| match x { FStrPart::Expr(x) => Some(x), FStrPart::Lit(_) => None }).collect(),
+            Expr::List(x) | Expr::Tuple(x) => x.iter().collect(),
+            Expr::Dict(x) => x.iter().flat_map(|(k, v)| [k, v]).collect(),
+            Expr::Starred(x) | Expr::Attr(x, _) | Expr::Unary(_, x) | Expr::Lambda(_, x) => vec![x],
+            Expr::Call(f, args) => iter::once(&**f).chain(args.iter().map(|x| match x { Arg::Pos(x) | Arg::Kw(_, x) | Arg::Star(x) | Arg::DoubleStar(x) => x })).collect(),
+            Expr::Index(a, b) | Expr::Bin(_, a, b) | Expr::Cmp(_, a, b) | Expr::Logic(_, a, b) => vec![a, b],
+            Expr::Slice(a, b, c) => [a, b, c].into_iter().flatten().map(|x| &**x).collect(),
+            Expr::IfElse { then, condition, otherwise } => vec![then, condition, otherwise],
+            Expr::Comp { item, clauses, condition, .. } => iter::once(&**item).chain(clauses.iter().map(|x| &x.1)).chain(condition.as_deref()).collect(),
+        }
+    }
+    /// Visits this expression and all of its sub-expressions in pre-order.
+    pub(crate) fn walk(&self, f: &mut dyn FnMut(&Expr)) {
+        f(self);
+        for child in self.children() {
+            child.walk(f);
+        }
+    }
+    /// Rebuilds this expression bottom-up, passing each (already rebuilt) sub-expression and finally this expression through `f`.
+    pub(crate) fn map(self, f: &mut dyn FnMut(Expr) -> Expr) -> Expr {
+        fn g(mut x: Box<Expr>, f: &mut dyn FnMut(Expr) -> Expr) -> Box<Expr> {
+            *x = std::mem::replace(&mut *x, Expr::None).map(f);
+            x
+        }
+        let res = match self {
+            x @ (Expr::Name(_) | Expr::Num(_) | Expr::Str(_) | Expr::Bool(_) | Expr::None) => x,
+            Expr::FStr(parts) => Expr::FStr(parts.into_iter().map(|x| match x { FStrPart::Expr(x) => FStrPart::Expr(x.map(f)), x => x }).collect()),
+            Expr::List(x) => Expr::List(x.into_iter().map(|x| x.map(f)).collect()),
+            Expr::Tuple(x) => Expr::Tuple(x.into_iter().map(|x| x.map(f)).collect()),
+            Expr::Dict(x) => Expr::Dict(x.into_iter().map(|(k, v)| (k.map(f), v.map(f))).collect()),
+            Expr::Starred(x) => Expr::Starred(g(x, f)),
+            Expr::Attr(x, attr) => Expr::Attr(g(x, f), attr),
+            Expr::Call(func, args) => {
+                let func = g(func, f);
+                Expr::Call(func, args.into_iter().map(|x| match x {
+                    Arg::Pos(x) => Arg::Pos(x.map(f)),
+                    Arg::Kw(k, x) => Arg::Kw(k, x.map(f)),
+                    Arg::Star(x) => Arg::Star(x.map(f)),
+                    Arg::DoubleStar(x) => Arg::DoubleStar(x.map(f)),
+                }).collect())
+            }
+            Expr::Index(a, b) => Expr::Index(g(a, f), g(b, f)),
+            Expr::Slice(a, b, c) => Expr::Slice(a.map(|x| g(x, f)), b.map(|x| g(x, f)), c.map(|x| g(x, f))),
+            Expr::Unary(op, x) => Expr::Unary(op, g(x, f)),
+            Expr::Bin(op, a, b) => Expr::Bin(op, g(a, f), g(b, f)),
+            Expr::Cmp(op, a, b) => Expr::Cmp(op, g(a, f), g(b, f)),
+            Expr::Logic(op, a, b) => Expr::Logic(op, g(a, f), g(b, f)),
+            Expr::IfElse { then, condition, otherwise } => Expr::IfElse { then: g(then, f), condition: g(condition, f), otherwise: g(otherwise, f) },
+            Expr::Lambda(params, body) => Expr::Lambda(params, g(body, f)),
+            Expr::Comp { kind, item, clauses, condition } => Expr::Comp {
+                kind,
+                item: g(item, f),
+                clauses: clauses.into_iter().map(|(t, x)| (t, x.map(f))).collect(),
+                condition: condition.map(|x| g(x, f)),
+            },
+        };
+        f(res)
+    }
+
+    /// Gets the binding strength of this expression, where higher values bind more tightly.
+    fn prec(&self) -> u8 {
+        match self {
+            Expr::Lambda(..) => 1,
+            Expr::IfElse { .. } => 2,
+            Expr::Logic(LogicOp::Or, ..) => 3,
+            Expr::Logic(LogicOp::And, ..) => 4,
+            Expr::Unary(UnaryOp::Not, _) => 5,
+            Expr::Cmp(..) => 6,
+            Expr::Bin(BinOp::Add | BinOp::Sub, ..) => 10,
+            Expr::Bin(BinOp::Mul | BinOp::Div | BinOp::Mod, ..) => 11,
+            Expr::Unary(UnaryOp::Neg | UnaryOp::Pos, _) => 12,
+            Expr::Num(x) if x.is_sign_negative() && x.is_finite() => 12,
+            Expr::Bin(BinOp::Pow, ..) => 13,
+            Expr::Attr(..) | Expr::Call(..) | Expr::Index(..) => 15,
+            _ => 16,
+        }
+    }
+}
+
+/// Returns the quote character to use for an f-string with the given parts,
+/// or `None` if it cannot be expressed as an f-string because its expressions use both kinds of quotes.
+pub(crate) fn fstr_quote(parts: &[FStrPart]) -> Option<char> {
+    let mut body = String::new();
+    write_fstr_body(&mut body, parts).unwrap();
+    match (body.contains('\''), body.contains('"')) {
+        (false, _) => Some('\''),
+        (true, false) => Some('"'),
+        (true, true) => None,
+    }
+}
+fn write_fstr_body(f: &mut dyn Write, parts: &[FStrPart]) -> fmt::Result {
+    for part in parts {
+        match part {
+            FStrPart::Lit(x) => for c in escape(x).chars() {
+                f.write_char(c)?;
+                if c == '{' || c == '}' { f.write_char(c)? }
+            }
+            FStrPart::Expr(x) => {
+                f.write_char('{')?;
+                write_expr(f, x, 2)?;
+                f.write_char('}')?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_num(f: &mut dyn Write, value: f64) -> fmt::Result {
+    if value.is_nan() { f.write_str("float('nan')") }
+    else if value.is_infinite() { f.write_str(if value > 0.0 { "float('inf')" } else { "-float('inf')" }) }
+    else { write!(f, "{value}") }
+}
+fn write_list(f: &mut dyn Write, items: &[Expr]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 { f.write_str(", ")? }
+        write_expr(f, item, 0)?;
+    }
+    Ok(())
+}
+fn write_args(f: &mut dyn Write, args: &[Arg]) -> fmt::Result {
+    if let [Arg::Pos(gen @ Expr::Comp { kind: CompKind::Gen, .. })] = args {
+        return write_comp_body(f, gen); // a generator expression which is the only argument does not need its own parens
+    }
+    for (i, arg) in args.iter().enumerate() {
+        if i != 0 { f.write_str(", ")? }
+        match arg {
+            Arg::Pos(x) => write_expr(f, x, 0)?,
+            Arg::Kw(name, x) => {
+                write!(f, "{name} = ")?;
+                write_expr(f, x, 0)?;
+            }
+            Arg::Star(x) => {
+                f.write_char('*')?;
+                write_expr(f, x, 15)?;
+            }
+            Arg::DoubleStar(x) => {
+                f.write_str("**")?;
+                write_expr(f, x, 15)?;
+            }
+        }
+    }
+    Ok(())
+}
+fn write_comp_body(f: &mut dyn Write, comp: &Expr) -> fmt::Result {
+    let (item, clauses, condition) = match comp {
+        Expr::Comp { item, clauses, condition, .. } => (item, clauses, condition),
+        _ => unreachable!(),
+    };
+    write_expr(f, item, 1)?;
+    for (target, iter) in clauses {
+        write!(f, " for {target} in ")?;
+        write_expr(f, iter, 3)?;
+    }
+    if let Some(condition) = condition {
+        f.write_str(" if ")?;
+        write_expr(f, condition, 3)?;
+    }
+    Ok(())
+}
+fn write_expr(f: &mut dyn Write, expr: &Expr, min_prec: u8) -> fmt::Result {
+    let parens = expr.prec() < min_prec;
+    if parens { f.write_char('(')? }
+    match expr {
+        Expr::Name(x) => f.write_str(x)?,
+        Expr::Num(x) => write_num(f, *x)?,
+        Expr::Str(x) => write!(f, "'{}'", escape(x))?,
+        Expr::FStr(parts) => match parts.iter().all(|x| matches!(x, FStrPart::Lit(_))) {
+            true => {
+                let mut value = CompactString::default();
+                for part in parts {
+                    if let FStrPart::Lit(x) = part { value.push_str(x) }
+                }
+                write!(f, "'{}'", escape(&value))?;
+            }
+            false => {
+                let quote = fstr_quote(parts).unwrap_or('\'');
+                write!(f, "f{quote}")?;
+                write_fstr_body(f, parts)?;
+                f.write_char(quote)?;
+            }
+        }
+        Expr::Bool(x) => f.write_str(if *x { "True" } else { "False" })?,
+        Expr::None => f.write_str("None")?,
+        Expr::List(items) => {
+            f.write_char('[')?;
+            write_list(f, items)?;
+            f.write_char(']')?;
+        }
+        Expr::Tuple(items) => {
+            f.write_char('(')?;
+            write_list(f, items)?;
+            if items.len() == 1 { f.write_char(',')? }
+            f.write_char(')')?;
+        }
+        Expr::Dict(items) => match items.is_empty() {
+            true => f.write_str("{}")?,
+            false => {
+                f.write_str("{ ")?;
+                for (i, (key, value)) in items.iter().enumerate() {
+                    if i != 0 { f.write_str(", ")? }
+                    write_expr(f, key, 2)?;
+                    f.write_str(": ")?;
+                    write_expr(f, value, 2)?;
+                }
+                f.write_str(" }")?;
+            }
+        }
+        Expr::Starred(x) => {
+            f.write_char('*')?;
+            write_expr(f, x, 15)?;
+        }
+        Expr::Attr(base, attr) => {
+            match base.as_ref() {
+                Expr::Num(_) => { // `1.x` would lex as a float
+                    f.write_char('(')?;
+                    write_expr(f, base, 0)?;
+                    f.write_char(')')?;
+                }
+                _ => write_expr(f, base, 15)?,
+            }
+            write!(f, ".{attr}")?;
+        }
+        Expr::Call(func, args) => {
+            write_expr(f, func, 15)?;
+            f.write_char('(')?;
+            write_args(f, args)?;
+            f.write_char(')')?;
+        }
+        Expr::Index(base, index) => {
+            write_expr(f, base, 15)?;
+            f.write_char('[')?;
+            write_expr(f, index, 0)?;
+            f.write_char(']')?;
+        }
+        Expr::Slice(start, stop, step) => {
+            if let Some(x) = start { write_expr(f, x, 2)? }
+            f.write_char(':')?;
+            if let Some(x) = stop { write_expr(f, x, 2)? }
+            if let Some(x) = step {
+                f.write_char(':')?;
+                write_expr(f, x, 2)?;
+            }
+        }
+        Expr::Unary(op, value) => {
+            f.write_str(op.as_str())?;
+            write_expr(f, value, expr.prec())?;
+        }
+        Expr::Bin(op, left, right) => {
+            let prec = expr.prec();
+            let (left_prec, right_prec) = match op {
+                BinOp::Pow => (prec + 1, 12), // right associative, and binds less tightly than a unary operator on its right
+                _ => (prec, prec + 1),
+            };
+            write_expr(f, left, left_prec)?;
+            write!(f, " {} ", op.as_str())?;
+            write_expr(f, right, right_prec)?;
+        }
+        Expr::Cmp(op, left, right) => { // comparisons chain in python, so nested comparisons always need parens
+            write_expr(f, left, expr.prec() + 1)?;
+            write!(f, " {} ", op.as_str())?;
+            write_expr(f, right, expr.prec() + 1)?;
+        }
+        Expr::Logic(op, left, right) => {
+            write_expr(f, left, expr.prec())?;
+            write!(f, " {} ", op.as_str())?;
+            write_expr(f, right, expr.prec() + 1)?;
+        }
+        Expr::IfElse { then, condition, otherwise } => {
+            write_expr(f, then, 3)?;
+            f.write_str(" if ")?;
+            write_expr(f, condition, 3)?;
+            f.write_str(" else ")?;
+            write_expr(f, otherwise, 2)?;
+        }
+        Expr::Lambda(params, body) => {
+            f.write_str("lambda")?;
+            for (i, param) in params.iter().enumerate() {
+                f.write_str(if i == 0 { " " } else { ", " })?;
+                f.write_str(param)?;
+            }
+            f.write_str(": ")?;
+            write_expr(f, body, 1)?;
+        }
+        Expr::Comp { kind, .. } => {
+            let (open, close) = match kind {
+                CompKind::List => ('[', ']'),
+                CompKind::Gen => ('(', ')'),
+            };
+            f.write_char(open)?;
+            write_comp_body(f, expr)?;
+            f.write_char(close)?;
+        }
+    }
+    if parens { f.write_char(')')? }
+    Ok(())
+}
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+/// A function parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Param {
+    pub name: CompactString,
+    pub default: Option<Expr>,
+}
+impl Param {
+    pub(crate) fn new(name: &str) -> Self {
+        Self { name: name.into(), default: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FunctionDef {
+    pub decorators: Vec<Expr>,
+    pub name: CompactString,
+    pub params: Vec<Param>,
+    /// The number of leading parameters which are positional-only (followed by `/`).
+    pub positional_only: usize,
+    /// The name of the `**` parameter which collects any other keyword arguments, if any.
+    pub kwargs: Option<CompactString>,
+    pub body: Vec<Stmt>,
+}
+impl FunctionDef {
+    pub(crate) fn new(decorators: Vec<Expr>, name: CompactString, params: Vec<Param>, body: Vec<Stmt>) -> Self {
+        Self { decorators, name, params, positional_only: 0, kwargs: None, body }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StmtKind {
+    Expr(Expr),
+    Assign(Expr, Expr),
+    AugAssign(Expr, BinOp, Expr),
+    Del(Expr),
+    Raise(Expr),
+    Return(Expr),
+    Import(Vec<CompactString>),
+    Pass,
+    /// An empty line, used to visually separate groups of statements.
+    Blank,
+    /// A docstring, which should only appear as the first statement of a function body.
+    Docstring(CompactString),
+
+    /// An `if` statement. An `else` block holding a single `if` statement is printed as an `elif` chain.
+    If { condition: Expr, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    While { condition: Expr, body: Vec<Stmt> },
+    For { target: CompactString, iter: Expr, body: Vec<Stmt> },
+    With { context: Expr, body: Vec<Stmt> },
+    /// A `try` statement which catches any `Exception` into the given variable.
+    Try { body: Vec<Stmt>, var: CompactString, handler: Vec<Stmt> },
+    FunctionDef(Box<FunctionDef>),
+}
+
+/// The block which a statement was translated from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Source {
+    /// The collaboration id of the block, if it has one.
+    pub block: Option<CompactString>,
+    /// The position of the block in its script, which alternates between the index of a block in its sequence and the index of the slot holding the next sequence.
+    /// This is empty for the hat block itself, or for the definition of a custom block.
+    pub path: Vec<usize>,
+}
+
+/// The lines printed for a statement which has a [`Source`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Span {
+    /// The first line of the statement (zero-based).
+    pub start: usize,
+    /// The line after the last line of the statement, including any nested blocks.
+    pub end: usize,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Stmt {
+    pub kind: StmtKind,
+    /// A comment (possibly spanning several lines) to place above the statement.
+    pub comment: Option<CompactString>,
+    /// The block this statement was translated from, if any.
+    pub source: Option<Source>,
+}
+impl From<StmtKind> for Stmt {
+    fn from(kind: StmtKind) -> Self {
+        Self { kind, comment: None, source: None }
+    }
+}
+impl From<Expr> for Stmt {
+    fn from(expr: Expr) -> Self {
+        StmtKind::Expr(expr).into()
+    }
+}
+impl StmtKind {
+    /// Gets the expressions which appear directly in this statement, not counting those in nested blocks.
+    pub(crate) fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            StmtKind::Expr(x) | StmtKind::Del(x) | StmtKind::Raise(x) | StmtKind::Return(x) => vec![x],
+            StmtKind::Assign(a, b) | StmtKind::AugAssign(a, _, b) => vec![a, b],
+            StmtKind::If { condition, .. } | StmtKind::While { condition, .. } => vec![condition],
+            StmtKind::For { iter, .. } => vec![iter],
+            StmtKind::With { context, .. } => vec![context],
+            StmtKind::FunctionDef(def) => def.decorators.iter().chain(def.params.iter().filter_map(|x| x.default.as_ref())).collect(),
+            StmtKind::Import(_) | StmtKind::Pass | StmtKind::Blank | StmtKind::Docstring(_) | StmtKind::Try { .. } => vec![],
+        }
+    }
+    /// Gets the statement blocks nested directly in this statement.
+    pub(crate) fn bodies(&self) -> Vec<&[Stmt]> {
+        match self {
+            StmtKind::If { then, otherwise, .. } => vec![then, otherwise],
+            StmtKind::While { body, .. } | StmtKind::For { body, .. } | StmtKind::With { body, .. } => vec![body],
+            StmtKind::Try { body, handler, .. } => vec![body, handler],
+            StmtKind::FunctionDef(def) => vec![&def.body],
+            _ => vec![],
+        }
+    }
+}
+
+/// Visits every expression (and sub-expression) in some statements, including those in nested blocks.
+pub(crate) fn walk_stmts(stmts: &[Stmt], f: &mut dyn FnMut(&Expr)) {
+    for stmt in stmts {
+        for expr in stmt.kind.exprs() {
+            expr.walk(f);
+        }
+        for body in stmt.kind.bodies() {
+            walk_stmts(body, f);
+        }
+    }
+}
+
+impl Stmt {
+    pub(crate) fn with_comment(mut self, comment: Option<&str>) -> Self {
+        self.comment = comment.map(Into::into);
+        self
+    }
+    pub(crate) fn with_source(mut self, source: Source) -> Self {
+        self.source = Some(source);
+        self
+    }
+}
+
+struct Printer {
+    lines: Vec<String>,
+    spans: Vec<Span>,
+    width: usize,
+}
+impl Printer {
+    /// Prints a statement made of `head`, an expression, and `tail`, wrapping it if it is too long.
+    /// If `optional_parens` is true, the expression may be wrapped in parentheses so it can be split.
+    fn wrapped(&mut self, depth: usize, head: &str, expr: &Expr, tail: &str, optional_parens: bool) {
+        let mut layout = layout::Layout::new(self.width);
+        layout.stmt(depth, head, expr, tail, optional_parens);
+        self.lines.extend(layout.lines);
+    }
+    fn line(&mut self, depth: usize, content: fmt::Arguments) {
+        let mut line = "    ".repeat(depth);
+        line.write_fmt(content).unwrap();
+        self.lines.push(line);
+    }
+    /// Prints a comment as a group of `#` lines, keeping any blank lines within it.
+    fn comment(&mut self, depth: usize, comment: &str) {
+        for line in comment.trim_end().lines() {
+            match line.trim_end() {
+                "" => self.line(depth, format_args!("#")),
+                line => self.line(depth, format_args!("# {line}")),
+            }
+        }
+    }
+    fn docstring(&mut self, depth: usize, doc: &str) {
+        let mut doc = doc.trim().replace('\\', "\\\\").replace("\"\"\"", "\\\"\"\"");
+        if doc.ends_with('"') { // would merge with the closing quotes
+            doc.insert(doc.len() - 1, '\\');
+        }
+        let mut lines = doc.lines();
+        let first = lines.next().unwrap_or_default();
+        match lines.next() {
+            None => self.line(depth, format_args!("\"\"\"{first}\"\"\"")),
+            Some(second) => {
+                self.line(depth, format_args!("\"\"\"{first}"));
+                for line in iter::once(second).chain(lines) {
+                    match line.trim_end() {
+                        "" => self.lines.push(String::new()),
+                        line => self.line(depth, format_args!("{line}")),
+                    }
+                }
+                self.line(depth, format_args!("\"\"\""));
+            }
+        }
+    }
+    fn block(&mut self, depth: usize, stmts: &[Stmt]) {
+        match stmts.is_empty() {
+            true => self.line(depth, format_args!("pass")),
+            false => for stmt in stmts { self.stmt(depth, stmt, "") },
+        }
+    }
+    fn stmt(&mut self, depth: usize, stmt: &Stmt, prefix: &str) {
+        if let Some(comment) = &stmt.comment {
+            self.comment(depth, comment);
+        }
+        let start = self.lines.len();
+        self.stmt_kind(depth, stmt, prefix);
+        if let Some(source) = &stmt.source {
+            self.spans.push(Span { start, end: self.lines.len(), source: source.clone() });
+        }
+    }
+    fn stmt_kind(&mut self, depth: usize, stmt: &Stmt, prefix: &str) {
+        match &stmt.kind {
+            StmtKind::Expr(x) => self.wrapped(depth, "", x, "", false),
+            StmtKind::Assign(target, value) => self.wrapped(depth, &format!("{target} = "), value, "", true),
+            StmtKind::AugAssign(target, op, value) => self.wrapped(depth, &format!("{target} {}= ", op.as_str()), value, "", true),
+            StmtKind::Del(x) => self.wrapped(depth, "del ", x, "", true),
+            StmtKind::Raise(x) => self.wrapped(depth, "raise ", x, "", true),
+            StmtKind::Return(x) => self.wrapped(depth, "return ", x, "", true),
+            StmtKind::Import(names) => self.line(depth, format_args!("import {}", names.join(", "))),
+            StmtKind::Pass => self.line(depth, format_args!("pass")),
+            StmtKind::Blank => self.lines.push(String::new()),
+            StmtKind::Docstring(doc) => self.docstring(depth, doc),
+            StmtKind::If { condition, then, otherwise } => {
+                self.wrapped(depth, &format!("{prefix}if "), condition, ":", true);
+                self.block(depth + 1, then);
+                match otherwise.as_slice() {
+                    [] => (),
+                    [x @ Stmt { kind: StmtKind::If { .. }, .. }] => self.stmt(depth, x, "el"),
+                    _ => {
+                        self.line(depth, format_args!("else:"));
+                        self.block(depth + 1, otherwise);
+                    }
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.wrapped(depth, "while ", condition, ":", true);
+                self.block(depth + 1, body);
+            }
+            StmtKind::For { target, iter, body } => {
+                self.wrapped(depth, &format!("for {target} in "), iter, ":", true);
+                self.block(depth + 1, body);
+            }
+            StmtKind::With { context, body } => {
+                self.wrapped(depth, "with ", context, ":", false);
+                self.block(depth + 1, body);
+            }
+            StmtKind::Try { body, var, handler } => {
+                self.line(depth, format_args!("try:"));
+                self.block(depth + 1, body);
+                self.line(depth, format_args!("except Exception as {var}:"));
+                self.block(depth + 1, handler);
+            }
+            StmtKind::FunctionDef(def) => {
+                for decorator in def.decorators.iter() {
+                    self.wrapped(depth, "@", decorator, "", false);
+                }
+                let mut params: Vec<String> = def.params.iter().map(|param| match &param.default {
+                    Some(default) => format!("{} = {default}", param.name),
+                    None => param.name.to_string(),
+                }).collect();
+                if def.positional_only != 0 { params.insert(def.positional_only, "/".into()) }
+                if let Some(kwargs) = &def.kwargs { params.push(format!("**{kwargs}")) }
+                self.line(depth, format_args!("def {}({}):", def.name, params.join(", ")));
+                self.block(depth + 1, &def.body);
+            }
+        }
+    }
+}
+
+/// Prints a sequence of statements as python source code (without a trailing new line),
+/// wrapping lines which are longer than the given width.
+///
+/// Also returns the lines printed for each statement which has a [`Source`].
+/// Spans of nested statements are inside the spans of their parents, and come before them.
+pub(crate) fn print_stmts(stmts: &[Stmt], width: usize) -> (CompactString, Vec<Span>) {
+    let mut printer = Printer { lines: vec![], spans: vec![], width };
+    for stmt in stmts {
+        printer.stmt(0, stmt, "");
+    }
+    (printer.lines.join("\n").into(), printer.spans)
+}
+
+/// Prints a comment which is not attached to any statement.
+pub(crate) fn print_comment(comment: &str) -> CompactString {
+    let mut printer = Printer { lines: vec![], spans: vec![], width: 0 };
+    printer.comment(0, comment);
+    printer.lines.join("\n").into()
+}
+
+#[test]
+fn test_precedence() {
+    let (a, b, c) = (Expr::name("a"), Expr::name("b"), Expr::name("c"));
+    assert_eq!(Expr::bin(BinOp::Add, Expr::bin(BinOp::Add, a.clone(), b.clone()), c.clone()).to_string(), "a + b + c");
+    assert_eq!(Expr::bin(BinOp::Sub, a.clone(), Expr::bin(BinOp::Sub, b.clone(), c.clone())).to_string(), "a - (b - c)");
+    assert_eq!(Expr::bin(BinOp::Mul, Expr::bin(BinOp::Add, a.clone(), b.clone()), c.clone()).to_string(), "(a + b) * c");
+    assert_eq!(Expr::bin(BinOp::Pow, Expr::bin(BinOp::Pow, a.clone(), b.clone()), c.clone()).to_string(), "(a ** b) ** c");
+    assert_eq!(Expr::bin(BinOp::Pow, a.clone(), Expr::bin(BinOp::Pow, b.clone(), c.clone())).to_string(), "a ** b ** c");
+    assert_eq!(Expr::bin(BinOp::Pow, Expr::unary(UnaryOp::Neg, a.clone()), b.clone()).to_string(), "(-a) ** b");
+    assert_eq!(Expr::bin(BinOp::Pow, Expr::Num(-2.0), b.clone()).to_string(), "(-2) ** b");
+    assert_eq!(Expr::cmp(CmpOp::Less, Expr::cmp(CmpOp::Less, a.clone(), b.clone()), c.clone()).to_string(), "(a < b) < c");
+    assert_eq!(Expr::unary(UnaryOp::Not, Expr::cmp(CmpOp::Eq, a.clone(), b.clone())).to_string(), "not a == b");
+    assert_eq!(Expr::unary(UnaryOp::Not, Expr::logic(LogicOp::Or, a.clone(), b.clone())).to_string(), "not (a or b)");
+    assert_eq!(Expr::logic(LogicOp::And, Expr::logic(LogicOp::Or, a.clone(), b.clone()), c.clone()).to_string(), "(a or b) and c");
+    assert_eq!(Expr::Lambda(vec![], Box::new(a.clone())).call(vec![]).to_string(), "(lambda: a)()");
+    assert_eq!(Expr::bin(BinOp::Add, a.clone(), b.clone()).attr("x").to_string(), "(a + b).x");
+    assert_eq!(Expr::Num(1.0).attr("x").to_string(), "(1).x");
+    assert_eq!(Expr::IfElse { then: Box::new(Expr::IfElse { then: Box::new(a.clone()), condition: Box::new(b.clone()), otherwise: Box::new(c.clone()) }), condition: Box::new(b.clone()), otherwise: Box::new(c.clone()) }.to_string(), "(a if b else c) if b else c");
+    assert_eq!(Expr::Num(f64::INFINITY).to_string(), "float('inf')");
+    assert_eq!(Expr::Tuple(vec![a.clone()]).to_string(), "(a,)");
+}
+#[test]
+fn test_print_stmts() {
+    let body = vec![
+        Stmt::from(StmtKind::If {
+            condition: Expr::name("a"),
+            then: vec![Expr::name("f").call(vec![]).into()],
+            otherwise: vec![StmtKind::If { condition: Expr::name("b"), then: vec![], otherwise: vec![StmtKind::Pass.into()] }.into()],
+        }).with_comment(Some("hello\nworld")),
+    ];
+    let def = Stmt::from(StmtKind::FunctionDef(Box::new(FunctionDef::new(vec![Expr::name("onstart").call(vec![])], "foo".into(), vec![Param::new("self")], body)))).with_comment(Some("hat"));
+    assert_eq!(print_stmts(&[def], 88).0, "# hat\n@onstart()\ndef foo(self):\n    # hello\n    # world\n    if a:\n        f()\n    elif b:\n        pass\n    else:\n        pass");
+    let mut def = FunctionDef::new(vec![], "bar".into(), vec![Param::new("self"), Param { name: "a".into(), default: Some(Expr::None) }], vec![StmtKind::Pass.into()]);
+    def.positional_only = 2;
+    def.kwargs = Some("rest".into());
+    assert_eq!(print_stmts(&[StmtKind::FunctionDef(Box::new(def)).into()], 88).0, "def bar(self, a = None, /, **rest):\n    pass");
+
+    let doc = |x: &str| print_stmts(&[StmtKind::Docstring(x.into()).into()], 88).0;
+    assert_eq!(doc("adds two numbers"), r#""""adds two numbers""""#);
+    assert_eq!(doc("say \"hi\""), r#""""say "hi\"""""#);
+    assert_eq!(doc("adds two numbers\n\nsee also: sub"), "\"\"\"adds two numbers\n\nsee also: sub\n\"\"\"");
+    assert_eq!(print_comment("first\n\nsecond\n"), "# first\n#\n# second");
+}
@@ -0,0 +1,350 @@
+//! Line wrapping for the printer, following the rules of the Black formatter.
+//!
+//! Anything that fits in the line width stays on one line.
+//! Otherwise a line is split at its last bracket (Black's "right hand split"), moving the bracket contents to their own indented lines,
+//! and contents which are still too long are split at their commas or lowest-priority operators (Black's "delimiter split").
+//! The values of assignments, returns, and conditions may also be wrapped in optional parentheses so they can be split at their operators.
+//! As with Black, strings are never split, so a line can still end up too long if there is nowhere to split it.
+
+use super::*;
+
+/// A piece of code to lay out: a raw text prefix followed by an expression,
+/// which is parenthesized if it binds less tightly than the given precedence.
+#[derive(Clone)]
+struct Item<'a> {
+    prefix: String,
+    expr: &'a Expr,
+    min_prec: u8,
+}
+impl<'a> Item<'a> {
+    fn new(prefix: impl Into<String>, expr: &'a Expr, min_prec: u8) -> Self {
+        Self { prefix: prefix.into(), expr, min_prec }
+    }
+    fn flat(&self) -> String {
+        let mut res = self.prefix.clone();
+        write_expr(&mut res, self.expr, self.min_prec).unwrap();
+        res
+    }
+    fn parenthesized(&self) -> bool {
+        self.expr.prec() < self.min_prec
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Comma {
+    /// No trailing comma, as in a subscript.
+    Never,
+    /// A trailing comma is added when the items are split one per line.
+    Exploded,
+    /// A trailing comma is always present, as in a one-element tuple.
+    Always,
+}
+/// The contents of a pair of brackets.
+enum Body<'a> {
+    /// Comma-separated items, such as call arguments.
+    Items(Vec<Item<'a>>, Comma),
+    /// Space-separated pieces, such as the clauses of a comprehension or the operands of a split expression.
+    Pieces(Vec<Item<'a>>),
+}
+impl Body<'_> {
+    fn flat(&self) -> String {
+        match self {
+            Body::Items(items, comma) => {
+                let mut res = items.iter().map(Item::flat).collect::<Vec<_>>().join(", ");
+                if *comma == Comma::Always { res.push(',') }
+                res
+            }
+            Body::Pieces(pieces) => pieces.iter().map(Item::flat).collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+/// A way to split an expression at one of its brackets.
+struct Bracket<'a> {
+    /// Everything up to and including the opening bracket.
+    open: String,
+    body: Body<'a>,
+    /// The closing bracket and everything after it.
+    close: String,
+    subscript: bool,
+}
+
+fn flat(expr: &Expr, min_prec: u8) -> String {
+    let mut res = String::new();
+    write_expr(&mut res, expr, min_prec).unwrap();
+    res
+}
+
+/// Gets Black's priority for splitting at the top-level operator of an expression, where higher values split first.
+fn priority(expr: &Expr) -> Option<u8> {
+    Some(match expr {
+        Expr::IfElse { .. } => 16,
+        Expr::Logic(..) => 14,
+        Expr::Cmp(..) => 10,
+        Expr::Bin(BinOp::Add | BinOp::Sub, ..) => 5,
+        Expr::Bin(BinOp::Mul | BinOp::Div | BinOp::Mod, ..) => 4,
+        Expr::Bin(BinOp::Pow, ..) => 1,
+        _ => return None,
+    })
+}
+/// Splits an expression into its operands at all the top-level operators with the given priority, mirroring how [`write_expr`] prints it.
+fn chain<'a>(expr: &'a Expr, min_prec: u8, prio: u8, prefix: String, out: &mut Vec<Item<'a>>) {
+    if expr.prec() < min_prec || priority(expr) != Some(prio) {
+        out.push(Item::new(prefix, expr, min_prec));
+        return;
+    }
+    let prec = expr.prec();
+    match expr {
+        Expr::Logic(op, left, right) => {
+            chain(left, prec, prio, prefix, out);
+            chain(right, prec + 1, prio, format!("{} ", op.as_str()), out);
+        }
+        Expr::Bin(op, left, right) => {
+            let (left_prec, right_prec) = match op {
+                BinOp::Pow => (prec + 1, 12),
+                _ => (prec, prec + 1),
+            };
+            chain(left, left_prec, prio, prefix, out);
+            chain(right, right_prec, prio, format!("{} ", op.as_str()), out);
+        }
+        Expr::Cmp(op, left, right) => {
+            out.push(Item::new(prefix, left, prec + 1));
+            out.push(Item::new(format!("{} ", op.as_str()), right, prec + 1));
+        }
+        Expr::IfElse { then, condition, otherwise } => {
+            out.push(Item::new(prefix, then, 3));
+            out.push(Item::new("if ", condition, 3));
+            chain(otherwise, 2, prio, "else ".into(), out);
+        }
+        _ => unreachable!(),
+    }
+}
+/// Splits an item at its lowest-priority top-level operators, if it has any.
+fn pieces<'a>(item: &Item<'a>) -> Option<Vec<Item<'a>>> {
+    if item.parenthesized() { return None }
+    match item.expr { // operators in the operand of a prefix operator or the body of a lambda are still at the top level
+        Expr::Unary(op, x) => return pieces(&Item::new(format!("{}{}", item.prefix, op.as_str()), x, item.expr.prec())),
+        Expr::Lambda(params, body) => {
+            let head = match params.is_empty() {
+                true => "lambda: ".to_owned(),
+                false => format!("lambda {}: ", params.join(", ")),
+            };
+            return pieces(&Item::new(format!("{}{head}", item.prefix), body, 1));
+        }
+        _ => (),
+    }
+    let mut res = vec![];
+    chain(item.expr, item.min_prec, priority(item.expr)?, item.prefix.clone(), &mut res);
+    Some(res)
+}
+
+fn comp_pieces(comp: &Expr) -> Vec<Item<'_>> {
+    let Expr::Comp { item, clauses, condition, .. } = comp else { unreachable!() };
+    let mut res = vec![Item::new("", item, 1)];
+    for (target, iter) in clauses {
+        res.push(Item::new(format!("for {target} in "), iter, 3));
+    }
+    if let Some(condition) = condition {
+        res.push(Item::new("if ", condition, 3));
+    }
+    res
+}
+fn args_body(args: &[Arg]) -> Body<'_> {
+    if let [Arg::Pos(gen @ Expr::Comp { kind: CompKind::Gen, .. })] = args {
+        return Body::Pieces(comp_pieces(gen));
+    }
+    Body::Items(args.iter().map(|arg| match arg {
+        Arg::Pos(x) => Item::new("", x, 0),
+        Arg::Kw(name, x) => Item::new(format!("{name} = "), x, 0),
+        Arg::Star(x) => Item::new("*", x, 15),
+        Arg::DoubleStar(x) => Item::new("**", x, 15),
+    }).collect(), Comma::Exploded)
+}
+
+/// Gets the ways to split an item at the brackets which close at its end (possibly followed by more trailers),
+/// ordered from the last bracket to the first.
+fn brackets<'a>(prefix: &str, expr: &'a Expr, min_prec: u8, suffix: &str) -> Vec<Bracket<'a>> {
+    if expr.prec() < min_prec {
+        let body = Body::Pieces(pieces(&Item::new("", expr, 0)).unwrap_or_else(|| vec![Item::new("", expr, 0)]));
+        return vec![Bracket { open: format!("{prefix}("), body, close: format!("){suffix}"), subscript: false }];
+    }
+    let bracket = |open: &str, body: Body<'a>, close: &str| Bracket { open: format!("{prefix}{open}"), body, close: format!("{close}{suffix}"), subscript: false };
+    match expr {
+        Expr::Call(func, args) => {
+            let mut res = vec![];
+            if !args.is_empty() {
+                res.push(bracket(&format!("{}(", flat(func, 15)), args_body(args), ")"));
+            }
+            let mut args_text = String::new();
+            write_args(&mut args_text, args).unwrap();
+            res.extend(brackets(prefix, func, 15, &format!("({args_text}){suffix}")));
+            res
+        }
+        Expr::Index(base, index) => {
+            let mut res = vec![Bracket { subscript: true, ..bracket(&format!("{}[", flat(base, 15)), Body::Items(vec![Item::new("", index, 0)], Comma::Never), "]") }];
+            res.extend(brackets(prefix, base, 15, &format!("[{}]{suffix}", flat(index, 0))));
+            res
+        }
+        Expr::Attr(base, attr) if !matches!(**base, Expr::Num(_)) => brackets(prefix, base, 15, &format!(".{attr}{suffix}")),
+        Expr::List(items) if !items.is_empty() => vec![bracket("[", Body::Items(items.iter().map(|x| Item::new("", x, 0)).collect(), Comma::Exploded), "]")],
+        Expr::Tuple(items) if !items.is_empty() => {
+            let comma = if items.len() == 1 { Comma::Always } else { Comma::Exploded };
+            vec![bracket("(", Body::Items(items.iter().map(|x| Item::new("", x, 0)).collect(), comma), ")")]
+        }
+        Expr::Dict(items) if !items.is_empty() => {
+            let items = items.iter().map(|(k, v)| Item::new(format!("{}: ", flat(k, 2)), v, 2)).collect();
+            vec![bracket("{", Body::Items(items, Comma::Exploded), "}")]
+        }
+        Expr::Comp { kind, .. } => match kind {
+            CompKind::List => vec![bracket("[", Body::Pieces(comp_pieces(expr)), "]")],
+            CompKind::Gen => vec![bracket("(", Body::Pieces(comp_pieces(expr)), ")")],
+        }
+        Expr::Starred(x) => brackets(&format!("{prefix}*"), x, 15, suffix),
+        Expr::Unary(op, x) => brackets(&format!("{prefix}{}", op.as_str()), x, expr.prec(), suffix),
+        Expr::Bin(op, left, right) => {
+            let prec = expr.prec();
+            let (left_prec, right_prec) = match op {
+                BinOp::Pow => (prec + 1, 12),
+                _ => (prec, prec + 1),
+            };
+            brackets(&format!("{prefix}{} {} ", flat(left, left_prec), op.as_str()), right, right_prec, suffix)
+        }
+        Expr::Cmp(op, left, right) => brackets(&format!("{prefix}{} {} ", flat(left, expr.prec() + 1), op.as_str()), right, expr.prec() + 1, suffix),
+        Expr::Logic(op, left, right) => brackets(&format!("{prefix}{} {} ", flat(left, expr.prec()), op.as_str()), right, expr.prec() + 1, suffix),
+        Expr::IfElse { then, condition, otherwise } => brackets(&format!("{prefix}{} if {} else ", flat(then, 3), flat(condition, 3)), otherwise, 2, suffix),
+        Expr::Lambda(params, body) => {
+            let head = match params.is_empty() {
+                true => "lambda: ".to_owned(),
+                false => format!("lambda {}: ", params.join(", ")),
+            };
+            brackets(&format!("{prefix}{head}"), body, 1, suffix)
+        }
+        _ => vec![],
+    }
+}
+
+pub(super) struct Layout {
+    width: usize,
+    pub(super) lines: Vec<String>,
+}
+impl Layout {
+    pub(super) fn new(width: usize) -> Self {
+        Self { width, lines: vec![] }
+    }
+    fn fits(&self, depth: usize, line: &str) -> bool {
+        (depth * 4).saturating_add(line.chars().count()) <= self.width
+    }
+    fn push(&mut self, depth: usize, line: &str) {
+        self.lines.push(format!("{}{line}", "    ".repeat(depth)));
+    }
+
+    /// Lays out a statement made of `head`, an expression, and `tail`.
+    /// If `optional_parens` is true, the expression may be wrapped in parentheses so it can be split.
+    pub(super) fn stmt(&mut self, depth: usize, head: &str, expr: &Expr, tail: &str, optional_parens: bool) {
+        let item = Item::new("", expr, 0);
+        let line = format!("{head}{}{tail}", item.flat());
+        if self.fits(depth, &line) { return self.push(depth, &line) }
+
+        if optional_parens {
+            let brackets = brackets("", expr, 0, "");
+            let pieces = pieces(&item);
+            let use_parens = match (&pieces, brackets.first()) {
+                (Some(pieces), _) if pieces.len() > 2 => true, // with several operators of a kind, the parentheses read better
+                (Some(_), Some(last)) => { // a single operator is fine if we can split at the last bracket instead
+                    let opening = &last.open[..last.open.len() - 1];
+                    let can_omit = last.close.len() == 1 && !last.subscript && (self.fits(depth, &format!("{head}{}", last.open)) || opening.contains(['(', '[', '{']));
+                    !can_omit
+                }
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => self.fits(depth + 1, &item.flat()), // an atom only gets parentheses if it then fits
+            };
+            if use_parens {
+                self.push(depth, &format!("{head}("));
+                self.body(depth + 1, Body::Pieces(pieces.unwrap_or_else(|| vec![item])));
+                self.push(depth, &format!("){tail}"));
+                return;
+            }
+        }
+
+        if !self.right_hand_split(depth, head, &item, tail) {
+            self.push(depth, &line);
+        }
+    }
+
+    /// Splits at the last bracket whose opening line fits (or just the last bracket if none do).
+    /// Returns false if there are no brackets to split at.
+    fn right_hand_split(&mut self, depth: usize, head: &str, item: &Item, tail: &str) -> bool {
+        let mut brackets = brackets(&item.prefix, item.expr, item.min_prec, "");
+        if brackets.is_empty() { return false }
+        let chosen = brackets.iter().position(|b| self.fits(depth, &format!("{head}{}", b.open)) && self.fits(depth, &format!("{}{tail}", b.close))).unwrap_or(0);
+        let bracket = brackets.swap_remove(chosen);
+        self.push(depth, &format!("{head}{}", bracket.open));
+        self.body(depth + 1, bracket.body);
+        self.push(depth, &format!("{}{tail}", bracket.close));
+        true
+    }
+
+    fn body(&mut self, depth: usize, body: Body) {
+        let line = body.flat();
+        if self.fits(depth, &line) { return self.push(depth, &line) }
+        match body {
+            Body::Items(items, comma) => {
+                let count = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    let trailing = i + 1 < count || comma == Comma::Always || (comma == Comma::Exploded && count > 1);
+                    self.inner(depth, &item, if trailing { "," } else { "" });
+                }
+            }
+            Body::Pieces(pieces) => for piece in pieces {
+                self.inner(depth, &piece, "");
+            }
+        }
+    }
+
+    /// Lays out a line inside brackets, which is split at its operators before its brackets
+    /// (unless it ends in a comma, which has a higher priority than any operator, so splitting there would change nothing).
+    fn inner(&mut self, depth: usize, item: &Item, tail: &str) {
+        let line = format!("{}{tail}", item.flat());
+        if self.fits(depth, &line) { return self.push(depth, &line) }
+        if tail != "," {
+            if let Some(pieces) = pieces(item) {
+                let count = pieces.len();
+                for (i, piece) in pieces.iter().enumerate() {
+                    self.inner(depth, piece, if i + 1 == count { tail } else { "" });
+                }
+                return;
+            }
+        }
+        if !self.right_hand_split(depth, "", item, tail) {
+            self.push(depth, &line);
+        }
+    }
+}
+
+#[test]
+fn test_layout() {
+    let layout = |width: usize, head: &str, expr: &Expr, tail: &str, parens: bool| {
+        let mut layout = Layout::new(width);
+        layout.stmt(0, head, expr, tail, parens);
+        layout.lines.join("\n")
+    };
+    let name = |x: &str| Expr::name(x);
+    let call = Expr::name("foo").call_args(vec![Arg::Pos(name("alpha")), Arg::Kw("beta".into(), name("gamma")), Arg::Star(name("delta"))]);
+
+    assert_eq!(layout(88, "x = ", &call, "", true), "x = foo(alpha, beta = gamma, *delta)");
+    assert_eq!(layout(32, "x = ", &call, "", true), "x = foo(\n    alpha, beta = gamma, *delta\n)");
+    assert_eq!(layout(20, "x = ", &call, "", true), "x = foo(\n    alpha,\n    beta = gamma,\n    *delta,\n)");
+    assert_eq!(layout(20, "", &Expr::name("snap.wrap").call(vec![call.clone()]), "", false), "snap.wrap(\n    foo(\n        alpha,\n        beta = gamma,\n        *delta,\n    )\n)");
+
+    let chain = Expr::logic(LogicOp::And, Expr::logic(LogicOp::Or, name("alpha"), name("beta")), Expr::cmp(CmpOp::Eq, name("gamma"), name("delta")));
+    assert_eq!(layout(30, "if ", &chain, ":", true), "if (\n    (alpha or beta)\n    and gamma == delta\n):");
+    let sum = Expr::bin_chain(BinOp::Add, vec![name("alpha"), name("beta"), name("gamma")]);
+    assert_eq!(layout(24, "return ", &sum, "", true), "return (\n    alpha + beta + gamma\n)");
+    assert_eq!(layout(20, "", &sum, "", false), "alpha + beta + gamma");
+    assert_eq!(layout(20, "x = ", &Expr::bin(BinOp::Add, name("alpha"), call.clone()), "", true), "x = alpha + foo(\n    alpha,\n    beta = gamma,\n    *delta,\n)");
+
+    let method = name("alpha").method("beta", vec![name("gamma")]).method("delta", vec![]);
+    assert_eq!(layout(20, "xyz = ", &method, "", true), "xyz = alpha.beta(\n    gamma\n).delta()");
+    assert_eq!(layout(10, "x = ", &Expr::str("a long string"), "", true), "x = 'a long string'");
+    assert_eq!(layout(16, "", &Expr::Tuple(vec![Expr::str("a long string")]), "", false), "(\n    'a long string',\n)");
+}
@@ -3,32 +3,147 @@
 #[cfg(feature = "pyo3")]
 mod python;
 
+mod ir;
+mod opt;
+mod idiom;
+mod comments;
+mod names;
+mod chunks;
+mod report;
+
+pub mod project;
+pub mod merge;
+
 use std::fmt::Write;
 use std::rc::Rc;
 use std::iter;
 use std::sync::LazyLock;
+use std::collections::{BTreeMap, BTreeSet};
 
-use compact_str::{CompactString, ToCompactString, format_compact};
-use base64::engine::Engine as Base64Engine;
+use compact_str::{CompactString, format_compact};
 use regex::Regex;
 
-#[macro_use] extern crate serde_json;
-
 pub use netsblox_ast::Error as ParseError;
 use netsblox_ast::{*, util::*};
+use ir::{Expr as PyExpr, Stmt as PyStmt, StmtKind as PyStmtKind, Arg, BinOp, CmpOp, CompKind, FStrPart, FunctionDef, LogicOp, Param, UnaryOp};
 
 #[cfg(test)]
 mod test;
 
 static PY_IDENT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[_a-zA-Z][_a-zA-Z0-9]*$").unwrap());
 fn is_py_ident(sym: &str) -> bool {
-    PY_IDENT_REGEX.is_match(sym)
+    PY_IDENT_REGEX.is_match(sym) && !names::is_keyword(sym)
 }
 #[test]
-fn test_py_ident() {
+fn test_is_py_ident() {
     assert!(is_py_ident("fooBar_23"));
     assert!(!is_py_ident("34hello"));
     assert!(!is_py_ident("hello world"));
+    assert!(!is_py_ident("from"));
+}
+
+/// Block selectors for the "call RPC" blocks whose service and method names are computed at runtime.
+/// These are not known to the parser, so they reach us as unknown blocks with arguments `(service, rpc, args)`.
+const DYNAMIC_RPC_EXPR: &str = "reportCallRPC";
+const DYNAMIC_RPC_STMT: &str = "doCallRPC";
+
+/// Block selector for the reporter which gets the address of the sender of the network message being handled.
+/// Like the dynamic RPC blocks, this is not known to the parser and reaches us as an unknown block with no arguments.
+const MSG_SENDER_EXPR: &str = "reportMessageSender";
+
+/// Block selectors for the NetsBlox identity and room reporters, along with the `nb` client property they map to.
+/// Like the dynamic RPC blocks, these are not known to the parser and reach us as unknown blocks with no arguments.
+const IDENTITY_REPORTERS: &[(&str, &str)] = &[
+    ("getProjectId", "nb.public_id"),
+    ("reportUsername", "nb.username"),
+    ("getRoleName", "nb.role_name"),
+    ("getRoomName", "nb.room_name"),
+    ("getRoomRoles", "nb.room_roles"),
+];
+
+/// The block library which PyBlox loads for each role unless [`TranslateOptions::block_sources`] says otherwise.
+pub const DEFAULT_BLOCK_SOURCE: &str = "netsblox://assets/default-blocks.json";
+
+/// Modules which the generated code may use without importing them itself, as long as they are in the imports of the role.
+const EDITOR_MODULES: &[&str] = &["time", "math", "random"];
+
+/// Adds the modules (out of [`EDITOR_MODULES`]) which are referred to by some generated statements.
+fn used_modules(stmts: &[PyStmt], used: &mut BTreeSet<&'static str>) {
+    ir::walk_stmts(stmts, &mut |x| if let PyExpr::Name(name) = x {
+        let module = name.split('.').next().unwrap_or_default();
+        if let Some(module) = EDITOR_MODULES.iter().find(|x| **x == module && name.len() > module.len()) { used.insert(module); }
+    });
+}
+#[test]
+fn test_used_modules() {
+    let mut used = BTreeSet::new();
+    used_modules(&[PyStmtKind::Assign(PyExpr::name("x"), PyExpr::name("random.randint").call(vec![PyExpr::Num(1.0), PyExpr::Num(2.0)])).into()], &mut used);
+    assert_eq!(used, ["random"].into());
+    let body = vec![PyExpr::name("time.sleep").call(vec![PyExpr::name("self").attr("math")]).into(), PyExpr::name("maths.pi").into(), PyExpr::name("math").into()];
+    used_modules(&[PyStmtKind::While { condition: PyExpr::Bool(true), body }.into()], &mut used);
+    assert_eq!(used, ["random", "time"].into());
+}
+
+/// Scripts whose hat block has a comment consisting of only this marker have their RPC statements translated as non-blocking futures
+/// when [`TranslateOptions::async_rpcs`] is enabled. The marker is an instruction to the translator, so it is not copied into the generated code.
+pub const ASYNC_SCRIPT_MARKER: &str = "@async";
+
+fn is_async_marker(comment: &str) -> bool {
+    comment.trim() == ASYNC_SCRIPT_MARKER
+}
+
+/// Options which control how a project is translated.
+#[derive(Debug, Clone)]
+pub struct TranslateOptions {
+    /// If true, RPC calls whose results are not needed immediately are translated to non-blocking `nb.call_async` futures.
+    /// This applies to RPCs in launched command rings (including the "launch, then run callback with the result" pattern)
+    /// and to RPC statements in scripts marked with [`ASYNC_SCRIPT_MARKER`].
+    ///
+    /// Like blocking calls, the futures are created through `nothrow`, but an error from the RPC itself is only raised when the result of the future is read.
+    /// Launched rings of any other shape are still not supported, even with this option enabled.
+    pub async_rpcs: bool,
+    /// How much the generated code is simplified.
+    pub opt_level: OptLevel,
+    /// If true, the generated code uses plain python values, builtins, and native lists wherever possible instead of faithfully reproducing Snap's semantics.
+    /// This is meant for teaching python itself, so any remaining `snap` helpers are flagged with a warning comment.
+    pub idiomatic: bool,
+    /// The maximum length of generated lines, which are wrapped following the rules of the Black formatter.
+    /// Defaults to 88, like Black itself.
+    pub line_width: usize,
+    /// If true, names keep any unicode characters which python allows in identifiers (e.g., `número` or `分数`).
+    /// Otherwise, names are restricted to ASCII.
+    pub unicode_idents: bool,
+    /// The python modules which PyBlox imports into every editor of each role.
+    /// If `None` (the default), these are the modules which the generated code actually uses.
+    pub imports: Option<Vec<CompactString>>,
+    /// The URLs of the block libraries which PyBlox loads for each role.
+    /// Defaults to just [`DEFAULT_BLOCK_SOURCE`].
+    pub block_sources: Vec<CompactString>,
+}
+impl Default for TranslateOptions {
+    fn default() -> Self {
+        Self {
+            async_rpcs: false,
+            opt_level: OptLevel::default(),
+            idiomatic: false,
+            line_width: 88,
+            unicode_idents: false,
+            imports: None,
+            block_sources: vec![DEFAULT_BLOCK_SOURCE.into()],
+        }
+    }
+}
+
+/// How aggressively the generated code is simplified after translation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// Emit the code exactly as each block is translated.
+    None,
+    /// Fold constant arithmetic, comparisons, string joins, and list indices.
+    Fold,
+    /// Constant folding, plus the removal of dead code such as `if False` blocks and statements after a `return`.
+    #[default]
+    Full,
 }
 
 #[derive(Debug)]
@@ -47,92 +162,357 @@ pub enum TranslateError {
     RingTypeQuery,
     CommandRing,
     TellAskClosure,
+    ReplyOutsideMessageHandler,
 }
 impl From<Box<Error>> for TranslateError { fn from(e: Box<Error>) -> Self { Self::Parse(e) } }
 
-fn fmt_comment(comment: Option<&str>) -> CompactString {
-    match comment {
-        Some(v) => format_compact!(" # {}", v.replace('\n', " -- ")),
-        None => "".into(),
+/// The static type of a translated expression.
+///
+/// Numbers and text are plain python values, which must be wrapped before they are stored or used with Snap semantics.
+/// The other known types never need to be wrapped: bools can't be extended, and lists, sprites, and closures are always produced in wrapped form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Number, Text, Bool, List, Sprite, Closure,
+    /// A wrapped value of unknown type.
+    Wrapped,
+    /// A plain python value of unknown type.
+    Unknown,
+}
+impl Type {
+    /// Checks if values of this type can be used wherever a wrapped value is expected.
+    fn is_wrapped(self) -> bool {
+        matches!(self, Type::Bool | Type::List | Type::Sprite | Type::Closure | Type::Wrapped)
+    }
+    /// Gets the type of a value of this type after passing it through [`wrap`].
+    fn wrapped(self) -> Type {
+        if self.is_wrapped() { self } else { Type::Wrapped }
+    }
+    /// Gets the most specific type which includes values of both types.
+    fn join(self, other: Type) -> Type {
+        if self == other { self } else if self.is_wrapped() && other.is_wrapped() { Type::Wrapped } else { Type::Unknown }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Type {
-    Unknown, Wrapped,
+fn wrap(val: (PyExpr, Type)) -> PyExpr {
+    match val.1.is_wrapped() {
+        true => val.0,
+        false => snap("wrap", vec![val.0]),
+    }
+}
+fn wrap_number(val: (PyExpr, Type), coerce: bool) -> PyExpr {
+    let val = as_number(val);
+    match (val.1, coerce) {
+        (Type::Number, _) => val.0,
+        (_, true) => PyExpr::unary(UnaryOp::Pos, wrap(val)),
+        (_, false) => wrap(val),
+    }
 }
 
-fn wrap(val: (CompactString, Type)) -> CompactString {
-    match &val.1 {
-        Type::Wrapped => val.0,
-        Type::Unknown => format_compact!("snap.wrap({})", val.0),
+/// Parses a text literal which Snap would treat as a (finite) number.
+fn numeric_literal(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().filter(|x| x.is_finite())
+}
+/// Converts numeric text literals into number literals, leaving other values unchanged.
+fn as_number(val: (PyExpr, Type)) -> (PyExpr, Type) {
+    match &val.0 {
+        PyExpr::Str(x) => match numeric_literal(x) {
+            Some(x) => (PyExpr::Num(x), Type::Number),
+            None => val,
+        }
+        _ => val,
+    }
+}
+/// Translates an arithmetic operator, using plain python arithmetic when both operands are known to be numbers
+/// and the operation can't raise an error where Snap would produce a value (e.g., division by zero).
+fn arith(op: BinOp, left: (PyExpr, Type), right: (PyExpr, Type)) -> (PyExpr, Type) {
+    let (left, right) = (as_number(left), as_number(right));
+    let plain = left.1 == Type::Number && right.1 == Type::Number && match (op, &right.0) {
+        (BinOp::Add | BinOp::Sub | BinOp::Mul, _) => true,
+        (BinOp::Div | BinOp::Mod, PyExpr::Num(x)) => *x != 0.0,
+        (BinOp::Pow, PyExpr::Num(x)) => *x >= 0.0 && x.fract() == 0.0,
+        _ => false,
+    };
+    match plain {
+        true => (PyExpr::bin(op, left.0, right.0), Type::Number),
+        false => (PyExpr::bin(op, wrap(left), wrap(right)), Type::Wrapped),
     }
 }
-fn wrap_number(val: (CompactString, Type), coerce: bool) -> CompactString {
-    val.0.chars().next().filter(|&ch| (ch == '"' || ch == '\'') && val.0.len() > 1 && val.0.ends_with(ch))
-        .and_then(|_| val.0[1..val.0.len()-1].parse::<f64>().ok().map(|f| f.to_string().into())).unwrap_or_else(|| format_compact!("{}{}", coerce.then(|| "+").unwrap_or_default(), wrap(val)))
+/// Translates a comparison operator, using a plain python comparison when both operands are known to be numbers.
+fn compare(op: CmpOp, left: (PyExpr, Type), right: (PyExpr, Type)) -> (PyExpr, Type) {
+    let (left, right) = (as_number(left), as_number(right));
+    match left.1 == Type::Number && right.1 == Type::Number {
+        true => (PyExpr::cmp(op, left.0, right.0), Type::Bool),
+        false => (PyExpr::cmp(op, wrap(left), wrap(right)), Type::Bool),
+    }
+}
+#[test]
+fn test_arith_types() {
+    let text = |x: &str| (PyExpr::str(x), Type::Text);
+    assert_eq!(arith(BinOp::Add, text("1"), text("4")).0.to_string(), "1 + 4");
+    assert_eq!(arith(BinOp::Add, text("1"), text("abc")).0.to_string(), "snap.wrap(1) + snap.wrap('abc')");
+    assert_eq!(arith(BinOp::Div, text("1"), text("0")).0.to_string(), "snap.wrap(1) / snap.wrap(0)");
+    assert_eq!(arith(BinOp::Pow, text("2"), text("-1")).1, Type::Wrapped);
+    assert_eq!(compare(CmpOp::Less, (PyExpr::name("x"), Type::Wrapped), text("3")).0.to_string(), "x < snap.wrap(3)");
+    assert_eq!(Type::List.join(Type::Sprite), Type::Wrapped);
+    assert_eq!(Type::Number.join(Type::Bool), Type::Unknown);
+}
+
+/// Constructs a call to one of the `snap` helper functions.
+fn snap(func: &str, args: Vec<PyExpr>) -> PyExpr {
+    PyExpr::Name(format_compact!("snap.{func}")).call(args)
 }
 
-fn translate_var(var: &VariableRef) -> CompactString {
+fn translate_var(var: &VariableRef) -> PyExpr {
     match &var.location {
-        VarLocation::Local => var.trans_name.clone(),
-        VarLocation::Field => format_compact!("self.{}", var.trans_name),
-        VarLocation::Global => format_compact!("globals.{}", var.trans_name),
+        VarLocation::Local => PyExpr::Name(var.trans_name.clone()),
+        VarLocation::Field => PyExpr::Name(format_compact!("self.{}", var.trans_name)),
+        VarLocation::Global => PyExpr::Name(format_compact!("globals.{}", var.trans_name)),
+    }
+}
+
+/// The parameter of a network message handler which holds the address of the sender.
+/// This is a private name, so it cannot be confused with the translated name of a field.
+const MSG_SENDER_PARAM: &str = "_sender";
+/// The parameter of a network message handler which holds the fields of the message, keyed by their original names.
+const MSG_FIELDS_PARAM: &str = "_fields";
+
+/// Network message types (and their field names) which are used by a role.
+type MsgTypes = BTreeMap<CompactString, Vec<CompactString>>;
+
+/// Checks if a declared message type is the `message` type which NetsBlox adds to every project, which is only declared in the output if a script uses it.
+fn is_default_msg_type(msg_type: &(CompactString, Vec<CompactString>)) -> bool {
+    msg_type.0 == "message" && msg_type.1 == ["msg"]
+}
+
+/// The static types of the variables and custom blocks in a role.
+///
+/// Variables are keyed by their owner (sprite name) and translated name, so identically-named locals in different scripts share a type.
+/// This loses precision, but never soundness, since types are only ever joined.
+#[derive(Debug, Default)]
+struct TypeEnv {
+    vars: BTreeMap<CompactString, Type>,
+    funcs: BTreeMap<CompactString, Type>,
+    /// Variables which are used with "change by", so their initial values are meant to be numbers.
+    counters: BTreeSet<CompactString>,
+}
+impl TypeEnv {
+    fn var_key(owner: &str, var: &VariableRef) -> CompactString {
+        match var.location {
+            VarLocation::Local => format_compact!("{owner}.{}", var.trans_name),
+            VarLocation::Field => format_compact!("{owner}.self.{}", var.trans_name),
+            VarLocation::Global => format_compact!("globals.{}", var.trans_name),
+        }
+    }
+    fn func_key(owner: &str, location: FnLocation, trans_name: &str) -> CompactString {
+        match location {
+            FnLocation::Method => format_compact!("{owner}.{trans_name}"),
+            FnLocation::Global => format_compact!("globals.{trans_name}"),
+        }
+    }
+    /// Gets the type of a variable, which is always wrapped since every assignment is wrapped.
+    fn var(&self, key: &str) -> Type {
+        self.vars.get(key).copied().unwrap_or(Type::Wrapped)
+    }
+    /// Gets the return type of a custom block, which is always wrapped since every return value is wrapped.
+    fn func(&self, key: &str) -> Type {
+        self.funcs.get(key).copied().unwrap_or(Type::Wrapped)
+    }
+    fn bind_var(&mut self, key: CompactString, ty: Type) {
+        let entry = self.vars.entry(key).or_insert(ty);
+        *entry = entry.join(ty);
     }
+    fn bind_func(&mut self, key: CompactString, ty: Type) {
+        let entry = self.funcs.entry(key).or_insert(ty);
+        *entry = entry.join(ty);
+    }
+}
+
+/// Checks if running a sequence of statements always ends in a `return` (or never ends), so that a custom block cannot fall off the end and return `None`.
+fn always_returns(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return { .. } | StmtKind::InfLoop { .. } => true,
+        StmtKind::IfElse { then, otherwise, .. } => always_returns(then) && always_returns(otherwise),
+        _ => false,
+    })
 }
 
-struct ScriptInfo<'a> {
-    stage_name: &'a str,
+/// State which is shared by every script in a role.
+struct RoleContext<'a> {
+    stage_name: CompactString,
+    options: &'a TranslateOptions,
+    msg_types: MsgTypes,
+    /// Types inferred by a previous pass over the role (or empty on the first pass).
+    types: &'a TypeEnv,
+    /// Types found during this pass over the role.
+    found_types: TypeEnv,
+    /// The python names of everything in the role, keyed by the python name of the owning sprite (or empty for globals).
+    names: BTreeMap<CompactString, names::NameMap>,
+    /// The modules (out of [`EDITOR_MODULES`]) which the emitted code refers to.
+    modules: BTreeSet<&'static str>,
 }
-impl<'a> ScriptInfo<'a> {
-    fn new(stage_name: &'a str) -> Self {
-        Self { stage_name }
+impl RoleContext<'_> {
+    /// Optimises and prints a sequence of generated statements.
+    fn emit(&mut self, stmts: Vec<PyStmt>) -> CompactString {
+        self.emit_mapped(stmts).0
+    }
+    /// Equivalent to [`RoleContext::emit`], but also returns the lines printed for each translated block.
+    fn emit_mapped(&mut self, stmts: Vec<PyStmt>) -> (CompactString, Vec<ir::Span>) {
+        let stmts = if self.options.idiomatic { idiom::idiomatize(stmts) } else { stmts };
+        let stmts = opt::optimize(stmts, self.options.opt_level);
+        used_modules(&stmts, &mut self.modules);
+        ir::print_stmts(&stmts, self.options.line_width)
     }
-    fn translate_value(&mut self, value: &Value) -> Result<(CompactString, Type), TranslateError> {
+    /// In the idiomatic style, converts numeric text assigned to a variable which is later used with "change by" into a number,
+    /// since python's `+=` would otherwise fail (or concatenate) where Snap adds.
+    fn counter_init(&self, key: &str, value: (PyExpr, Type)) -> (PyExpr, Type) {
+        match self.options.idiomatic && self.types.counters.contains(key) {
+            true => as_number(value),
+            false => value,
+        }
+    }
+}
+
+struct ScriptInfo<'a, 'b> {
+    role: &'a mut RoleContext<'b>,
+    /// The name of the sprite which owns the script, or empty for global custom blocks.
+    owner: &'a str,
+    /// The key of the custom block being translated, if any.
+    func: Option<CompactString>,
+    /// If true, the script is the body of a network message handler, so the sender of the message is in scope (see [`MSG_SENDER_PARAM`]).
+    in_msg_handler: bool,
+    /// If true, RPC statements in the script are translated as non-blocking futures.
+    async_rpcs: bool,
+    /// The position of the statement being translated (see [`ir::Source::path`]).
+    path: Vec<usize>,
+}
+impl<'a, 'b> ScriptInfo<'a, 'b> {
+    fn new(role: &'a mut RoleContext<'b>, owner: &'a str) -> Self {
+        Self { role, owner, func: None, in_msg_handler: false, async_rpcs: false, path: vec![] }
+    }
+    fn stage(&self) -> PyExpr {
+        PyExpr::Name(self.role.stage_name.clone())
+    }
+    fn bind_local(&mut self, name: &str, trans_name: &str, ty: Type) {
+        self.role.found_types.bind_var(format_compact!("{}.{trans_name}", self.owner), ty);
+        self.role.names.entry(self.owner.into()).or_default().locals.insert(name.into(), trans_name.into());
+    }
+    /// Translates a one-based Snap index into a zero-based python index.
+    fn translate_index(&mut self, index: &Expr) -> Result<PyExpr, TranslateError> {
+        let index = arith(BinOp::Sub, self.translate_expr(index)?, (PyExpr::Num(1.0), Type::Number));
+        Ok(index.0)
+    }
+    fn translate_logic(&mut self, op: LogicOp, left: &Expr, right: &Expr) -> Result<(PyExpr, Type), TranslateError> {
+        let (left, right) = (self.translate_expr(left)?, self.translate_expr(right)?);
+        let ty = left.1.wrapped().join(right.1.wrapped()); // python returns one of the operands
+        Ok((PyExpr::logic(op, wrap(left), wrap(right)), ty))
+    }
+    fn translate_value(&mut self, value: &Value) -> Result<(PyExpr, Type), TranslateError> {
         Ok(match value {
-            Value::String(v) => (format_compact!("'{}'", escape(v)), Type::Unknown),
-            Value::Number(v) => (format_compact!("{}", v), Type::Unknown),
-            Value::Bool(v) => ((if *v { "True" } else { "False" }).into(), Type::Wrapped), // bool is considered wrapped since we can't extend it
+            Value::String(v) => (PyExpr::Str(v.clone()), Type::Text),
+            Value::Number(v) => (PyExpr::Num(*v), Type::Number),
+            Value::Bool(v) => (PyExpr::Bool(*v), Type::Bool),
             Value::Constant(c) => match c {
-                Constant::Pi => ("math.pi".into(), Type::Unknown),
-                Constant::E => ("math.e".into(), Type::Unknown),
+                Constant::Pi => (PyExpr::name("math.pi"), Type::Number),
+                Constant::E => (PyExpr::name("math.e"), Type::Number),
             }
             Value::List(vals, _) => {
                 let mut items = Vec::with_capacity(vals.len());
                 for val in vals {
                     items.push(self.translate_value(val)?.0);
                 }
-                (format_compact!("[{}]", Punctuated(items.iter(), ", ")), Type::Unknown)
+                (PyExpr::List(items), Type::Unknown)
             }
             Value::Image(_) => unreachable!(),
             Value::Audio(_) => unreachable!(),
             Value::Ref(_) => unreachable!(),
         })
     }
-    fn translate_kwargs(&mut self, kwargs: &[(CompactString, Expr)], prefix: &str, wrap_vals: bool) -> Result<CompactString, TranslateError> {
+    fn translate_kwargs(&mut self, kwargs: &[(CompactString, Expr)], wrap_vals: bool) -> Result<Vec<Arg>, TranslateError> {
         let mut ident_args = vec![];
         let mut non_ident_args = vec![];
         for arg in kwargs {
             let val_raw = self.translate_expr(&arg.1)?;
             let val = if wrap_vals { wrap(val_raw) } else { val_raw.0 };
             match is_py_ident(&arg.0) {
-                true => ident_args.push(format_compact!("{} = {}", arg.0, val)),
-                false => non_ident_args.push(format_compact!("'{}': {}", escape(&arg.0), val)),
+                true => ident_args.push(Arg::Kw(arg.0.clone(), val)),
+                false => non_ident_args.push((PyExpr::Str(arg.0.clone()), val)),
             }
         }
-
-        Ok(match (ident_args.is_empty(), non_ident_args.is_empty()) {
-            (false, false) => format_compact!("{}{}, **{{ {} }}", prefix, Punctuated(ident_args.iter(), ", "), Punctuated(non_ident_args.iter(), ", ")),
-            (false, true) => format_compact!("{}{}", prefix, Punctuated(ident_args.iter(), ", ")),
-            (true, false) => format_compact!("{}**{{ {} }}", prefix, Punctuated(non_ident_args.iter(), ", ")),
-            (true, true) => CompactString::default(),
+        if !non_ident_args.is_empty() {
+            ident_args.push(Arg::DoubleStar(PyExpr::Dict(non_ident_args)));
+        }
+        Ok(ident_args)
+    }
+    fn translate_rpc(&mut self, service: &str, rpc: &str, args: &[(CompactString, Expr)]) -> Result<PyExpr, TranslateError> {
+        let mut all_args = vec![Arg::Pos(PyExpr::str(service)), Arg::Pos(PyExpr::str(rpc))];
+        all_args.extend(self.translate_kwargs(args, false)?);
+        Ok(PyExpr::name("nothrow").call(vec![PyExpr::name("nb.call")]).call_args(all_args))
+    }
+    fn translate_async_rpc(&mut self, service: &str, rpc: &str, args: &[(CompactString, Expr)]) -> Result<PyExpr, TranslateError> {
+        let mut all_args = vec![Arg::Pos(PyExpr::str(service)), Arg::Pos(PyExpr::str(rpc))];
+        all_args.extend(self.translate_kwargs(args, false)?);
+        Ok(PyExpr::name("nothrow").call(vec![PyExpr::name("nb.call_async")]).call_args(all_args))
+    }
+    /// Attempts to translate a launched command ring as a non-blocking RPC call.
+    /// Returns `None` if the ring is not a (possibly callback-wrapped) RPC call.
+    fn translate_launched_rpc(&mut self, closure: &Expr, args: &[Expr]) -> Result<Option<PyExpr>, TranslateError> {
+        let stmts = match &closure.kind {
+            ExprKind::Closure { kind: ClosureKind::Command, params, captures: _, stmts } if params.is_empty() && args.is_empty() => stmts,
+            _ => return Ok(None),
+        };
+        Ok(match stmts.as_slice() {
+            [Stmt { kind: StmtKind::CallRpc { service, host: _, rpc, args }, .. }] => Some(self.translate_async_rpc(service, rpc, args)?),
+            [Stmt { kind: StmtKind::CallClosure { new_entity: None, closure: callback, args: callback_args }, .. }] => match callback_args.as_slice() {
+                [Expr { kind: ExprKind::CallRpc { service, host: _, rpc, args }, .. }] => {
+                    let future = self.translate_async_rpc(service, rpc, args)?;
+                    let result = snap("wrap", vec![PyExpr::name("_x").method("result", vec![])]);
+                    let callback = PyExpr::Lambda(vec!["_x".into()], Box::new(self.translate_expr(callback)?.0.call(vec![result])));
+                    Some(future.method("add_done_callback", vec![callback]))
+                }
+                _ => None,
+            }
+            _ => None,
         })
     }
-    fn translate_rpc(&mut self, service: &str, rpc: &str, args: &[(CompactString, Expr)]) -> Result<CompactString, TranslateError> {
-        let args_str = self.translate_kwargs(args, ", ", false)?;
-        Ok(format_compact!("nothrow(nb.call)('{}', '{}'{})", escape(service), escape(rpc), args_str))
+    fn translate_dynamic_rpc(&mut self, service: &Expr, rpc: &Expr, args: &Expr) -> Result<PyExpr, TranslateError> {
+        fn as_arg_pair(value: &Expr) -> Option<(CompactString, Expr)> {
+            match &value.kind {
+                ExprKind::MakeList { values } => match values.as_slice() {
+                    [Expr { kind: ExprKind::Value(Value::String(name)), .. }, value] => Some((name.clone(), value.clone())),
+                    _ => None,
+                }
+                ExprKind::Value(Value::List(values, _)) => match values.as_slice() {
+                    [Value::String(name), value] => Some((name.clone(), value.clone().into())),
+                    _ => None,
+                }
+                _ => None,
+            }
+        }
+
+        let literal_args = match &args.kind {
+            ExprKind::MakeList { values } => values.iter().map(as_arg_pair).collect::<Option<Vec<_>>>(),
+            ExprKind::Value(Value::List(values, _)) => values.iter().map(|x| as_arg_pair(&x.clone().into())).collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        let kwargs = match literal_args {
+            Some(args) => self.translate_kwargs(&args, false)?,
+            None => { // args built at runtime are a list of [name, value] pairs, whose names must be strings to be used as keyword arguments
+                let pairs = wrap(self.translate_expr(args)?);
+                let item = PyExpr::Tuple(vec![PyExpr::name("str").call(vec![PyExpr::name("_k")]), PyExpr::name("_v")]);
+                vec![Arg::DoubleStar(PyExpr::name("dict").call(vec![PyExpr::Comp { kind: CompKind::Gen, item: Box::new(item), clauses: vec![("_k, _v".into(), pairs)], condition: None }]))]
+            }
+        };
+        let mut name = |x: &Expr| -> Result<PyExpr, TranslateError> {
+            Ok(match self.translate_expr(x)? {
+                (x, Type::Text) => x,
+                (x, _) => PyExpr::name("str").call(vec![x]),
+            })
+        };
+        let mut all_args = vec![Arg::Pos(name(service)?), Arg::Pos(name(rpc)?)];
+        all_args.extend(kwargs);
+        Ok(PyExpr::name("nothrow").call(vec![PyExpr::name("nb.call")]).call_args(all_args))
     }
-    fn translate_fn_call(&mut self, function: &FnRef, args: &[Expr], upvars: &[VariableRef]) -> Result<CompactString, TranslateError> {
+    fn translate_fn_call(&mut self, function: &FnRef, args: &[Expr], upvars: &[VariableRef]) -> Result<(PyExpr, Type), TranslateError> {
         if !upvars.is_empty() {
             return Err(TranslateError::Upvars);
         }
@@ -142,419 +522,530 @@ impl<'a> ScriptInfo<'a> {
             trans_args.push(wrap(self.translate_expr(arg)?));
         }
 
+        let ty = self.role.types.func(&TypeEnv::func_key(self.owner, function.location, &function.trans_name));
         Ok(match function.location {
-            FnLocation::Global => format_compact!("{}({})", function.trans_name, Punctuated(trans_args.iter(), ", ")),
-            FnLocation::Method => format_compact!("self.{}({})", function.trans_name, Punctuated(trans_args.iter(), ", ")),
+            FnLocation::Global => (PyExpr::Name(function.trans_name.clone()).call(trans_args), ty),
+            FnLocation::Method => (PyExpr::name("self").attr(&function.trans_name).call(trans_args), ty),
         })
     }
-    fn translate_closure_call(&mut self, new_entity: Option<&Expr>, closure: &Expr, args: &[Expr]) -> Result<CompactString, TranslateError> {
+    fn translate_closure_call(&mut self, new_entity: Option<&Expr>, closure: &Expr, args: &[Expr]) -> Result<PyExpr, TranslateError> {
         if new_entity.is_some() {
             return Err(TranslateError::TellAskClosure);
         }
 
         let args = args.iter().map(|x| Ok(wrap(self.translate_expr(x)?))).collect::<Result<Vec<_>,TranslateError>>()?;
-        Ok(format_compact!("{}({})", self.translate_expr(closure)?.0, args.join(", "))) // return values are always considered wrapped
+        Ok(self.translate_expr(closure)?.0.call(args)) // return values are always considered wrapped
     }
-    fn translate_expr(&mut self, expr: &Expr) -> Result<(CompactString, Type), TranslateError> {
+    fn translate_expr(&mut self, expr: &Expr) -> Result<(PyExpr, Type), TranslateError> {
         Ok(match &expr.kind {
             ExprKind::Value(v) => self.translate_value(v)?,
-            ExprKind::Variable { var, .. } => (translate_var(var), Type::Wrapped), // all assignments are wrapped, so we can assume vars are wrapped
+            ExprKind::Variable { var, .. } => (translate_var(var), self.role.types.var(&TypeEnv::var_key(self.owner, var))),
 
             ExprKind::Closure { kind: _, params, captures: _, stmts } => match stmts.as_slice() {
                 [Stmt { kind: StmtKind::Return { value }, info: _ }] => {
-                    let mut params_string = CompactString::default();
                     for param in params {
-                        if params_string.is_empty() {
-                            params_string.push(' ');
-                        } else {
-                            params_string.push_str(", ");
-                        }
-                        params_string.push_str(&param.trans_name);
+                        self.bind_local(&param.name, &param.trans_name, Type::Wrapped); // closures are always called with wrapped arguments
                     }
-                    (format_compact!("(lambda{}: {})",params_string, wrap(self.translate_expr(value)?)), Type::Wrapped) // functions are always considered wrapped
+                    let params = params.iter().map(|x| x.trans_name.clone()).collect();
+                    (PyExpr::Lambda(params, Box::new(wrap(self.translate_expr(value)?))), Type::Closure)
                 },
                 _ => return Err(TranslateError::CommandRing),
             }
 
-            ExprKind::This => ("self".into(), Type::Wrapped), // non-primitives are considered wrapped
-            ExprKind::Entity { trans_name, .. } => (trans_name.clone(), Type::Wrapped), // non-primitives are considered wrapped
+            ExprKind::This => (PyExpr::name("self"), Type::Sprite),
+            ExprKind::Entity { trans_name, .. } => (PyExpr::Name(trans_name.clone()), Type::Sprite),
 
-            ExprKind::ImageOfEntity { entity } => (format_compact!("{}.get_image()", self.translate_expr(entity)?.0), Type::Wrapped), // non-primitives are considered wrapped
-            ExprKind::ImageOfDrawings => (format_compact!("{}.get_drawings()", self.stage_name), Type::Wrapped), // non-primitives are considered wrapped
+            ExprKind::ImageOfEntity { entity } => (self.translate_expr(entity)?.0.method("get_image", vec![]), Type::Wrapped), // non-primitives are considered wrapped
+            ExprKind::ImageOfDrawings => (self.stage().method("get_drawings", vec![]), Type::Wrapped), // non-primitives are considered wrapped
 
-            ExprKind::IsTouchingEntity { entity } => (format_compact!("self.is_touching({})", self.translate_expr(entity)?.0), Type::Wrapped), // bool is considered wrapped
+            ExprKind::IsTouchingEntity { entity } => (PyExpr::name("self").method("is_touching", vec![self.translate_expr(entity)?.0]), Type::Bool),
 
             ExprKind::MakeList { values } => {
                 let trans = values.iter().map(|x| Ok(self.translate_expr(x)?.0)).collect::<Result<Vec<_>,TranslateError>>()?;
-                (format_compact!("[{}]", trans.join(", ")), Type::Unknown)
+                (PyExpr::List(trans), Type::Unknown)
             }
-            ExprKind::CopyList { list } => (format_compact!("[*{}]", wrap(self.translate_expr(list)?)), Type::Unknown),
-            ExprKind::ListCons { item, list } => (format_compact!("[{}, *{}]", self.translate_expr(item)?.0, wrap(self.translate_expr(list)?)), Type::Unknown),
-            ExprKind::ListCdr { value } => (format_compact!("{}[1:]", wrap(self.translate_expr(value)?)), Type::Wrapped),
+            ExprKind::CopyList { list } => (PyExpr::List(vec![PyExpr::Starred(Box::new(wrap(self.translate_expr(list)?)))]), Type::Unknown),
+            ExprKind::ListCons { item, list } => (PyExpr::List(vec![self.translate_expr(item)?.0, PyExpr::Starred(Box::new(wrap(self.translate_expr(list)?)))]), Type::Unknown),
+            ExprKind::ListCdr { value } => (wrap(self.translate_expr(value)?).index(PyExpr::Slice(Some(Box::new(PyExpr::Num(1.0))), None, None)), Type::List),
 
-            ExprKind::ListGet { list, index } => (format_compact!("{}[{} - snap.wrap(1)]", wrap(self.translate_expr(list)?), wrap(self.translate_expr(index)?)), Type::Wrapped),
-            ExprKind::ListGetRandom { list } => (format_compact!("{}.rand", wrap(self.translate_expr(list)?)), Type::Wrapped),
-            ExprKind::ListGetLast { list } => (format_compact!("{}.last", wrap(self.translate_expr(list)?)), Type::Wrapped),
+            ExprKind::ListGet { list, index } => (wrap(self.translate_expr(list)?).index(self.translate_index(index)?), Type::Wrapped),
+            ExprKind::ListGetRandom { list } => (wrap(self.translate_expr(list)?).attr("rand"), Type::Wrapped),
+            ExprKind::ListGetLast { list } => (wrap(self.translate_expr(list)?).attr("last"), Type::Wrapped),
 
-            ExprKind::ListFind { list, value } => (format_compact!("({}.index({}) + snap.wrap(1))", wrap(self.translate_expr(list)?), self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::ListContains { list, value } => (format_compact!("({} in {})", wrap(self.translate_expr(value)?), wrap(self.translate_expr(list)?)), Type::Wrapped),
+            ExprKind::ListFind { list, value } => arith(BinOp::Add, (wrap(self.translate_expr(list)?).method("index", vec![self.translate_expr(value)?.0]), Type::Wrapped), (PyExpr::Num(1.0), Type::Number)),
+            ExprKind::ListContains { list, value } => (PyExpr::cmp(CmpOp::In, wrap(self.translate_expr(value)?), wrap(self.translate_expr(list)?)), Type::Bool),
 
-            ExprKind::ListLen { value } | ExprKind::StrLen { value } => (format_compact!("len({})", self.translate_expr(value)?.0), Type::Unknown), // builtin __len__ can't be overloaded to return wrapped
-            ExprKind::ListIsEmpty { value } => (format_compact!("(len({}) == 0)", self.translate_expr(value)?.0), Type::Wrapped),
+            ExprKind::ListLen { value } | ExprKind::StrLen { value } => (PyExpr::name("len").call(vec![self.translate_expr(value)?.0]), Type::Number), // builtin __len__ can't be overloaded to return wrapped
+            ExprKind::ListIsEmpty { value } => (PyExpr::cmp(CmpOp::Eq, PyExpr::name("len").call(vec![self.translate_expr(value)?.0]), PyExpr::Num(0.0)), Type::Bool),
 
-            ExprKind::ListRank { value } => (format_compact!("len({}.shape)", wrap(self.translate_expr(value)?)), Type::Unknown), // builtin __len__ can't be overloaded to return wrapped
-            ExprKind::ListDims { value } => (format_compact!("{}.shape", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::ListFlatten { value } => (format_compact!("{}.flat", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::ListColumns { value } => (format_compact!("{}.T", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::ListRev { value } => (format_compact!("{}[::-1]", wrap(self.translate_expr(value)?)), Type::Wrapped),
+            ExprKind::ListRank { value } => (PyExpr::name("len").call(vec![wrap(self.translate_expr(value)?).attr("shape")]), Type::Number), // builtin __len__ can't be overloaded to return wrapped
+            ExprKind::ListDims { value } => (wrap(self.translate_expr(value)?).attr("shape"), Type::Wrapped),
+            ExprKind::ListFlatten { value } => (wrap(self.translate_expr(value)?).attr("flat"), Type::Wrapped),
+            ExprKind::ListColumns { value } => (wrap(self.translate_expr(value)?).attr("T"), Type::List),
+            ExprKind::ListRev { value } => (wrap(self.translate_expr(value)?).index(PyExpr::Slice(None, None, Some(Box::new(PyExpr::Num(-1.0))))), Type::List),
 
-            ExprKind::ListLines { value } => (format_compact!("'\\n'.join(str(x) for x in {})", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::ListCsv { value } => (format_compact!("{}.csv", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::ListJson { value } => (format_compact!("{}.json", wrap(self.translate_expr(value)?)), Type::Wrapped),
+            ExprKind::ListLines { value } => (PyExpr::str("\n").method("join", vec![str_of_each(wrap(self.translate_expr(value)?))]), Type::Text),
+            ExprKind::ListCsv { value } => (wrap(self.translate_expr(value)?).attr("csv"), Type::Wrapped),
+            ExprKind::ListJson { value } => (wrap(self.translate_expr(value)?).attr("json"), Type::Wrapped),
 
-            ExprKind::ListReshape { value, dims } => (format_compact!("{}.reshaped({})", wrap(self.translate_expr(value)?), self.translate_expr(dims)?.0), Type::Wrapped),
+            ExprKind::ListReshape { value, dims } => (wrap(self.translate_expr(value)?).method("reshaped", vec![self.translate_expr(dims)?.0]), Type::List),
 
-            ExprKind::Map { f, list } => (format_compact!("[{}(x) for x in {}]", self.translate_expr(f)?.0, wrap(self.translate_expr(list)?)), Type::Unknown),
-            ExprKind::Keep { f, list } => (format_compact!("[x for x in {} if {}(x)]", wrap(self.translate_expr(list)?), self.translate_expr(f)?.0), Type::Unknown),
-            ExprKind::FindFirst { f, list } => (format_compact!("{}.index_where({})", wrap(self.translate_expr(list)?), self.translate_expr(f)?.0), Type::Wrapped),
-            ExprKind::Combine { f, list } => (format_compact!("{}.fold({})", wrap(self.translate_expr(list)?), self.translate_expr(f)?.0), Type::Wrapped),
+            ExprKind::Map { f, list } => {
+                let f = self.translate_expr(f)?.0;
+                (PyExpr::Comp { kind: CompKind::List, item: Box::new(f.call(vec![PyExpr::name("_x")])), clauses: vec![("_x".into(), wrap(self.translate_expr(list)?))], condition: None }, Type::Unknown)
+            }
+            ExprKind::Keep { f, list } => {
+                let list = wrap(self.translate_expr(list)?);
+                let f = self.translate_expr(f)?.0;
+                (PyExpr::Comp { kind: CompKind::List, item: Box::new(PyExpr::name("_x")), clauses: vec![("_x".into(), list)], condition: Some(Box::new(f.call(vec![PyExpr::name("_x")]))) }, Type::Unknown)
+            }
+            ExprKind::FindFirst { f, list } => (wrap(self.translate_expr(list)?).method("index_where", vec![self.translate_expr(f)?.0]), Type::Wrapped),
+            ExprKind::Combine { f, list } => (wrap(self.translate_expr(list)?).method("fold", vec![self.translate_expr(f)?.0]), Type::Wrapped),
 
-            ExprKind::StrGet { string, index } => (format_compact!("{}[{} - snap.wrap(1)]", wrap(self.translate_expr(string)?), wrap(self.translate_expr(index)?)), Type::Wrapped),
-            ExprKind::StrGetLast { string } => (format_compact!("{}.last", wrap(self.translate_expr(string)?)), Type::Wrapped),
-            ExprKind::StrGetRandom { string } => (format_compact!("{}.rand", wrap(self.translate_expr(string)?)), Type::Wrapped),
+            ExprKind::StrGet { string, index } => (wrap(self.translate_expr(string)?).index(self.translate_index(index)?), Type::Wrapped),
+            ExprKind::StrGetLast { string } => (wrap(self.translate_expr(string)?).attr("last"), Type::Wrapped),
+            ExprKind::StrGetRandom { string } => (wrap(self.translate_expr(string)?).attr("rand"), Type::Wrapped),
 
-            ExprKind::Neg { value } => (format_compact!("-{}", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::Not { value } => (format_compact!("snap.lnot({})", self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::Abs { value } => (format_compact!("abs({})", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::Sign { value } => (format_compact!("snap.sign({})", self.translate_expr(value)?.0), Type::Wrapped),
+            ExprKind::Neg { value } => match as_number(self.translate_expr(value)?) {
+                (value, Type::Number) => (PyExpr::unary(UnaryOp::Neg, value), Type::Number),
+                value => (PyExpr::unary(UnaryOp::Neg, wrap(value)), Type::Wrapped),
+            }
+            ExprKind::Not { value } => match self.translate_expr(value)? {
+                (value, Type::Bool) => (PyExpr::unary(UnaryOp::Not, value), Type::Bool),
+                (value, _) => (snap("lnot", vec![value]), Type::Bool),
+            }
+            ExprKind::Abs { value } => match as_number(self.translate_expr(value)?) {
+                (value, Type::Number) => (PyExpr::name("abs").call(vec![value]), Type::Number),
+                value => (PyExpr::name("abs").call(vec![wrap(value)]), Type::Wrapped),
+            }
+            ExprKind::Sign { value } => (snap("sign", vec![self.translate_expr(value)?.0]), Type::Wrapped),
 
-            ExprKind::Atan2 { y, x } => (format_compact!("snap.atan2({}, {})", self.translate_expr(y)?.0, self.translate_expr(x)?.0), Type::Wrapped),
+            ExprKind::Atan2 { y, x } => (snap("atan2", vec![self.translate_expr(y)?.0, self.translate_expr(x)?.0]), Type::Wrapped),
 
             ExprKind::ListCombinations { sources } => match &sources.kind {
-                ExprKind::Value(Value::List(values, _)) => (format_compact!("snap.combinations({})", values.iter().map(|x| Ok(self.translate_value(x)?.0)).collect::<Result<Vec<_>,TranslateError>>()?.join(", ")), Type::Wrapped),
-                ExprKind::MakeList { values } => (format_compact!("snap.combinations({})", values.iter().map(|x| Ok(self.translate_expr(x)?.0)).collect::<Result<Vec<_>,TranslateError>>()?.join(", ")), Type::Wrapped),
-                _ => (format_compact!("snap.combinations(*{})", wrap(self.translate_expr(sources)?)), Type::Wrapped),
+                ExprKind::Value(Value::List(values, _)) => (snap("combinations", values.iter().map(|x| Ok(self.translate_value(x)?.0)).collect::<Result<Vec<_>,TranslateError>>()?), Type::List),
+                ExprKind::MakeList { values } => (snap("combinations", values.iter().map(|x| Ok(self.translate_expr(x)?.0)).collect::<Result<Vec<_>,TranslateError>>()?), Type::List),
+                _ => (PyExpr::name("snap.combinations").call_args(vec![Arg::Star(wrap(self.translate_expr(sources)?))]), Type::List),
             }
             ExprKind::Add { values } => match &values.kind {
                 ExprKind::Value(Value::List(values, _)) => match values.as_slice() {
-                    [] => ("0".into(), Type::Unknown),
-                    _ => (format_compact!("({})", values.iter().map(|x| Ok(wrap(self.translate_value(x)?))).collect::<Result<Vec<_>,TranslateError>>()?.join(" + ")), Type::Wrapped),
+                    [] => (PyExpr::Num(0.0), Type::Number),
+                    _ => values.iter().map(|x| self.translate_value(x)).collect::<Result<Vec<_>,TranslateError>>()?.into_iter().reduce(|a, b| arith(BinOp::Add, a, b)).unwrap(),
                 }
                 ExprKind::MakeList { values } => match values.as_slice() {
-                    [] => ("0".into(), Type::Unknown),
-                    _ => (format_compact!("({})", values.iter().map(|x| Ok(wrap(self.translate_expr(x)?))).collect::<Result<Vec<_>,TranslateError>>()?.join(" + ")), Type::Wrapped),
+                    [] => (PyExpr::Num(0.0), Type::Number),
+                    _ => values.iter().map(|x| self.translate_expr(x)).collect::<Result<Vec<_>,TranslateError>>()?.into_iter().reduce(|a, b| arith(BinOp::Add, a, b)).unwrap(),
                 }
-                _ => (format_compact!("sum({})", wrap(self.translate_expr(values)?)), Type::Unknown),
+                _ => (PyExpr::name("sum").call(vec![wrap(self.translate_expr(values)?)]), Type::Unknown),
             }
             ExprKind::Mul { values } => match &values.kind {
                 ExprKind::Value(Value::List(values, _)) => match values.as_slice() {
-                    [] => ("1".into(), Type::Unknown),
-                    _ => (format_compact!("({})", values.iter().map(|x| Ok(wrap(self.translate_value(x)?))).collect::<Result<Vec<_>,TranslateError>>()?.join(" * ")), Type::Wrapped),
+                    [] => (PyExpr::Num(1.0), Type::Number),
+                    _ => values.iter().map(|x| self.translate_value(x)).collect::<Result<Vec<_>,TranslateError>>()?.into_iter().reduce(|a, b| arith(BinOp::Mul, a, b)).unwrap(),
                 }
                 ExprKind::MakeList { values } => match values.as_slice() {
-                    [] => ("1".into(), Type::Unknown),
-                    _ => (format_compact!("({})", values.iter().map(|x| Ok(wrap(self.translate_expr(x)?))).collect::<Result<Vec<_>,TranslateError>>()?.join(" * ")), Type::Wrapped),
+                    [] => (PyExpr::Num(1.0), Type::Number),
+                    _ => values.iter().map(|x| self.translate_expr(x)).collect::<Result<Vec<_>,TranslateError>>()?.into_iter().reduce(|a, b| arith(BinOp::Mul, a, b)).unwrap(),
                 }
-                _ => (format_compact!("snap.prod({})", self.translate_expr(values)?.0), Type::Wrapped),
+                _ => (snap("prod", vec![self.translate_expr(values)?.0]), Type::Wrapped),
             }
 
-            ExprKind::Min { values } => (format_compact!("min({})", wrap(self.translate_expr(values)?)), Type::Wrapped),
-            ExprKind::Max { values } => (format_compact!("max({})", wrap(self.translate_expr(values)?)), Type::Wrapped),
+            ExprKind::Min { values } => (PyExpr::name("min").call(vec![wrap(self.translate_expr(values)?)]), Type::Wrapped),
+            ExprKind::Max { values } => (PyExpr::name("max").call(vec![wrap(self.translate_expr(values)?)]), Type::Wrapped),
 
-            ExprKind::Sub { left, right } => (format_compact!("({} - {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::Div { left, right } => (format_compact!("({} / {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::Mod { left, right } => (format_compact!("({} % {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
+            ExprKind::Sub { left, right } => arith(BinOp::Sub, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::Div { left, right } => arith(BinOp::Div, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::Mod { left, right } => arith(BinOp::Mod, self.translate_expr(left)?, self.translate_expr(right)?),
 
-            ExprKind::Pow { base, power } => (format_compact!("({} ** {})", wrap(self.translate_expr(base)?), wrap(self.translate_expr(power)?)), Type::Wrapped),
-            ExprKind::Log { value, base } => (format_compact!("snap.log({}, {})", self.translate_expr(value)?.0, self.translate_expr(base)?.0), Type::Wrapped),
+            ExprKind::Pow { base, power } => arith(BinOp::Pow, self.translate_expr(base)?, self.translate_expr(power)?),
+            ExprKind::Log { value, base } => (snap("log", vec![self.translate_expr(value)?.0, self.translate_expr(base)?.0]), Type::Wrapped),
 
-            ExprKind::Sqrt { value } => (format_compact!("snap.sqrt({})", self.translate_expr(value)?.0), Type::Wrapped),
+            ExprKind::Sqrt { value } => (snap("sqrt", vec![self.translate_expr(value)?.0]), Type::Wrapped),
 
-            ExprKind::Round { value } => (format_compact!("round({})", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::Floor { value } => (format_compact!("math.floor({})", wrap(self.translate_expr(value)?)), Type::Wrapped),
-            ExprKind::Ceil { value } => (format_compact!("math.ceil({})", wrap(self.translate_expr(value)?)), Type::Wrapped),
+            ExprKind::Round { value } => (PyExpr::name("round").call(vec![wrap(self.translate_expr(value)?)]), Type::Wrapped),
+            ExprKind::Floor { value } => match as_number(self.translate_expr(value)?) {
+                (value, Type::Number) => (PyExpr::name("math.floor").call(vec![value]), Type::Number),
+                value => (PyExpr::name("math.floor").call(vec![wrap(value)]), Type::Wrapped),
+            }
+            ExprKind::Ceil { value } => match as_number(self.translate_expr(value)?) {
+                (value, Type::Number) => (PyExpr::name("math.ceil").call(vec![value]), Type::Number),
+                value => (PyExpr::name("math.ceil").call(vec![wrap(value)]), Type::Wrapped),
+            }
 
-            ExprKind::Sin { value } => (format_compact!("snap.sin({})", self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::Cos { value } => (format_compact!("snap.cos({})", self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::Tan { value } => (format_compact!("snap.tan({})", self.translate_expr(value)?.0), Type::Wrapped),
+            ExprKind::Sin { value } => (snap("sin", vec![self.translate_expr(value)?.0]), Type::Wrapped),
+            ExprKind::Cos { value } => (snap("cos", vec![self.translate_expr(value)?.0]), Type::Wrapped),
+            ExprKind::Tan { value } => (snap("tan", vec![self.translate_expr(value)?.0]), Type::Wrapped),
 
-            ExprKind::Asin { value } => (format_compact!("snap.asin({})", self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::Acos { value } => (format_compact!("snap.acos({})", self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::Atan { value } => (format_compact!("snap.atan({})", self.translate_expr(value)?.0), Type::Wrapped),
+            ExprKind::Asin { value } => (snap("asin", vec![self.translate_expr(value)?.0]), Type::Wrapped),
+            ExprKind::Acos { value } => (snap("acos", vec![self.translate_expr(value)?.0]), Type::Wrapped),
+            ExprKind::Atan { value } => (snap("atan", vec![self.translate_expr(value)?.0]), Type::Wrapped),
 
-            ExprKind::And { left, right } => (format_compact!("({} and {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::Or { left, right } => (format_compact!("({} or {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
+            ExprKind::And { left, right } => self.translate_logic(LogicOp::And, left, right)?,
+            ExprKind::Or { left, right } => self.translate_logic(LogicOp::Or, left, right)?,
             ExprKind::Conditional { condition, then, otherwise } => {
                 let (then, otherwise) = (self.translate_expr(then)?, self.translate_expr(otherwise)?);
-                (format_compact!("({} if {} else {})", then.0, wrap(self.translate_expr(condition)?), otherwise.0), if then.1 == otherwise.1 { then.1 } else { Type::Unknown })
+                let ty = then.1.join(otherwise.1);
+                (PyExpr::IfElse { then: Box::new(then.0), condition: Box::new(wrap(self.translate_expr(condition)?)), otherwise: Box::new(otherwise.0) }, ty)
             }
 
-            ExprKind::Identical { left, right } => (format_compact!("snap.identical({}, {})", self.translate_expr(left)?.0, self.translate_expr(right)?.0), Type::Wrapped), // bool is considered wrapped
+            ExprKind::Identical { left, right } => (snap("identical", vec![self.translate_expr(left)?.0, self.translate_expr(right)?.0]), Type::Bool),
 
-            ExprKind::Less { left, right } => (format_compact!("({} < {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::LessEq { left, right } => (format_compact!("({} <= {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::Eq { left, right } => (format_compact!("({} == {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::Neq { left, right } => (format_compact!("({} != {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::Greater { left, right } => (format_compact!("({} > {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
-            ExprKind::GreaterEq { left, right } => (format_compact!("({} >= {})", wrap(self.translate_expr(left)?), wrap(self.translate_expr(right)?)), Type::Wrapped),
+            ExprKind::Less { left, right } => compare(CmpOp::Less, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::LessEq { left, right } => compare(CmpOp::LessEq, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::Eq { left, right } => compare(CmpOp::Eq, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::Neq { left, right } => compare(CmpOp::Neq, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::Greater { left, right } => compare(CmpOp::Greater, self.translate_expr(left)?, self.translate_expr(right)?),
+            ExprKind::GreaterEq { left, right } => compare(CmpOp::GreaterEq, self.translate_expr(left)?, self.translate_expr(right)?),
 
-            ExprKind::Random { a, b } => (format_compact!("snap.rand({}, {})", self.translate_expr(a)?.0, self.translate_expr(b)?.0), Type::Wrapped), // python impl returns wrapped
-            ExprKind::Range { start, stop } => (format_compact!("snap.srange({}, {})", self.translate_expr(start)?.0, self.translate_expr(stop)?.0), Type::Wrapped), // python impl returns wrapped
+            ExprKind::Random { a, b } => (snap("rand", vec![self.translate_expr(a)?.0, self.translate_expr(b)?.0]), Type::Wrapped), // python impl returns wrapped
+            ExprKind::Range { start, stop } => (snap("srange", vec![self.translate_expr(start)?.0, self.translate_expr(stop)?.0]), Type::List), // python impl returns wrapped
 
-            ExprKind::CostumeNumber => (format_compact!("(self.costumes.index(self.costume, -1) + 1)"), Type::Unknown),
+            ExprKind::CostumeNumber => (costume_index(), Type::Number),
 
             ExprKind::TextSplit { text, mode } => match mode {
-                TextSplitMode::Custom(x) => (format_compact!("snap.split({}, {})", self.translate_expr(text)?.0, self.translate_expr(x)?.0), Type::Wrapped),
-                TextSplitMode::LF => (format_compact!("snap.split({}, '\\n')", self.translate_expr(text)?.0), Type::Wrapped),
-                TextSplitMode::CR => (format_compact!("snap.split({}, '\\r')", self.translate_expr(text)?.0), Type::Wrapped),
-                TextSplitMode::Tab => (format_compact!("snap.split({}, '\\t')", self.translate_expr(text)?.0), Type::Wrapped),
-                TextSplitMode::Letter => (format_compact!("snap.split({}, '')", self.translate_expr(text)?.0), Type::Wrapped),
-                TextSplitMode::Word => (format_compact!("snap.split_words({})", self.translate_expr(text)?.0), Type::Wrapped),
-                TextSplitMode::Csv => (format_compact!("snap.split_csv({})", self.translate_expr(text)?.0), Type::Wrapped),
-                TextSplitMode::Json => (format_compact!("snap.split_json({})", self.translate_expr(text)?.0), Type::Wrapped),
+                TextSplitMode::Custom(x) => (snap("split", vec![self.translate_expr(text)?.0, self.translate_expr(x)?.0]), Type::List),
+                TextSplitMode::LF => (snap("split", vec![self.translate_expr(text)?.0, PyExpr::str("\n")]), Type::List),
+                TextSplitMode::CR => (snap("split", vec![self.translate_expr(text)?.0, PyExpr::str("\r")]), Type::List),
+                TextSplitMode::Tab => (snap("split", vec![self.translate_expr(text)?.0, PyExpr::str("\t")]), Type::List),
+                TextSplitMode::Letter => (snap("split", vec![self.translate_expr(text)?.0, PyExpr::str("")]), Type::List),
+                TextSplitMode::Word => (snap("split_words", vec![self.translate_expr(text)?.0]), Type::List),
+                TextSplitMode::Csv => (snap("split_csv", vec![self.translate_expr(text)?.0]), Type::List),
+                TextSplitMode::Json => (snap("split_json", vec![self.translate_expr(text)?.0]), Type::Wrapped),
             }
 
             ExprKind::TypeQuery { value, ty } => match ty {
-                ValueType::Bool => (format_compact!("snap.is_bool({})", self.translate_expr(value)?.0), Type::Wrapped),
-                ValueType::Text => (format_compact!("snap.is_text({})", self.translate_expr(value)?.0), Type::Wrapped),
-                ValueType::Number => (format_compact!("snap.is_number({})", self.translate_expr(value)?.0), Type::Wrapped),
-                ValueType::List => (format_compact!("snap.is_list({})", self.translate_expr(value)?.0), Type::Wrapped),
-                ValueType::Sprite => (format_compact!("snap.is_sprite({})", self.translate_expr(value)?.0), Type::Wrapped),
-                ValueType::Costume => (format_compact!("snap.is_costume({})", self.translate_expr(value)?.0), Type::Wrapped),
-                ValueType::Sound => (format_compact!("snap.is_sound({})", self.translate_expr(value)?.0), Type::Wrapped),
+                ValueType::Bool => (snap("is_bool", vec![self.translate_expr(value)?.0]), Type::Bool),
+                ValueType::Text => (snap("is_text", vec![self.translate_expr(value)?.0]), Type::Bool),
+                ValueType::Number => (snap("is_number", vec![self.translate_expr(value)?.0]), Type::Bool),
+                ValueType::List => (snap("is_list", vec![self.translate_expr(value)?.0]), Type::Bool),
+                ValueType::Sprite => (snap("is_sprite", vec![self.translate_expr(value)?.0]), Type::Bool),
+                ValueType::Costume => (snap("is_costume", vec![self.translate_expr(value)?.0]), Type::Bool),
+                ValueType::Sound => (snap("is_sound", vec![self.translate_expr(value)?.0]), Type::Bool),
                 ValueType::Command | ValueType::Reporter | ValueType::Predicate => return Err(TranslateError::RingTypeQuery),
             }
 
             ExprKind::ListCat { lists } => match &lists.kind {
-                ExprKind::Value(Value::List(values, _)) => (format_compact!("[{}]", values.iter().map(|x| Ok(format_compact!("*{}", wrap(self.translate_value(x)?)))).collect::<Result<Vec<_>,TranslateError>>()?.join(", ")), Type::Unknown),
-                _ => (format_compact!("[y for x in {} for y in x]", wrap(self.translate_expr(lists)?)), Type::Unknown),
+                ExprKind::Value(Value::List(values, _)) => (PyExpr::List(values.iter().map(|x| Ok(PyExpr::Starred(Box::new(wrap(self.translate_value(x)?))))).collect::<Result<Vec<_>,TranslateError>>()?), Type::Unknown),
+                _ => (PyExpr::Comp { kind: CompKind::List, item: Box::new(PyExpr::name("_y")), clauses: vec![("_x".into(), wrap(self.translate_expr(lists)?)), ("_y".into(), PyExpr::name("_x"))], condition: None }, Type::Unknown),
             }
             ExprKind::StrCat { values } => {
-                fn as_str_lit(mut s: &str) -> Option<(&str, bool)> {
-                    let mut fmt_str = false;
-                    if s.starts_with('f') { s = &s[1..]; fmt_str = true; }
-                    s.chars().next().filter(|&c| (c == '"' || c == '\'') && s.len() >= 2 && s.ends_with(c)).map(|_| (&s[1..s.len() - 1], fmt_str))
-                }
-                fn escape_braces(s: &str) -> CompactString {
-                    let mut res = CompactString::with_capacity(s.len());
-                    for c in s.chars() {
-                        res.push(c);
-                        if c == '{' || c == '}' { res.push(c); }
-                    }
-                    res
-                }
-                fn handle_segments(segments: Vec<(CompactString, Type)>) -> (CompactString, Type) {
-                    let mut fmt_str = false;
-                    let mut res = CompactString::default();
+                fn handle_segments(segments: Vec<(PyExpr, Type)>) -> (PyExpr, Type) {
+                    let mut parts = vec![];
                     for segment in segments.iter() {
-                        match as_str_lit(&segment.0) {
-                            Some(lit) => {
-                                if !fmt_str && lit.1 { res = escape_braces(&res) }
-                                fmt_str |= lit.1;
-                                if fmt_str && !lit.1 { res.push_str(&escape_braces(&lit.0)) } else { res.push_str(&lit.0) }
-                            }
-                            None => {
-                                if !fmt_str { res = escape_braces(&res) }
-                                fmt_str = true;
-                                write!(res, "{{{}}}", segment.0).unwrap();
-                            }
+                        match &segment.0 {
+                            PyExpr::Str(x) => parts.push(FStrPart::Lit(x.clone())),
+                            PyExpr::FStr(x) => parts.extend(x.iter().cloned()),
+                            x => parts.push(FStrPart::Expr(x.clone())),
                         }
                     }
-                    let quote_char = match (res.contains('\''), res.contains('"')) {
-                        (false, _) => '\'',
-                        (true, false) => '"',
-                        (true, true) => return (format_compact!("({})", segments.into_iter().map(|x| format_compact!("str({})", wrap(x))).collect::<Vec<_>>().join(" + ")), Type::Unknown),
-                    };
-                    (format_compact!("{}{quote_char}{res}{quote_char}", if fmt_str { "f" } else { "" }), Type::Unknown)
+                    match ir::fstr_quote(&parts) {
+                        Some(_) => (PyExpr::FStr(parts), Type::Text),
+                        None => (PyExpr::bin_chain(BinOp::Add, segments.into_iter().map(|x| PyExpr::name("str").call(vec![wrap(x)])).collect()), Type::Text),
+                    }
                 }
                 match &values.kind {
                     ExprKind::Value(Value::List(values, _)) => handle_segments(values.iter().map(|x| self.translate_value(x)).collect::<Result<Vec<_>,_>>()?),
                     ExprKind::MakeList { values } => handle_segments(values.iter().map(|x| self.translate_expr(x)).collect::<Result<Vec<_>,_>>()?),
-                    _ => (format_compact!("''.join(str(x) for x in {})", wrap(self.translate_expr(values)?)), Type::Unknown),
+                    _ => (PyExpr::str("").method("join", vec![str_of_each(wrap(self.translate_expr(values)?))]), Type::Text),
                 }
             }
 
-            ExprKind::UnicodeToChar { value } => (format_compact!("snap.get_chr({})", self.translate_expr(value)?.0), Type::Wrapped),
-            ExprKind::CharToUnicode { value } => (format_compact!("snap.get_ord({})", self.translate_expr(value)?.0), Type::Wrapped),
+            ExprKind::UnicodeToChar { value } => (snap("get_chr", vec![self.translate_expr(value)?.0]), Type::Wrapped),
+            ExprKind::CharToUnicode { value } => (snap("get_ord", vec![self.translate_expr(value)?.0]), Type::Wrapped),
 
             ExprKind::CallRpc { service, host: _, rpc, args } => (self.translate_rpc(service, rpc, args)?, Type::Unknown),
-            ExprKind::CallFn { function, args, upvars } => (self.translate_fn_call(function, args, upvars)?, Type::Wrapped),
+            ExprKind::NetworkMessageReply { target, msg_type, values } => {
+                self.role.msg_types.entry(msg_type.clone()).or_insert_with(|| values.iter().map(|x| x.0.clone()).collect());
+                let mut args = vec![Arg::Pos(PyExpr::str(msg_type)), Arg::Pos(self.translate_expr(target)?.0)];
+                args.extend(self.translate_kwargs(values, false)?);
+                (PyExpr::name("nb.send_request").call_args(args), Type::Unknown)
+            }
+            ExprKind::CallFn { function, args, upvars } => self.translate_fn_call(function, args, upvars)?,
             ExprKind::CallClosure { new_entity, closure, args } => (self.translate_closure_call(new_entity.as_deref(), closure, args)?, Type::Wrapped),
 
-            ExprKind::XPos => ("self.x_pos".into(), Type::Unknown),
-            ExprKind::YPos => ("self.y_pos".into(), Type::Unknown),
-            ExprKind::Heading => ("self.heading".into(), Type::Unknown),
+            ExprKind::XPos => (PyExpr::name("self.x_pos"), Type::Number),
+            ExprKind::YPos => (PyExpr::name("self.y_pos"), Type::Number),
+            ExprKind::Heading => (PyExpr::name("self.heading"), Type::Number),
+
+            ExprKind::Answer => (self.stage().attr("last_answer"), Type::Wrapped),
+
+            ExprKind::MouseX => (self.stage().attr("mouse_pos").index(PyExpr::Num(0.0)), Type::Number),
+            ExprKind::MouseY => (self.stage().attr("mouse_pos").index(PyExpr::Num(1.0)), Type::Number),
 
-            ExprKind::Answer => (format_compact!("{}.last_answer", self.stage_name), Type::Wrapped),
+            ExprKind::StageWidth => (self.stage().attr("width"), Type::Number),
+            ExprKind::StageHeight => (self.stage().attr("height"), Type::Number),
 
-            ExprKind::MouseX => (format_compact!("{}.mouse_pos[0]", self.stage_name), Type::Unknown),
-            ExprKind::MouseY => (format_compact!("{}.mouse_pos[1]", self.stage_name), Type::Unknown),
+            ExprKind::Latitude => (self.stage().attr("gps_location").index(PyExpr::Num(0.0)), Type::Number),
+            ExprKind::Longitude => (self.stage().attr("gps_location").index(PyExpr::Num(1.0)), Type::Number),
 
-            ExprKind::StageWidth => (format_compact!("{}.width", self.stage_name), Type::Unknown),
-            ExprKind::StageHeight => (format_compact!("{}.height", self.stage_name), Type::Unknown),
+            ExprKind::KeyDown { key } => (self.stage().method("is_key_down", vec![self.translate_expr(key)?.0]), Type::Bool),
 
-            ExprKind::Latitude => (format_compact!("{}.gps_location[0]", self.stage_name), Type::Unknown),
-            ExprKind::Longitude => (format_compact!("{}.gps_location[1]", self.stage_name), Type::Unknown),
+            ExprKind::PenDown => (PyExpr::name("self.drawing"), Type::Bool),
+            ExprKind::Size => (PyExpr::bin(BinOp::Mul, PyExpr::name("self.scale"), PyExpr::Num(100.0)), Type::Number),
+            ExprKind::IsVisible => (PyExpr::name("self.visible"), Type::Bool),
 
-            ExprKind::KeyDown { key } => (format_compact!("{stage_name}.is_key_down({key})", key = self.translate_expr(key)?.0, stage_name = self.stage_name), Type::Wrapped), // bool is considered wrapped
+            ExprKind::RpcError => (PyExpr::logic(LogicOp::Or, PyExpr::name("get_error").call(vec![]), PyExpr::str("")), Type::Text),
 
-            ExprKind::PenDown => ("self.drawing".into(), Type::Wrapped), // bool is considered wrapped
-            ExprKind::Size => ("(self.scale * 100)".into(), Type::Wrapped),
-            ExprKind::IsVisible => ("self.visible".into(), Type::Wrapped), // bool is considered wrapped
+            ExprKind::Clone { target } => (self.translate_expr(target)?.0.method("clone", vec![]), Type::Sprite),
 
-            ExprKind::RpcError => ("(get_error() or '')".into(), Type::Unknown),
+            ExprKind::Timer => (self.stage().attr("timer"), Type::Number),
 
-            ExprKind::Clone { target } => (format_compact!("{}.clone()", self.translate_expr(target)?.0), Type::Wrapped), // sprites are considered wrapped
+            ExprKind::SoundDuration { sound } => (PyExpr::name("self.sounds").method("lookup", vec![self.translate_expr(sound)?.0]).attr("duration"), Type::Wrapped), // sounds are considered wrapped
 
-            ExprKind::Timer => (format_compact!("{}.timer", self.stage_name), Type::Unknown),
+            ExprKind::UnknownBlock { name, args } if name == DYNAMIC_RPC_EXPR => match args.as_slice() {
+                [service, rpc, args] => (self.translate_dynamic_rpc(service, rpc, args)?, Type::Unknown),
+                _ => return Err(TranslateError::UnsupportedExpr(Box::new(expr.clone()))),
+            }
 
-            ExprKind::SoundDuration { sound } => (format_compact!("self.sounds.lookup({}).duration", self.translate_expr(sound)?.0), Type::Wrapped), // sounds are considered wrapped
+            ExprKind::UnknownBlock { name, args } if name == MSG_SENDER_EXPR && args.is_empty() && self.in_msg_handler => (PyExpr::name(MSG_SENDER_PARAM), Type::Unknown),
+            ExprKind::UnknownBlock { name, args } if args.is_empty() => match IDENTITY_REPORTERS.iter().find(|x| x.0 == name) {
+                Some(x) => (PyExpr::name(x.1), Type::Unknown),
+                None => return Err(TranslateError::UnsupportedExpr(Box::new(expr.clone()))),
+            }
 
             _ => return Err(TranslateError::UnsupportedExpr(Box::new(expr.clone()))),
         })
     }
-    fn translate_stmts(&mut self, stmts: &[Stmt]) -> Result<CompactString, TranslateError> {
-        if stmts.is_empty() { return Ok("pass".into()) }
+    fn translate_stmts(&mut self, stmts: &[Stmt]) -> Result<Vec<PyStmt>, TranslateError> {
+        if stmts.is_empty() { return Ok(vec![PyStmtKind::Pass.into()]) }
 
         let mut lines = Vec::with_capacity(stmts.len());
-        for stmt in stmts {
-            match &stmt.kind {
-                StmtKind::DeclareLocals { vars } => lines.extend(vars.iter().map(|x| format_compact!("{} = snap.wrap(0)", x.trans_name))),
-                StmtKind::Assign { var, value } => lines.push(format_compact!("{} = {}{}", translate_var(var), wrap(self.translate_expr(value)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::AddAssign { var, value } => lines.push(format_compact!("{} += {}{}", translate_var(var), wrap(self.translate_expr(value)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListAssign { list, index, value } => lines.push(format_compact!("{}[{} - snap.wrap(1)] = {}{}", wrap(self.translate_expr(list)?), wrap(self.translate_expr(index)?), self.translate_expr(value)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListAssignLast { list, value } => lines.push(format_compact!("{}.last = {}{}", wrap(self.translate_expr(list)?), self.translate_expr(value)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListAssignRandom { list, value } => lines.push(format_compact!("{}.rand = {}{}", wrap(self.translate_expr(list)?), self.translate_expr(value)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListInsert { list, index, value } => lines.push(format_compact!("{}.insert({}, {}){}", wrap(self.translate_expr(list)?), self.translate_expr(index)?.0, self.translate_expr(value)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListInsertLast { list, value } => lines.push(format_compact!("{}.append({}){}", wrap(self.translate_expr(list)?), wrap(self.translate_expr(value)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListInsertRandom { list, value } => lines.push(format_compact!("{}.insert_rand({}){}", wrap(self.translate_expr(list)?), self.translate_expr(value)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListRemoveLast { list } => lines.push(format_compact!("{}.pop(){}", wrap(self.translate_expr(list)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListRemove { list, index } => lines.push(format_compact!("del {}[{} - snap.wrap(1)]{}", wrap(self.translate_expr(list)?), wrap(self.translate_expr(index)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ListRemoveAll { list } => lines.push(format_compact!("{}.clear(){}", wrap(self.translate_expr(list)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Throw { error } => lines.push(format_compact!("raise RuntimeError(str({})){}", wrap(self.translate_expr(error)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Warp { stmts } => {
-                    let code = self.translate_stmts(stmts)?;
-                    lines.push(format_compact!("with NoYield():{}\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&code)));
-                }
-                StmtKind::If { condition, then } => {
-                    let condition = wrap(self.translate_expr(condition)?);
-                    let then = self.translate_stmts(then)?;
-                    lines.push(format_compact!("if {condition}:{}\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&then)));
-                }
-                StmtKind::IfElse { condition, then, otherwise } => {
-                    let condition = wrap(self.translate_expr(condition)?);
-                    let then_code = self.translate_stmts(then)?;
-                    let otherwise_code = self.translate_stmts(otherwise)?;
-
-                    match otherwise.as_slice() {
-                        [Stmt { kind: StmtKind::If { .. } | StmtKind::IfElse { .. }, .. }] => {
-                            lines.push(format_compact!("if {condition}:{}\n{}\nel{otherwise_code}", fmt_comment(stmt.info.comment.as_deref()), indent(&then_code)));
-                        }
-                        _ => {
-                            lines.push(format_compact!("if {condition}:{}\n{}\nelse:\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&then_code), indent(&otherwise_code)));
-                        }
-                    }
-                }
-                StmtKind::TryCatch { code, var, handler } => {
-                    let code = self.translate_stmts(code)?;
-                    let handler = self.translate_stmts(handler)?;
-                    lines.push(format_compact!("try:{}\n{}\nexcept Exception as {}:\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&code), var.trans_name, indent(&handler)));
-                }
-                StmtKind::InfLoop { stmts } => {
-                    let code = self.translate_stmts(stmts)?;
-                    lines.push(format_compact!("while True:{}\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&code)));
-                }
-                StmtKind::ForLoop { var, start, stop, stmts } => {
-                    let start = wrap_number(self.translate_expr(start)?, false);
-                    let stop = wrap_number(self.translate_expr(stop)?, false);
-                    let code = self.translate_stmts(stmts)?;
-                    lines.push(format_compact!("for {} in snap.sxrange({start}, {stop}):{}\n{}", var.trans_name, fmt_comment(stmt.info.comment.as_deref()), indent(&code)));
-                }
-                StmtKind::ForeachLoop { var, items, stmts } => {
-                    let items = wrap(self.translate_expr(items)?);
-                    let code = self.translate_stmts(stmts)?;
-                    lines.push(format_compact!("for {} in {items}:{}\n{}", var.trans_name, fmt_comment(stmt.info.comment.as_deref()), indent(&code)));
-                }
-                StmtKind::Repeat { times, stmts } => {
-                    let times = wrap_number(self.translate_expr(times)?, true);
-                    let code = self.translate_stmts(stmts)?;
-                    lines.push(format_compact!("for _ in range({times}):{}\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&code)));
-                }
-                StmtKind::UntilLoop { condition, stmts } => {
-                    let condition = wrap(self.translate_expr(condition)?);
-                    let code = self.translate_stmts(stmts)?;
-                    lines.push(format_compact!("while not {condition}:{}\n{}", fmt_comment(stmt.info.comment.as_deref()), indent(&code)));
-                }
-                StmtKind::SetCostume { costume } => {
-                    let costume = self.translate_expr(costume)?.0;
-                    lines.push(format_compact!("self.costume = {costume}{}", fmt_comment(stmt.info.comment.as_deref())));
-                }
-                StmtKind::NextCostume => lines.push(format_compact!("self.costume = (self.costumes.index(self.costume, -1) + 1) % len(self.costumes)")),
-                StmtKind::PlaySound { sound, blocking } => {
-                    let blocking_suffix = if *blocking { ", wait = True" } else { "" };
-                    let sound = self.translate_expr(sound)?.0;
-                    lines.push(format_compact!("self.play_sound({sound}{blocking_suffix}){}", fmt_comment(stmt.info.comment.as_deref())));
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.path.push(i);
+            let start = lines.len();
+            self.translate_stmt(stmt, &mut lines)?;
+            let source = ir::Source { block: stmt.info.location.clone(), path: self.path.clone() };
+            for line in lines[start..].iter_mut() {
+                line.source.get_or_insert_with(|| source.clone());
+            }
+            self.path.pop();
+        }
+
+        Ok(lines)
+    }
+    /// Translates the sequence of statements in a slot of the current statement, such as the body of a loop.
+    fn translate_slot(&mut self, slot: usize, stmts: &[Stmt]) -> Result<Vec<PyStmt>, TranslateError> {
+        self.path.push(slot);
+        let res = self.translate_stmts(stmts);
+        self.path.pop();
+        res
+    }
+    fn translate_stmt(&mut self, stmt: &Stmt, lines: &mut Vec<PyStmt>) -> Result<(), TranslateError> {
+        let kind = match &stmt.kind {
+            StmtKind::DeclareLocals { vars } => {
+                for var in vars {
+                    self.bind_local(&var.name, &var.trans_name, Type::Wrapped);
                 }
-                StmtKind::StopSounds => lines.push(format_compact!("{}.stop_sounds()", self.stage_name)),
+                lines.extend(vars.iter().map(|x| PyStmt::from(PyStmtKind::Assign(PyExpr::Name(x.trans_name.clone()), snap("wrap", vec![PyExpr::Num(0.0)])))));
+                return Ok(());
+            }
+            StmtKind::Assign { var, value } => {
+                let key = TypeEnv::var_key(self.owner, var);
+                let value = self.translate_expr(value)?;
+                let value = self.role.counter_init(&key, value);
+                self.role.found_types.bind_var(key, value.1.wrapped());
+                PyStmtKind::Assign(translate_var(var), wrap(value))
+            }
+            StmtKind::AddAssign { var, value } => {
+                let key = TypeEnv::var_key(self.owner, var);
+                self.role.found_types.bind_var(key.clone(), Type::Wrapped);
+                self.role.found_types.counters.insert(key);
+                PyStmtKind::AugAssign(translate_var(var), BinOp::Add, wrap(self.translate_expr(value)?))
+            }
+            StmtKind::ListAssign { list, index, value } => {
+                let target = wrap(self.translate_expr(list)?).index(self.translate_index(index)?);
+                PyStmtKind::Assign(target, self.translate_expr(value)?.0)
+            }
+            StmtKind::ListAssignLast { list, value } => PyStmtKind::Assign(wrap(self.translate_expr(list)?).attr("last"), self.translate_expr(value)?.0),
+            StmtKind::ListAssignRandom { list, value } => PyStmtKind::Assign(wrap(self.translate_expr(list)?).attr("rand"), self.translate_expr(value)?.0),
+            StmtKind::ListInsert { list, index, value } => PyStmtKind::Expr(wrap(self.translate_expr(list)?).method("insert", vec![self.translate_expr(index)?.0, self.translate_expr(value)?.0])),
+            StmtKind::ListInsertLast { list, value } => PyStmtKind::Expr(wrap(self.translate_expr(list)?).method("append", vec![wrap(self.translate_expr(value)?)])),
+            StmtKind::ListInsertRandom { list, value } => PyStmtKind::Expr(wrap(self.translate_expr(list)?).method("insert_rand", vec![self.translate_expr(value)?.0])),
+            StmtKind::ListRemoveLast { list } => PyStmtKind::Expr(wrap(self.translate_expr(list)?).method("pop", vec![])),
+            StmtKind::ListRemove { list, index } => PyStmtKind::Del(wrap(self.translate_expr(list)?).index(self.translate_index(index)?)),
+            StmtKind::ListRemoveAll { list } => PyStmtKind::Expr(wrap(self.translate_expr(list)?).method("clear", vec![])),
+            StmtKind::Throw { error } => PyStmtKind::Raise(PyExpr::name("RuntimeError").call(vec![PyExpr::name("str").call(vec![wrap(self.translate_expr(error)?)])])),
+            StmtKind::Warp { stmts } => PyStmtKind::With { context: PyExpr::name("NoYield").call(vec![]), body: self.translate_slot(0, stmts)? },
+            StmtKind::If { condition, then } => {
+                let condition = wrap(self.translate_expr(condition)?);
+                PyStmtKind::If { condition, then: self.translate_slot(0, then)?, otherwise: vec![] }
+            }
+            StmtKind::IfElse { condition, then, otherwise } => {
+                let condition = wrap(self.translate_expr(condition)?);
+                PyStmtKind::If { condition, then: self.translate_slot(0, then)?, otherwise: self.translate_slot(1, otherwise)? }
+            }
+            StmtKind::TryCatch { code, var, handler } => {
+                self.bind_local(&var.name, &var.trans_name, Type::Wrapped);
+                PyStmtKind::Try { body: self.translate_slot(0, code)?, var: var.trans_name.clone(), handler: self.translate_slot(1, handler)? }
+            }
+            StmtKind::InfLoop { stmts } => PyStmtKind::While { condition: PyExpr::Bool(true), body: self.translate_slot(0, stmts)? },
+            StmtKind::ForLoop { var, start, stop, stmts } => {
+                let start = wrap_number(self.translate_expr(start)?, false);
+                let stop = wrap_number(self.translate_expr(stop)?, false);
+                self.bind_local(&var.name, &var.trans_name, Type::Wrapped);
+                PyStmtKind::For { target: var.trans_name.clone(), iter: snap("sxrange", vec![start, stop]), body: self.translate_slot(0, stmts)? }
+            }
+            StmtKind::ForeachLoop { var, items, stmts } => {
+                let items = wrap(self.translate_expr(items)?);
+                self.bind_local(&var.name, &var.trans_name, Type::Wrapped);
+                PyStmtKind::For { target: var.trans_name.clone(), iter: items, body: self.translate_slot(0, stmts)? }
+            }
+            StmtKind::Repeat { times, stmts } => {
+                let times = wrap_number(self.translate_expr(times)?, true);
+                PyStmtKind::For { target: "_".into(), iter: PyExpr::name("range").call(vec![times]), body: self.translate_slot(0, stmts)? }
+            }
+            StmtKind::UntilLoop { condition, stmts } => {
+                let condition = PyExpr::unary(UnaryOp::Not, wrap(self.translate_expr(condition)?));
+                PyStmtKind::While { condition, body: self.translate_slot(0, stmts)? }
+            }
+            StmtKind::SetCostume { costume } => PyStmtKind::Assign(PyExpr::name("self.costume"), self.translate_expr(costume)?.0),
+            StmtKind::NextCostume => PyStmtKind::Assign(PyExpr::name("self.costume"), PyExpr::bin(BinOp::Mod, costume_index(), PyExpr::name("len").call(vec![PyExpr::name("self.costumes")]))),
+            StmtKind::PlaySound { sound, blocking } => {
+                let mut args = vec![Arg::Pos(self.translate_expr(sound)?.0)];
+                if *blocking { args.push(Arg::Kw("wait".into(), PyExpr::Bool(true))) }
+                PyStmtKind::Expr(PyExpr::name("self.play_sound").call_args(args))
+            }
+            StmtKind::StopSounds => PyStmtKind::Expr(self.stage().method("stop_sounds", vec![])),
 
-                StmtKind::SetX { value } => lines.push(format_compact!("self.x_pos = {}{}", wrap_number(self.translate_expr(value)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetY { value } => lines.push(format_compact!("self.y_pos = {}{}", wrap_number(self.translate_expr(value)?, false), fmt_comment(stmt.info.comment.as_deref()))),
+            StmtKind::SetX { value } => PyStmtKind::Assign(PyExpr::name("self.x_pos"), wrap_number(self.translate_expr(value)?, false)),
+            StmtKind::SetY { value } => PyStmtKind::Assign(PyExpr::name("self.y_pos"), wrap_number(self.translate_expr(value)?, false)),
 
-                StmtKind::ChangeX { delta } => lines.push(format_compact!("self.x_pos += {}{}", wrap_number(self.translate_expr(delta)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ChangeY { delta } => lines.push(format_compact!("self.y_pos += {}{}", wrap_number(self.translate_expr(delta)?, false), fmt_comment(stmt.info.comment.as_deref()))),
+            StmtKind::ChangeX { delta } => PyStmtKind::AugAssign(PyExpr::name("self.x_pos"), BinOp::Add, wrap_number(self.translate_expr(delta)?, false)),
+            StmtKind::ChangeY { delta } => PyStmtKind::AugAssign(PyExpr::name("self.y_pos"), BinOp::Add, wrap_number(self.translate_expr(delta)?, false)),
 
-                StmtKind::Goto { target } => match &target.kind {
-                    ExprKind::Value(Value::List(values, _)) if values.len() == 2 => lines.push(format_compact!("self.pos = ({}, {}){}", self.translate_value(&values[0])?.0, self.translate_value(&values[1])?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                    _ => lines.push(format_compact!("self.pos = {}{}", self.translate_expr(target)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                }
-                StmtKind::GotoXY { x, y } => lines.push(format_compact!("self.pos = ({}, {}){}", wrap_number(self.translate_expr(x)?, false), wrap_number(self.translate_expr(y)?, false), fmt_comment(stmt.info.comment.as_deref()))),
+            StmtKind::Goto { target } => match &target.kind {
+                ExprKind::Value(Value::List(values, _)) if values.len() == 2 => PyStmtKind::Assign(PyExpr::name("self.pos"), PyExpr::Tuple(vec![self.translate_value(&values[0])?.0, self.translate_value(&values[1])?.0])),
+                _ => PyStmtKind::Assign(PyExpr::name("self.pos"), self.translate_expr(target)?.0),
+            }
+            StmtKind::GotoXY { x, y } => PyStmtKind::Assign(PyExpr::name("self.pos"), PyExpr::Tuple(vec![wrap_number(self.translate_expr(x)?, false), wrap_number(self.translate_expr(y)?, false)])),
 
-                StmtKind::SendLocalMessage { target, msg_type, wait } => {
-                    if *wait { unimplemented!() }
-                    if target.is_some() { unimplemented!() }
+            StmtKind::SendLocalMessage { target, msg_type, wait } => {
+                if *wait || target.is_some() { return Err(TranslateError::UnsupportedStmt(Box::new(stmt.clone()))) }
 
-                    match &msg_type.kind {
-                        ExprKind::Value(Value::String(msg_type)) => lines.push(format_compact!("nb.send_message('local::{}'){}", escape(msg_type), fmt_comment(stmt.info.comment.as_deref()))),
-                        _  => lines.push(format_compact!("nb.send_message('local::' + str({})){}", self.translate_expr(msg_type)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                    }
-                }
-                StmtKind::SendNetworkMessage { target, msg_type, values } => {
-                    let kwargs_str = self.translate_kwargs(values, ", ", false)?;
-                    lines.push(format_compact!("nb.send_message('{}', {}{}){}", escape(msg_type), self.translate_expr(target)?.0, kwargs_str, fmt_comment(stmt.info.comment.as_deref())));
-                }
-                StmtKind::Say { content, duration } | StmtKind::Think { content, duration } => match duration {
-                    Some(duration) => lines.push(format_compact!("self.say({}, duration = {}){}", self.translate_expr(content)?.0, self.translate_expr(duration)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                    None => lines.push(format_compact!("self.say({}){}", self.translate_expr(content)?.0, fmt_comment(stmt.info.comment.as_deref()))),
+                let msg_type = match &msg_type.kind {
+                    ExprKind::Value(Value::String(msg_type)) => PyExpr::Str(format_compact!("local::{msg_type}")),
+                    _ => PyExpr::bin(BinOp::Add, PyExpr::str("local::"), PyExpr::name("str").call(vec![self.translate_expr(msg_type)?.0])),
+                };
+                PyStmtKind::Expr(PyExpr::name("nb.send_message").call(vec![msg_type]))
+            }
+            StmtKind::SendNetworkMessage { target, msg_type, values } => {
+                self.role.msg_types.entry(msg_type.clone()).or_insert_with(|| values.iter().map(|x| x.0.clone()).collect());
+                let mut args = vec![Arg::Pos(PyExpr::str(msg_type)), Arg::Pos(self.translate_expr(target)?.0)];
+                args.extend(self.translate_kwargs(values, false)?);
+                PyStmtKind::Expr(PyExpr::name("nb.send_message").call_args(args))
+            }
+            StmtKind::SendNetworkReply { value } => {
+                if !self.in_msg_handler { return Err(TranslateError::ReplyOutsideMessageHandler) }
+                PyStmtKind::Expr(PyExpr::name("nb.send_reply").call(vec![PyExpr::name(MSG_SENDER_PARAM), self.translate_expr(value)?.0]))
+            }
+            StmtKind::Say { content, duration } | StmtKind::Think { content, duration } => {
+                let mut args = vec![Arg::Pos(self.translate_expr(content)?.0)];
+                if let Some(duration) = duration {
+                    args.push(Arg::Kw("duration".into(), self.translate_expr(duration)?.0));
                 }
-                StmtKind::CallRpc { service, host: _, rpc, args } => lines.push(format_compact!("{}{}", self.translate_rpc(service, rpc, args)?, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::CallFn { function, args, upvars } => lines.push(format_compact!("{}{}", self.translate_fn_call(function, args, upvars)?, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::CallClosure { new_entity, closure, args } => lines.push(format_compact!("{}{}", self.translate_closure_call(new_entity.as_deref(), closure, args)?, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ChangePenSize { delta } => lines.push(format_compact!("self.pen_size += {}{}", wrap_number(self.translate_expr(delta)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetPenSize { value } => lines.push(format_compact!("self.pen_size = {}{}", wrap_number(self.translate_expr(value)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetVisible { value } => lines.push(format_compact!("self.visible = {}{}", if *value { "True" } else { "False" }, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::WaitUntil { condition } => lines.push(format_compact!("while not {}:{}\n    time.sleep(0.05)", wrap(self.translate_expr(condition)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::BounceOffEdge => lines.push(format_compact!("self.keep_on_stage(bounce = True){}", fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Sleep { seconds } => lines.push(format_compact!("time.sleep({}){}", wrap_number(self.translate_expr(seconds)?, true), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Forward { distance } => lines.push(format_compact!("self.forward({}){}", wrap_number(self.translate_expr(distance)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::TurnRight { angle } => lines.push(format_compact!("self.turn_right({}){}", wrap_number(self.translate_expr(angle)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::TurnLeft { angle } => lines.push(format_compact!("self.turn_left({}){}", wrap_number(self.translate_expr(angle)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetHeading { value } => lines.push(format_compact!("self.heading = {}{}", wrap_number(self.translate_expr(value)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Return { value } => lines.push(format_compact!("return {}{}", wrap(self.translate_expr(value)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Stamp => lines.push(format_compact!("self.stamp(){}", fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Write { content, font_size } => lines.push(format_compact!("self.write({}, size = {}){}", wrap(self.translate_expr(content)?), wrap(self.translate_expr(font_size)?), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetPenDown { value } => lines.push(format_compact!("self.drawing = {}{}", if *value { "True" } else { "False" }, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::PenClear => lines.push(format_compact!("{}.clear_drawings(){}", self.stage_name, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetPenColor { color } => lines.push(format_compact!("self.pen_color = '#{:02x}{:02x}{:02x}'{}", color.0, color.1, color.2, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ChangeSize { delta } => lines.push(format_compact!("self.scale += {} / 100{}", wrap_number(self.translate_expr(delta)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::SetSize { value } => lines.push(format_compact!("self.scale = {} / 100{}", wrap_number(self.translate_expr(value)?, false), fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Clone { target } => lines.push(format_compact!("{}.clone(){}", self.translate_expr(target)?.0, fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::Ask { prompt } => lines.push(format_compact!("{stage_name}.last_answer = snap.wrap(input({prompt})){comment}", prompt = self.translate_expr(prompt)?.0, stage_name = self.stage_name, comment = fmt_comment(stmt.info.comment.as_deref()))),
-                StmtKind::ResetTimer => lines.push(format_compact!("{}.timer = 0", self.stage_name)),
+                PyStmtKind::Expr(PyExpr::name("self.say").call_args(args))
+            }
+            StmtKind::CallRpc { service, host: _, rpc, args } => match self.async_rpcs && self.role.options.async_rpcs {
+                true => PyStmtKind::Expr(self.translate_async_rpc(service, rpc, args)?),
+                false => PyStmtKind::Expr(self.translate_rpc(service, rpc, args)?),
+            }
+            StmtKind::ForkClosure { closure, args } if self.role.options.async_rpcs => match self.translate_launched_rpc(closure, args)? {
+                Some(code) => PyStmtKind::Expr(code),
+                None => return Err(TranslateError::UnsupportedStmt(Box::new(stmt.clone()))),
+            }
+            StmtKind::UnknownBlock { name, args } if name == DYNAMIC_RPC_STMT => match args.as_slice() {
+                [service, rpc, args] => PyStmtKind::Expr(self.translate_dynamic_rpc(service, rpc, args)?),
                 _ => return Err(TranslateError::UnsupportedStmt(Box::new(stmt.clone()))),
             }
-        }
+            StmtKind::CallFn { function, args, upvars } => PyStmtKind::Expr(self.translate_fn_call(function, args, upvars)?.0),
+            StmtKind::CallClosure { new_entity, closure, args } => PyStmtKind::Expr(self.translate_closure_call(new_entity.as_deref(), closure, args)?),
+            StmtKind::ChangePenSize { delta } => PyStmtKind::AugAssign(PyExpr::name("self.pen_size"), BinOp::Add, wrap_number(self.translate_expr(delta)?, false)),
+            StmtKind::SetPenSize { value } => PyStmtKind::Assign(PyExpr::name("self.pen_size"), wrap_number(self.translate_expr(value)?, false)),
+            StmtKind::SetVisible { value } => PyStmtKind::Assign(PyExpr::name("self.visible"), PyExpr::Bool(*value)),
+            StmtKind::WaitUntil { condition } => {
+                let condition = PyExpr::unary(UnaryOp::Not, wrap(self.translate_expr(condition)?));
+                PyStmtKind::While { condition, body: vec![PyExpr::name("time.sleep").call(vec![PyExpr::Num(0.05)]).into()] }
+            }
+            StmtKind::BounceOffEdge => PyStmtKind::Expr(PyExpr::name("self.keep_on_stage").call_args(vec![Arg::Kw("bounce".into(), PyExpr::Bool(true))])),
+            StmtKind::Sleep { seconds } => PyStmtKind::Expr(PyExpr::name("time.sleep").call(vec![wrap_number(self.translate_expr(seconds)?, true)])),
+            StmtKind::Forward { distance } => PyStmtKind::Expr(PyExpr::name("self.forward").call(vec![wrap_number(self.translate_expr(distance)?, false)])),
+            StmtKind::TurnRight { angle } => PyStmtKind::Expr(PyExpr::name("self.turn_right").call(vec![wrap_number(self.translate_expr(angle)?, false)])),
+            StmtKind::TurnLeft { angle } => PyStmtKind::Expr(PyExpr::name("self.turn_left").call(vec![wrap_number(self.translate_expr(angle)?, false)])),
+            StmtKind::SetHeading { value } => PyStmtKind::Assign(PyExpr::name("self.heading"), wrap_number(self.translate_expr(value)?, false)),
+            StmtKind::Return { value } => {
+                let value = self.translate_expr(value)?;
+                if let Some(func) = &self.func {
+                    self.role.found_types.bind_func(func.clone(), value.1.wrapped());
+                }
+                PyStmtKind::Return(wrap(value))
+            }
+            StmtKind::Stamp => PyStmtKind::Expr(PyExpr::name("self.stamp").call(vec![])),
+            StmtKind::Write { content, font_size } => {
+                let args = vec![Arg::Pos(wrap(self.translate_expr(content)?)), Arg::Kw("size".into(), wrap(self.translate_expr(font_size)?))];
+                PyStmtKind::Expr(PyExpr::name("self.write").call_args(args))
+            }
+            StmtKind::SetPenDown { value } => PyStmtKind::Assign(PyExpr::name("self.drawing"), PyExpr::Bool(*value)),
+            StmtKind::PenClear => PyStmtKind::Expr(self.stage().method("clear_drawings", vec![])),
+            StmtKind::SetPenColor { color } => PyStmtKind::Assign(PyExpr::name("self.pen_color"), PyExpr::Str(format_compact!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2))),
+            StmtKind::ChangeSize { delta } => PyStmtKind::AugAssign(PyExpr::name("self.scale"), BinOp::Add, PyExpr::bin(BinOp::Div, wrap_number(self.translate_expr(delta)?, false), PyExpr::Num(100.0))),
+            StmtKind::SetSize { value } => PyStmtKind::Assign(PyExpr::name("self.scale"), PyExpr::bin(BinOp::Div, wrap_number(self.translate_expr(value)?, false), PyExpr::Num(100.0))),
+            StmtKind::Clone { target } => PyStmtKind::Expr(self.translate_expr(target)?.0.method("clone", vec![])),
+            StmtKind::Ask { prompt } => {
+                let answer = snap("wrap", vec![PyExpr::name("input").call(vec![self.translate_expr(prompt)?.0])]);
+                PyStmtKind::Assign(self.stage().attr("last_answer"), answer)
+            }
+            StmtKind::ResetTimer => PyStmtKind::Assign(self.stage().attr("timer"), PyExpr::Num(0.0)),
+            _ => return Err(TranslateError::UnsupportedStmt(Box::new(stmt.clone()))),
+        };
+        lines.push(PyStmt::from(kind).with_comment(stmt.info.comment.as_deref()));
+        Ok(())
+    }
+}
+
+/// The one-based index of the current costume, or zero if the sprite has no costume.
+fn costume_index() -> PyExpr {
+    PyExpr::bin(BinOp::Add, PyExpr::name("self.costumes").method("index", vec![PyExpr::name("self.costume"), PyExpr::Num(-1.0)]), PyExpr::Num(1.0))
+}
+/// Constructs a generator expression which converts each item of a list into a string.
+fn str_of_each(list: PyExpr) -> PyExpr {
+    PyExpr::Comp { kind: CompKind::Gen, item: Box::new(PyExpr::name("str").call(vec![PyExpr::name("_x")])), clauses: vec![("_x".into(), list)], condition: None }
+}
 
-        Ok(lines.join("\n").into())
+/// Prepends the comment on a custom block definition (if any) to its body as a docstring.
+fn with_docstring(comment: Option<&str>, body: Vec<PyStmt>) -> Vec<PyStmt> {
+    match comment {
+        Some(comment) => iter::once(PyStmtKind::Docstring(comment.into()).into()).chain(body).collect(),
+        None => body,
+    }
+}
+
+/// Describes a custom block for the PyBlox block palette, using the details of its definition which were recovered from the project XML.
+fn palette_block(func: &Function, def: Option<&comments::BlockDef>, entity: Option<&str>) -> project::Block {
+    let def = def.cloned().unwrap_or_default();
+    let mut inputs = def.inputs.iter();
+    let mut spec = CompactString::default();
+    for ch in func.name.chars() {
+        match ch {
+            '\t' => spec += match inputs.next() { // the parser marks each parameter with a tab
+                Some(slot) if !slot.is_empty() => slot,
+                _ => "%s",
+            },
+            _ => spec.push(ch),
+        }
+    }
+    let ty = match def.ty.as_str() {
+        "command" => project::BlockType::Command,
+        "reporter" => project::BlockType::Reporter,
+        "predicate" => project::BlockType::Predicate,
+        _ => if func.returns { project::BlockType::Reporter } else { project::BlockType::Command },
+    };
+    project::Block {
+        name: names::block_spec(func),
+        spec,
+        category: if def.category.is_empty() { "other".into() } else { def.category },
+        ty,
+        entity: entity.map(Into::into),
+        function: func.trans_name.clone(),
     }
 }
 
@@ -568,13 +1059,53 @@ impl RoleInfo {
     }
 }
 
+/// The printed code of a script, along with the name of its function and the lines printed for each of its blocks.
+struct ScriptCode {
+    name: CompactString,
+    code: CompactString,
+    spans: Vec<ir::Span>,
+}
+
+/// Builds the source map of an editor, which maps ranges of its lines to the blocks they were translated from.
+struct SourceMap<'a> {
+    role: &'a str,
+    entries: Vec<project::SourceMapEntry>,
+}
+impl SourceMap<'_> {
+    /// Adds the spans of a script whose code starts after the given number of lines.
+    /// The script is named by its function for hat blocks, or by its spec for custom blocks.
+    fn add(&mut self, entity: Option<&str>, script: &str, offset: usize, code: &str, spans: &[ir::Span]) {
+        let lines: Vec<_> = code.lines().collect();
+        self.entries.extend(spans.iter().map(|span| project::SourceMapEntry {
+            lines: (offset + span.start + 1, offset + span.end),
+            role: self.role.into(),
+            entity: entity.map(Into::into),
+            script: script.into(),
+            block: span.source.block.clone(),
+            path: span.source.path.clone(),
+            hash: span.source.path.is_empty().then(|| chunks::hash_lines(&lines[span.start..span.end])),
+        }));
+    }
+}
+
+/// Counts the lines which precede the next code appended to some content.
+fn line_offset(content: &str) -> usize {
+    content.matches('\n').count()
+}
+
+type ImageInfo = Rc<(Vec<u8>, Option<(f64, f64)>, CompactString)>;
+type AudioInfo = Rc<(Vec<u8>, CompactString)>;
+
 struct SpriteInfo {
     name: CompactString,
-    scripts: Vec<CompactString>,
-    fields: Vec<(CompactString, CompactString)>,
+    orig_name: CompactString,
+    scripts: Vec<ScriptCode>,
+    /// Every method and field name in the sprite, which script names must not collide with.
+    used_names: BTreeSet<CompactString>,
+    fields: Vec<(CompactString, PyExpr)>,
     funcs: Vec<Function>,
-    costumes: Vec<(CompactString, Rc<(Vec<u8>, Option<(f64, f64)>, CompactString)>)>,
-    sounds: Vec<(CompactString, Rc<(Vec<u8>, CompactString)>)>,
+    costumes: Vec<(CompactString, ImageInfo)>,
+    sounds: Vec<(CompactString, AudioInfo)>,
 
     active_costume: Option<usize>,
     visible: bool,
@@ -587,7 +1118,9 @@ impl SpriteInfo {
     fn new(src: &Entity) -> Self {
         Self {
             name: src.trans_name.clone(),
+            orig_name: src.name.clone(),
             scripts: vec![],
+            used_names: src.funcs.iter().map(|x| x.trans_name.clone()).chain(src.fields.iter().map(|x| x.def.trans_name.clone())).collect(),
             fields: vec![],
             funcs: src.funcs.clone(),
             costumes: vec![],
@@ -601,55 +1134,112 @@ impl SpriteInfo {
             scale: src.scale,
         }
     }
-    fn translate_hat(&mut self, hat: &Hat, stage_name: &str) -> Result<CompactString, TranslateError> {
-        Ok(match &hat.kind {
-            HatKind::OnFlag => format_compact!("@onstart(){}\ndef my_onstart_{}(self):\n", fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
-            HatKind::OnClone => format_compact!("@onstart('clone'){}\ndef my_onstart_{}(self):\n", fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
-            HatKind::OnKey { key } => format_compact!("@onkey('{}'){}\ndef my_onkey_{}(self):\n", key, fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
-            HatKind::MouseDown => format_compact!("@onmouse('down'){}\ndef my_onmouse_{}(self, x, y):\n", fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
-            HatKind::MouseUp => format_compact!("@onmouse('up'){}\ndef my_onmouse_{}(self, x, y):\n", fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
-            HatKind::ScrollDown => format_compact!("@onmouse('scroll-down'){}\ndef my_onmouse_{}(self, x, y):\n", fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
-            HatKind::ScrollUp => format_compact!("@onmouse('scroll-up'){}\ndef my_onmouse_{}(self, x, y):\n", fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
+    /// Gets a name for a script based on its hat block, which is numbered from 2 if the name is already in use.
+    fn script_name(&mut self, base: &str, suffix: Option<&str>, options: &TranslateOptions) -> CompactString {
+        let base = match suffix.and_then(|x| names::script_suffix(x, options.unicode_idents)) {
+            Some(suffix) => format_compact!("{base}_{suffix}"),
+            None => base.into(),
+        };
+        let res = iter::once(base.clone()).chain((2..).map(|i| format_compact!("{base}_{i}"))).find(|x| !self.used_names.contains(x)).unwrap();
+        self.used_names.insert(res.clone());
+        res
+    }
+    /// Translates a script's hat block into the function definition(s) which run the script.
+    /// The last statement is always a function definition whose body is a preamble (possibly empty) for the script's code.
+    fn translate_hat(&mut self, hat: &Hat, role: &mut RoleContext) -> Result<Vec<PyStmt>, TranslateError> {
+        fn def(decorator: PyExpr, name: CompactString, params: &[&str], body: Vec<PyStmt>) -> PyStmt {
+            let params = iter::once("self").chain(params.iter().copied()).map(Param::new).collect();
+            PyStmtKind::FunctionDef(Box::new(FunctionDef::new(vec![decorator], name, params, body))).into()
+        }
+        fn decorator(name: &str, args: Vec<PyExpr>) -> PyExpr {
+            PyExpr::name(name).call(args)
+        }
+
+        let mut res = match &hat.kind {
+            HatKind::OnFlag => vec![def(decorator("onstart", vec![]), self.script_name("on_start", None, role.options), &[], vec![])],
+            HatKind::OnClone => vec![def(decorator("onstart", vec![PyExpr::str("clone")]), self.script_name("on_clone", None, role.options), &[], vec![])],
+            HatKind::OnKey { key } => vec![def(decorator("onkey", vec![PyExpr::str(key)]), self.script_name("on_key", Some(key), role.options), &[], vec![])],
+            HatKind::MouseDown => vec![def(decorator("onmouse", vec![PyExpr::str("down")]), self.script_name("on_mouse_down", None, role.options), &["_x", "_y"], vec![])],
+            HatKind::MouseUp => vec![def(decorator("onmouse", vec![PyExpr::str("up")]), self.script_name("on_mouse_up", None, role.options), &["_x", "_y"], vec![])],
+            HatKind::ScrollDown => vec![def(decorator("onmouse", vec![PyExpr::str("scroll-down")]), self.script_name("on_scroll_down", None, role.options), &["_x", "_y"], vec![])],
+            HatKind::ScrollUp => vec![def(decorator("onmouse", vec![PyExpr::str("scroll-up")]), self.script_name("on_scroll_up", None, role.options), &["_x", "_y"], vec![])],
             HatKind::When { condition } => {
-                format_compact!(r#"@onstart(){comment}
-def my_onstart{idx}(self):
-    while True:
-        try:
-            time.sleep(0.05)
-            if {condition}:
-                self.my_oncondition{idx}()
-        except Exception as e:
-            import traceback, sys
-            print(traceback.format_exc(), file = sys.stderr)
-def my_oncondition{idx}(self):
-"#,
-                comment = fmt_comment(hat.info.comment.as_deref()),
-                idx = self.scripts.len() + 1,
-                condition = wrap(ScriptInfo::new(stage_name).translate_expr(condition)?))
+                let handler = self.script_name("on_condition", None, role.options);
+                let watcher = self.script_name(&handler.replacen("on_", "watch_", 1), None, role.options);
+                let condition = wrap(ScriptInfo::new(role, &self.name).translate_expr(condition)?);
+                let poll = vec![
+                    PyExpr::name("time.sleep").call(vec![PyExpr::Num(0.05)]).into(),
+                    PyStmtKind::If { condition, then: vec![PyExpr::name("self").method(&handler, vec![]).into()], otherwise: vec![] }.into(),
+                ];
+                let report = vec![
+                    PyStmtKind::Import(vec!["traceback".into(), "sys".into()]).into(),
+                    PyExpr::name("print").call_args(vec![Arg::Pos(PyExpr::name("traceback.format_exc").call(vec![])), Arg::Kw("file".into(), PyExpr::name("sys.stderr"))]).into(),
+                ];
+                let body = vec![PyStmtKind::While { condition: PyExpr::Bool(true), body: vec![PyStmtKind::Try { body: poll, var: "_e".into(), handler: report }.into()] }.into()];
+                vec![
+                    def(decorator("onstart", vec![]), watcher, &[], body),
+                    PyStmtKind::FunctionDef(Box::new(FunctionDef::new(vec![], handler, vec![Param::new("self")], vec![]))).into(),
+                ]
             }
             HatKind::LocalMessage { msg_type } => match msg_type {
-                Some(msg_type) => format_compact!("@nb.on_message('local::{}'){}\ndef my_on_message_{}(self):\n", escape(msg_type), fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1),
+                Some(msg_type) => vec![def(decorator("nb.on_message", vec![PyExpr::Str(format_compact!("local::{msg_type}"))]), self.script_name("on_local_message", Some(msg_type), role.options), &[], vec![])],
                 None => return Err(TranslateError::AnyMessage),
             }
             HatKind::NetworkMessage { msg_type, fields } => {
-                let mut res = format_compact!("@nb.on_message('{}'){}\ndef my_on_message_{}(self, **kwargs):\n", escape(msg_type), fmt_comment(hat.info.comment.as_deref()), self.scripts.len() + 1);
+                role.msg_types.entry(msg_type.clone()).or_insert_with(|| fields.iter().map(|x| x.name.clone()).collect());
                 for field in fields {
-                    writeln!(&mut res, "    {} = snap.wrap(kwargs['{}'])", field.trans_name, escape(&field.name)).unwrap();
+                    role.found_types.bind_var(format_compact!("{}.{}", self.name, field.trans_name), Type::Wrapped);
+                    role.names.entry(self.name.clone()).or_default().locals.insert(field.name.clone(), field.trans_name.clone());
                 }
-                if !fields.is_empty() { res.push('\n') }
-                res
+                let mut preamble: Vec<PyStmt> = fields.iter().map(|field| PyStmtKind::Assign(PyExpr::Name(field.trans_name.clone()), snap("wrap", vec![PyExpr::name(MSG_FIELDS_PARAM).index(PyExpr::Str(field.name.clone()))])).into()).collect();
+                if !fields.is_empty() { preamble.push(PyStmtKind::Blank.into()) }
+                let mut handler = def(decorator("nb.on_message", vec![PyExpr::str(msg_type)]), self.script_name("on_message", Some(msg_type), role.options), &[], preamble);
+                if let PyStmtKind::FunctionDef(def) = &mut handler.kind {
+                    def.params.push(Param { name: MSG_SENDER_PARAM.into(), default: Some(PyExpr::None) });
+                    def.positional_only = def.params.len(); // message fields with the same name as the sender go in the fields instead
+                    def.kwargs = Some(MSG_FIELDS_PARAM.into());
+                }
+                vec![handler]
             }
             _ => return Err(TranslateError::UnsupportedHat(Box::new(hat.clone()))),
-        })
+        };
+        res[0].comment = hat.info.comment.clone().filter(|x| !is_async_marker(x));
+        let source = ir::Source { block: hat.info.location.clone(), path: vec![] };
+        Ok(res.into_iter().map(|x| x.with_source(source.clone())).collect())
     }
 }
 
 /// Translates NetsBlox project XML into PyBlox project JSON
 ///
 /// On success, returns the project name and project json content as a tuple.
+/// Alongside its PyBlox content, each role has a `names` object which maps the original names of its variables, custom blocks, sprites, costumes, and sounds to their python names.
+/// Each editor also has a `source_map` list, whose entries give a range of its lines (one-based and inclusive) along with the block they were translated from:
+/// its `role`, owning `entity` (or null for global blocks), `script` (the function name of a hat block script, or the spec of a custom block),
+/// collaboration id (`block`), and `path` of statement and slot indices from the top of the script (empty for the hat block or custom block definition itself,
+/// whose entry also has a `hash` of the generated function so that [`merge()`] can tell if it was edited).
+/// Editors with other translated top-level statements (such as global variables) also have a `hashes` object for the same purpose.
+/// The `blocks` list of each role holds its custom blocks (see [`project::Block`]), so that they can still be used from the PyBlox block palette.
 pub fn translate(source: &str) -> Result<(CompactString, CompactString), TranslateError> {
+    translate_with_options(source, &TranslateOptions::default())
+}
+
+/// Equivalent to [`translate`], but with custom translation options.
+pub fn translate_with_options(source: &str, options: &TranslateOptions) -> Result<(CompactString, CompactString), TranslateError> {
+    let (name, project) = translate_project(source, options)?;
+    Ok((name, serde_json::to_string(&project).unwrap().into()))
+}
+
+/// Equivalent to [`translate_with_options`], but returns the PyBlox project as a [`project::Project`] rather than JSON.
+/// This can be inspected or modified before serializing it with `serde_json`.
+pub fn translate_project(source: &str, options: &TranslateOptions) -> Result<(CompactString, project::Project), TranslateError> {
+    let project = parse(source, options)?;
+    Ok((project.name.clone(), translate_ast(&project, source, options)?))
+}
+
+/// Parses NetsBlox project XML, naming everything the way the translation expects.
+fn parse(source: &str, options: &TranslateOptions) -> Result<Project, TranslateError> {
     let parser = Parser {
-        name_transformer: Box::new(netsblox_ast::util::c_ident),
+        name_transformer: Box::new(names::unique_names(if options.unicode_idents { names::py_unicode_ident } else { names::py_ident })),
         autofill_generator: Box::new(|x| Ok(format_compact!("_{x}"))),
         omit_nonhat_scripts: true, // we don't need dangling blocks of code since they can't do anything
         expr_replacements: vec![],
@@ -657,172 +1247,283 @@ pub fn translate(source: &str) -> Result<(CompactString, CompactString), Transla
     };
     let project = parser.parse(source)?;
     if project.roles.is_empty() { return Err(TranslateError::NoRoles) }
+    Ok(project)
+}
+
+/// Translates a parsed project, whose XML source is needed to find the comments on its blocks.
+fn translate_ast(project: &Project, source: &str, options: &TranslateOptions) -> Result<project::Project, TranslateError> {
+    let role_comments = comments::collect(source);
+    let no_comments = comments::RoleComments::default();
 
     let mut roles = vec![];
-    for role in project.roles.iter() {
-        let mut role_info = RoleInfo::new(role.name.clone());
-        let mut stage_name = None;
+    for (i, role) in project.roles.iter().enumerate() {
+        let comments = role_comments.get(i).unwrap_or(&no_comments);
+        // the first pass only infers the types of variables and custom blocks, which the second pass uses to generate the final code
+        let (_, types) = translate_role(role, comments, options, &TypeEnv::default())?;
+        roles.push(translate_role(role, comments, options, &types)?.0);
+    }
 
-        for sprite in role.entities.iter() {
-            let mut sprite_info = SpriteInfo::new(sprite);
-            if stage_name.is_none() {
-                stage_name = Some(sprite_info.name.clone());
-            }
+    Ok(project::Project { roles })
+}
 
-            for costume in sprite.costumes.iter() {
-                let info = match &costume.init {
-                    Value::Image(x) => x.clone(),
-                    _ => panic!(), // the parser lib would never do this
-                };
-                sprite_info.costumes.push((costume.def.trans_name.clone(), info));
-            }
-            for sound in sprite.sounds.iter() {
-                let info = match &sound.init {
-                    Value::Audio(x) => x.clone(),
-                    _ => panic!(), // the parser lib would never do this
-                };
-                sprite_info.sounds.push((sound.def.trans_name.clone(), info));
-            }
-            for field in sprite.fields.iter() {
-                let value = wrap(ScriptInfo::new(stage_name.as_deref().unwrap()).translate_value(&field.init)?);
-                sprite_info.fields.push((field.def.trans_name.clone(), value));
-            }
-            for script in sprite.scripts.iter() {
-                let func_def = match script.hat.as_ref() {
-                    Some(x) => sprite_info.translate_hat(x, stage_name.as_deref().unwrap())?,
-                    None => continue, // dangling blocks of code need not be translated
-                };
-                let body = ScriptInfo::new(stage_name.as_deref().unwrap()).translate_stmts(&script.stmts)?;
-                let res = format_compact!("{}{}", func_def, indent(&body));
-                sprite_info.scripts.push(res);
+/// Translates NetsBlox project XML and merges it into an existing PyBlox project (in JSON), which may have been edited by hand.
+///
+/// Editors, functions, globals, and assets which were translated from blocks are replaced by their new translations,
+/// while hand-written code is kept. See the [`merge`](mod@merge) module for details.
+pub fn merge(old: &str, source: &str, options: &TranslateOptions) -> Result<merge::MergedProject, merge::MergeError> {
+    let old = serde_json::from_str(old)?;
+    let (name, new) = translate_project(source, options)?;
+    let (project, conflicts) = merge::merge_projects(old, new);
+    Ok(merge::MergedProject { name, project, conflicts })
+}
+
+/// Translates NetsBlox project XML into a static HTML report, which shows the blocks of every script as Snap-style pseudo code next to the generated python code.
+///
+/// Scripts and custom blocks which cannot be translated are shown with the reason why (and the offending block highlighted) rather than failing the whole report,
+/// and warning comments in the generated code are highlighted. Costumes are embedded in the report, so it is a single self-contained file.
+pub fn report(source: &str, options: &TranslateOptions) -> Result<String, TranslateError> {
+    let project = parse(source, options)?;
+    Ok(report::render(&project, source, options))
+}
+
+/// Translates a single role into PyBlox JSON, using (and returning) the inferred types of its variables and custom blocks.
+fn translate_role(role: &Role, comments: &comments::RoleComments, options: &TranslateOptions, types: &TypeEnv) -> Result<(project::Role, TypeEnv), TranslateError> {
+    let mut role_info = RoleInfo::new(role.name.clone());
+    let mut ctx = RoleContext {
+        stage_name: role.entities[0].trans_name.clone(),
+        options,
+        msg_types: comments.msg_types().iter().filter(|x| !is_default_msg_type(x)).cloned().collect(), // declared types take precedence over the fields used by scripts
+        types,
+        found_types: TypeEnv::default(),
+        names: BTreeMap::new(),
+        modules: BTreeSet::new(),
+    };
+
+    let global_names = ctx.names.entry(CompactString::default()).or_default();
+    global_names.vars.extend(role.globals.iter().map(|x| (x.def.name.clone(), x.def.trans_name.clone())));
+    global_names.blocks.extend(role.funcs.iter().map(|x| (names::block_spec(x), x.trans_name.clone())));
+    for sprite in role.entities.iter() {
+        let sprite_names = ctx.names.entry(sprite.trans_name.clone()).or_default();
+        sprite_names.vars.extend(sprite.fields.iter().map(|x| (x.def.name.clone(), x.def.trans_name.clone())));
+        sprite_names.blocks.extend(sprite.funcs.iter().map(|x| (names::block_spec(x), x.trans_name.clone())));
+        sprite_names.costumes.extend(sprite.costumes.iter().map(|x| (x.def.name.clone(), format_compact!("{}_cst_{}", sprite.trans_name, x.def.trans_name))));
+        sprite_names.sounds.extend(sprite.sounds.iter().map(|x| (x.def.name.clone(), format_compact!("{}_snd_{}", sprite.trans_name, x.def.trans_name))));
+    }
+
+    for sprite in role.entities.iter() {
+        let mut sprite_info = SpriteInfo::new(sprite);
+
+        for costume in sprite.costumes.iter() {
+            let info = match &costume.init {
+                Value::Image(x) => x.clone(),
+                _ => panic!(), // the parser lib would never do this
+            };
+            sprite_info.costumes.push((costume.def.trans_name.clone(), info));
+        }
+        for sound in sprite.sounds.iter() {
+            let info = match &sound.init {
+                Value::Audio(x) => x.clone(),
+                _ => panic!(), // the parser lib would never do this
+            };
+            sprite_info.sounds.push((sound.def.trans_name.clone(), info));
+        }
+        for field in sprite.fields.iter() {
+            let key = format_compact!("{}.self.{}", sprite_info.name, field.def.trans_name);
+            let value = ScriptInfo::new(&mut ctx, &sprite_info.name).translate_value(&field.init)?;
+            let value = ctx.counter_init(&key, value);
+            ctx.found_types.bind_var(key, value.1.wrapped());
+            sprite_info.fields.push((field.def.trans_name.clone(), wrap(value)));
+        }
+        for script in sprite.scripts.iter() {
+            let mut func_defs = match script.hat.as_ref() {
+                Some(x) => sprite_info.translate_hat(x, &mut ctx)?,
+                None => continue, // dangling blocks of code need not be translated
+            };
+            let mut script_info = ScriptInfo::new(&mut ctx, &sprite_info.name);
+            script_info.in_msg_handler = matches!(script.hat.as_deref().map(|x| &x.kind), Some(HatKind::NetworkMessage { .. }));
+            script_info.async_rpcs = script.hat.as_deref().and_then(|x| x.info.comment.as_deref()).is_some_and(is_async_marker);
+            let body = script_info.translate_stmts(&script.stmts)?;
+            let mut name = CompactString::default();
+            if let Some(PyStmt { kind: PyStmtKind::FunctionDef(def), .. }) = func_defs.last_mut() {
+                def.body.extend(body);
+                name = def.name.clone();
             }
-            role_info.sprites.push(sprite_info);
+            let (code, spans) = ctx.emit_mapped(func_defs);
+            sprite_info.scripts.push(ScriptCode { name, code, spans });
         }
-        let stage_name = &role_info.sprites[0].name;
+        role_info.sprites.push(sprite_info);
+    }
 
-        let mut editors = vec![];
+    let mut editors = vec![];
 
+    for (i, sprite) in role_info.sprites.iter().enumerate() {
         let mut content = String::new();
-        content += "from netsblox import snap\n\n";
-        for global in role.globals.iter() {
-            let value = wrap(ScriptInfo::new(stage_name).translate_value(&global.init)?);
-            writeln!(&mut content, "{} = {}", global.def.trans_name, value).unwrap();
-        }
-        if !role.globals.is_empty() { content.push('\n') }
-        for func in role.funcs.iter() {
-            let params = func.params.iter().map(|v| v.trans_name.as_str());
-            let code = ScriptInfo::new(stage_name).translate_stmts(&func.stmts)?;
-            write!(&mut content, "def {}({}):\n{}\n\n", func.trans_name, Punctuated(params, ", "), indent(&code)).unwrap();
+        let mut source_map = SourceMap { role: &role_info.name, entries: vec![] };
+
+        for (field, value) in sprite.fields.iter() {
+            writeln!(&mut content, "{}", ctx.emit(vec![PyStmtKind::Assign(PyExpr::Name(field.clone()), value.clone()).into()])).unwrap();
         }
-        editors.push(json!({
-            "type": "globals",
-            "name": "globals",
-            "value": content,
-        }));
+        if !sprite.fields.is_empty() { content.push('\n'); }
 
-        for (i, sprite) in role_info.sprites.iter().enumerate() {
-            let mut content = String::new();
+        if i == 0 { // don't generate these for sprites
+            writeln!(&mut content, "{}", ctx.emit(vec![PyStmtKind::Assign(PyExpr::name("last_answer"), snap("wrap", vec![PyExpr::str("")])).into()])).unwrap();
+            content.push('\n');
+        }
 
-            for (field, value) in sprite.fields.iter() {
-                writeln!(&mut content, "{} = {}", field, value).unwrap();
-            }
-            if !sprite.fields.is_empty() { content.push('\n'); }
+        content += "def __init__(self):\n";
+        if i != 0 { // don't generate these for stage
+            writeln!(&mut content, "    self.pos = ({}, {})", sprite.pos.0, sprite.pos.1).unwrap();
+            writeln!(&mut content, "    self.heading = {}", sprite.heading).unwrap();
+            writeln!(&mut content, "    self.pen_color = ({}, {}, {})", sprite.color.0, sprite.color.1, sprite.color.2).unwrap();
+            writeln!(&mut content, "    self.scale = {}", sprite.scale).unwrap();
+            writeln!(&mut content, "    self.visible = {}", if sprite.visible { "True" } else { "False" }).unwrap();
 
-            if i == 0 { // don't generate these for sprites
-                content += "last_answer = snap.wrap('')\n";
+            if !sprite.sounds.is_empty() {
                 content.push('\n');
             }
-
-            content += "def __init__(self):\n";
-            if i != 0 { // don't generate these for stage
-                writeln!(&mut content, "    self.pos = ({}, {})", sprite.pos.0, sprite.pos.1).unwrap();
-                writeln!(&mut content, "    self.heading = {}", sprite.heading).unwrap();
-                writeln!(&mut content, "    self.pen_color = ({}, {}, {})", sprite.color.0, sprite.color.1, sprite.color.2).unwrap();
-                writeln!(&mut content, "    self.scale = {}", sprite.scale).unwrap();
-                writeln!(&mut content, "    self.visible = {}", if sprite.visible { "True" } else { "False" }).unwrap();
-
-                if !sprite.sounds.is_empty() {
-                    content.push('\n');
-                }
-                for (trans_name, info) in sprite.sounds.iter() {
-                    writeln!(&mut content, "    self.sounds.add('{}', sounds.{}_snd_{})", escape(&info.1), sprite.name, trans_name).unwrap();
-                }
-
-                if !sprite.costumes.is_empty() {
-                    content.push('\n');
-                }
-                for (trans_name, info) in sprite.costumes.iter() {
-                    writeln!(&mut content, "    self.costumes.add('{}', images.{}_cst_{})", escape(&info.2), sprite.name, trans_name).unwrap();
-                }
+            for (trans_name, info) in sprite.sounds.iter() {
+                writeln!(&mut content, "    self.sounds.add('{}', sounds.{}_snd_{})", escape(&info.1), sprite.name, trans_name).unwrap();
             }
-            if !sprite.sounds.is_empty() || !sprite.costumes.is_empty() {
+
+            if !sprite.costumes.is_empty() {
                 content.push('\n');
             }
-            match sprite.active_costume {
-                Some(idx) => writeln!(&mut content, "    self.costume = '{}'", escape(&sprite.costumes[idx].1.2)).unwrap(),
-                None => content += "    self.costume = None\n",
+            for (trans_name, info) in sprite.costumes.iter() {
+                writeln!(&mut content, "    self.costumes.add('{}', images.{}_cst_{})", escape(&info.2), sprite.name, trans_name).unwrap();
             }
+        }
+        if !sprite.sounds.is_empty() || !sprite.costumes.is_empty() {
             content.push('\n');
-
-            for func in sprite.funcs.iter() {
-                let params = iter::once("self").chain(func.params.iter().map(|v| v.trans_name.as_str()));
-                let code = ScriptInfo::new(stage_name).translate_stmts(&func.stmts)?;
-                write!(&mut content, "def {}({}):\n{}\n\n", func.trans_name, Punctuated(params, ", "), indent(&code)).unwrap();
-            }
-
-            for script in sprite.scripts.iter() {
-                content += script;
-                content += "\n\n";
+        }
+        match sprite.active_costume {
+            Some(idx) => writeln!(&mut content, "    self.costume = '{}'", escape(&sprite.costumes[idx].1.2)).unwrap(),
+            None => content += "    self.costume = None\n",
+        }
+        content.push('\n');
+
+        for func in sprite.funcs.iter() {
+            let params = iter::once("self").chain(func.params.iter().map(|v| v.trans_name.as_str())).map(Param::new).collect();
+            let mut script_info = ScriptInfo::new(&mut ctx, &sprite.name);
+            let key = TypeEnv::func_key(&sprite.name, FnLocation::Method, &func.trans_name);
+            script_info.func = Some(key.clone());
+            for param in func.params.iter() {
+                script_info.bind_local(&param.name, &param.trans_name, Type::Wrapped);
             }
+            let body = with_docstring(comments.func(Some(&sprite.orig_name), func), script_info.translate_stmts(&func.stmts)?);
+            if !always_returns(&func.stmts) { ctx.found_types.bind_func(key, Type::Unknown) } // falling off the end returns None
+            let def = PyStmtKind::FunctionDef(Box::new(FunctionDef::new(vec![], func.trans_name.clone(), params, body)));
+            let (code, spans) = ctx.emit_mapped(vec![PyStmt::from(def).with_source(ir::Source { block: None, path: vec![] })]);
+            source_map.add(Some(&sprite.orig_name), &names::block_spec(func), line_offset(&content), &code, &spans);
+            write!(&mut content, "{code}\n\n").unwrap();
+        }
 
-            editors.push(json!({
-                "type": if i == 0 { "stage" } else { "sprite" },
-                "name": sprite.name,
-                "value": content,
-            }));
+        for script in sprite.scripts.iter() {
+            source_map.add(Some(&sprite.orig_name), &script.name, line_offset(&content), &script.code, &script.spans);
+            content += &script.code;
+            content += "\n\n";
+        }
+        for comment in comments.free(&sprite.orig_name) {
+            content += &ir::print_comment(comment);
+            content += "\n\n";
         }
 
-        let mut images = serde_json::Map::new();
-        for sprite in role_info.sprites.iter() {
-            for (costume, info) in sprite.costumes.iter() {
-                let center = match info.1 {
-                    Some(ui_center) => match image::load_from_memory(&info.0) {
-                        Ok(img) => (ui_center.0 - img.width() as f64 / 2.0, -(ui_center.1 - img.height() as f64 / 2.0)),
-                        Err(_) => return Err(TranslateError::UnknownImageFormat),
-                    }
-                    None => (0.0, 0.0),
-                };
-                images.insert(format!("{}_cst_{}", sprite.name, costume), json!({
-                    "img": base64::engine::general_purpose::STANDARD.encode(info.0.as_slice()),
-                    "center": center,
-                }));
-            }
+        editors.push(project::Editor {
+            ty: if i == 0 { project::EditorType::Stage } else { project::EditorType::Sprite },
+            name: sprite.name.clone(),
+            hashes: chunks::statement_hashes(&content),
+            value: content,
+            source_map: source_map.entries,
+        });
+    }
+
+    // the globals editor goes first, but is generated last so that it can declare every message type used by the role
+    let mut funcs = String::new();
+    let mut func_spans = vec![];
+    for func in role.funcs.iter() {
+        let params = func.params.iter().map(|v| Param::new(&v.trans_name)).collect();
+        let mut script_info = ScriptInfo::new(&mut ctx, "");
+        let key = TypeEnv::func_key("", FnLocation::Global, &func.trans_name);
+        script_info.func = Some(key.clone());
+        for param in func.params.iter() {
+            script_info.bind_local(&param.name, &param.trans_name, Type::Wrapped);
         }
+        let body = with_docstring(comments.func(None, func), script_info.translate_stmts(&func.stmts)?);
+        if !always_returns(&func.stmts) { ctx.found_types.bind_func(key, Type::Unknown) } // falling off the end returns None
+        let def = PyStmtKind::FunctionDef(Box::new(FunctionDef::new(vec![], func.trans_name.clone(), params, body)));
+        let (code, spans) = ctx.emit_mapped(vec![PyStmt::from(def).with_source(ir::Source { block: None, path: vec![] })]);
+        func_spans.push((names::block_spec(func), line_offset(&funcs), code.clone(), spans));
+        write!(&mut funcs, "{code}\n\n").unwrap();
+    }
 
-        let mut sounds = serde_json::Map::new();
-        for sprite in role_info.sprites.iter() {
-            for (sound, info) in sprite.sounds.iter() {
-                sounds.insert(format!("{}_snd_{}", sprite.name, sound), json!({
-                    "snd": base64::engine::general_purpose::STANDARD.encode(info.0.as_slice()),
-                }));
-            }
+    let mut content = String::new();
+    content += "from netsblox import snap\n\n";
+    for global in role.globals.iter() {
+        let key = format_compact!("globals.{}", global.def.trans_name);
+        let value = ScriptInfo::new(&mut ctx, "").translate_value(&global.init)?;
+        let value = ctx.counter_init(&key, value);
+        ctx.found_types.bind_var(key, value.1.wrapped());
+        writeln!(&mut content, "{}", ctx.emit(vec![PyStmtKind::Assign(PyExpr::Name(global.def.trans_name.clone()), wrap(value)).into()])).unwrap();
+    }
+    if !role.globals.is_empty() { content.push('\n') }
+    let msg_types: Vec<PyStmt> = ctx.msg_types.iter().map(|(msg_type, fields)| {
+        PyStmtKind::Expr(PyExpr::name("nb.message_type").call(vec![PyExpr::Str(msg_type.clone()), PyExpr::List(fields.iter().map(|x| PyExpr::Str(x.clone())).collect())])).into()
+    }).collect();
+    if !msg_types.is_empty() { writeln!(&mut content, "{}\n", ctx.emit(msg_types)).unwrap() }
+    let mut source_map = SourceMap { role: &role_info.name, entries: vec![] };
+    for (spec, offset, code, spans) in func_spans.iter() {
+        source_map.add(None, spec, line_offset(&content) + offset, code, spans);
+    }
+    content += &funcs;
+    editors.insert(0, project::Editor {
+        ty: project::EditorType::Globals,
+        name: "globals".into(),
+        hashes: chunks::statement_hashes(&content),
+        value: content,
+        source_map: source_map.entries,
+    });
+
+    let mut images = BTreeMap::new();
+    for sprite in role_info.sprites.iter() {
+        for (costume, info) in sprite.costumes.iter() {
+            let center = match info.1 {
+                Some(ui_center) => match image::load_from_memory(&info.0) {
+                    Ok(img) => (ui_center.0 - img.width() as f64 / 2.0, -(ui_center.1 - img.height() as f64 / 2.0)),
+                    Err(_) => return Err(TranslateError::UnknownImageFormat),
+                }
+                None => (0.0, 0.0),
+            };
+            images.insert(format_compact!("{}_cst_{}", sprite.name, costume), project::Image { img: info.0.clone(), center });
         }
+    }
 
-        roles.push(json!({
-            "name": role_info.name,
-            "stage_size": role.stage_size,
-            "block_sources": [ "netsblox://assets/default-blocks.json" ],
-            "blocks": [],
-            "imports": ["time", "math", "random"],
-            "editors": editors,
-            "images": images,
-            "sounds": sounds,
-        }));
+    let mut sounds = BTreeMap::new();
+    for sprite in role_info.sprites.iter() {
+        for (sound, info) in sprite.sounds.iter() {
+            sounds.insert(format_compact!("{}_snd_{}", sprite.name, sound), project::Sound { snd: info.0.clone() });
+        }
     }
 
-    let res = json!({
-        "roles": roles,
-    });
+    let names = project::Names {
+        globals: ctx.names[""].to_global_names(),
+        sprites: role.entities.iter().map(|x| (x.name.clone(), ctx.names[&x.trans_name].to_sprite_names(&x.trans_name))).collect(),
+    };
 
-    Ok((project.name, res.to_compact_string()))
+    let mut blocks: Vec<_> = role.funcs.iter().map(|func| palette_block(func, comments.block(None, func), None)).collect();
+    for sprite in role.entities.iter() {
+        blocks.extend(sprite.funcs.iter().map(|func| palette_block(func, comments.block(Some(&sprite.name), func), Some(&sprite.trans_name))));
+    }
+
+    let res = project::Role {
+        name: role_info.name,
+        notes: role.notes.clone(),
+        stage_size: role.stage_size,
+        block_sources: options.block_sources.clone(),
+        blocks,
+        imports: options.imports.clone().unwrap_or_else(|| EDITOR_MODULES.iter().filter(|x| ctx.modules.contains(*x)).map(|x| CompactString::from(*x)).collect()),
+        editors,
+        images,
+        sounds,
+        names,
+    };
+    Ok((res, ctx.found_types))
 }
@@ -2,16 +2,47 @@ use nb2pb::*;
 
 fn main() {
     let args: Vec<_> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("usage: {} [input]", args[0]);
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("usage: {} [input] [output dir, .py file, .ipynb file, or .html report]?", args[0]);
         std::process::exit(1);
     }
 
     let input = &args[1];
     if input.ends_with(".xml") {
         let xml = std::fs::read_to_string(input).expect("failed to read file");
-        let res = translate(&xml).expect("failed to translate");
-        println!("{}", res.1);
+        match args.get(2) {
+            Some(output) if output.ends_with(".html") => {
+                std::fs::write(output, report(&xml, &TranslateOptions::default()).expect("failed to translate")).expect("failed to write report");
+            }
+            Some(output) => {
+                let (_, project) = translate_project(&xml, &TranslateOptions::default()).expect("failed to translate");
+                match [".py", ".ipynb"].into_iter().find(|x| output.ends_with(x)) {
+                    Some(ext) => for (i, role) in project.roles.iter().enumerate() {
+                        let path = if i == 0 { output.clone() } else { format!("{}_{i}{ext}", &output[..output.len() - ext.len()]) };
+                        let content = if ext == ".py" { role.to_script() } else { role.to_notebook() };
+                        std::fs::write(path, content).expect("failed to write file");
+                    }
+                    None => project.write_dir(output.as_ref()).expect("failed to write project"),
+                }
+            }
+            None => println!("{}", translate(&xml).expect("failed to translate").1),
+        }
+    }
+    else if input.ends_with(".json") {
+        let json = std::fs::read_to_string(input).expect("failed to read file");
+        match project::Project::parse(&json) {
+            Ok(_) => println!("ok"),
+            Err(project::ProjectError::Json(e)) => {
+                eprintln!("invalid project: {e}");
+                std::process::exit(1);
+            }
+            Err(project::ProjectError::Invalid(errors)) => {
+                for error in errors {
+                    eprintln!("{error:?}");
+                }
+                std::process::exit(1);
+            }
+        }
     }
     else {
         eprintln!("unknown input file type");
@@ -0,0 +1,239 @@
+//! Merging a new translation into an existing PyBlox project which may have been edited by hand.
+//!
+//! Each editor is split into top-level chunks (functions, assignments, and other statements along with the comments directly above them),
+//! which are matched between the old and new editors by name.
+//! Chunks from the new translation replace the old translated ones, while old translated chunks which are no longer produced are removed.
+//! Anything else in the old project is considered hand-written and kept.
+//!
+//! A function counts as translated if the old source map has an entry for a hat block or custom block definition with the same name,
+//! whose hash still matches the code of the function. Other statements (such as global variables and sprite fields) count as translated
+//! if their hash in the old editor's [`hashes`](Editor::hashes) still matches. So any edit to a translated chunk makes it hand-written.
+//! A hand-written chunk is kept, and is only reported as a conflict if the new translation of the same name is different from the old one;
+//! if the blocks it came from are unchanged, the edits are simply kept.
+//! The `__init__` function of each sprite is always considered translated, since it holds the state and assets of the sprite.
+
+use std::collections::{BTreeMap, BTreeSet};
+use compact_str::CompactString;
+
+use crate::chunks::{Chunk, hash_lines, split_chunks};
+use crate::project::*;
+
+/// A problem found while merging, which was resolved by keeping the old code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// A hand-written (or hand-edited) function has the same name as a different function in the new translation.
+    /// The old function is kept and the new one is dropped.
+    Function { role: CompactString, editor: CompactString, name: CompactString },
+    /// A hand-edited top-level statement (such as a global variable) has a different translation, which is matched by its key
+    /// (the assigned name, or the first line up to the first argument). The old statement is kept and the new one is dropped.
+    Statement { role: CompactString, editor: CompactString, key: CompactString },
+    /// The sprite of an editor with hand-written functions no longer exists, so the old editor is kept.
+    Editor { role: CompactString, editor: CompactString },
+    /// A role no longer exists, so it is kept unchanged.
+    Role { role: CompactString },
+}
+
+/// The result of [`merge`](crate::merge()).
+#[derive(Debug, Clone)]
+pub struct MergedProject {
+    /// The name of the project.
+    pub name: CompactString,
+    pub project: Project,
+    /// Conflicts between the old project and the new translation, in the order they were found.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// An error from [`merge`](crate::merge()).
+#[derive(Debug)]
+pub enum MergeError {
+    /// The old project is malformed or does not match the PyBlox schema.
+    Json(serde_json::Error),
+    /// The new project could not be translated.
+    Translate(crate::TranslateError),
+}
+impl From<serde_json::Error> for MergeError { fn from(e: serde_json::Error) -> Self { Self::Json(e) } }
+impl From<crate::TranslateError> for MergeError { fn from(e: crate::TranslateError) -> Self { Self::Translate(e) } }
+
+/// A chunk in a merged editor, taken from either the old or the new editor.
+struct Piece<'a> {
+    lines: Vec<&'a str>,
+    gap: usize,
+    /// The index of the chunk in the new editor, if the piece came from there.
+    new_chunk: Option<usize>,
+}
+
+/// The chunks in an editor which were translated from blocks, according to its source map and statement hashes.
+struct Translated<'a> {
+    /// The hashes of each translated function (not counting comments above it), keyed by its name.
+    defs: BTreeMap<CompactString, BTreeSet<CompactString>>,
+    /// The hashes of other translated statements, keyed by their chunk keys.
+    statements: &'a BTreeMap<CompactString, CompactString>,
+    /// If true, the editor is for a sprite (or the stage), whose `__init__` function holds its translated state and assets.
+    sprite: bool,
+}
+impl<'a> Translated<'a> {
+    fn new(role: &Role, editor: &'a Editor) -> Self {
+        let blocks = match editor.ty {
+            EditorType::Globals => &role.names.globals.blocks,
+            _ => match role.names.sprites.values().find(|x| x.name == editor.name) {
+                Some(x) => &x.blocks,
+                None => &BTreeMap::new(),
+            }
+        };
+        let mut defs: BTreeMap<CompactString, BTreeSet<CompactString>> = BTreeMap::new();
+        for entry in editor.source_map.iter().filter(|x| x.path.is_empty()) {
+            let Some(hash) = &entry.hash else { continue };
+            // hat scripts are named by their function (though "when" hats also have a watcher function), and custom blocks by their spec
+            let names = match blocks.get(&entry.script) {
+                Some(name) => vec![name.clone()],
+                None => vec![entry.script.clone(), entry.script.replacen("on_", "watch_", 1).into()],
+            };
+            for name in names {
+                defs.entry(name).or_default().insert(hash.clone());
+            }
+        }
+        Self { defs, statements: &editor.hashes, sprite: editor.ty != EditorType::Globals }
+    }
+    /// Checks if a chunk of some lines was translated and has not been edited since.
+    fn contains(&self, chunk: &Chunk, lines: &[&str]) -> bool {
+        let hash = || hash_lines(&lines[chunk.code_start..chunk.end]);
+        match chunk.def_name.as_deref() {
+            Some("__init__") => self.sprite,
+            Some(name) => self.defs.get(name).is_some_and(|x| x.contains(&hash())),
+            None => self.statements.get(&chunk.key).is_some_and(|x| *x == hash()),
+        }
+    }
+}
+
+/// Merges an editor from the new translation into the same editor from the old project.
+fn merge_editor(role: &Role, old: &Editor, new: &Editor, conflicts: &mut Vec<MergeConflict>) -> Editor {
+    let (old_lines, new_lines): (Vec<_>, Vec<_>) = (old.value.lines().collect(), new.value.lines().collect());
+    let (old_chunks, new_chunks) = (split_chunks(&old_lines), split_chunks(&new_lines));
+    let translated = Translated::new(role, old);
+
+    let mut new_keys: BTreeMap<&str, usize> = BTreeMap::new();
+    for (i, chunk) in new_chunks.iter().enumerate() {
+        new_keys.entry(&chunk.key).or_insert(i);
+    }
+
+    let mut pieces: Vec<Piece> = vec![];
+    let mut placed = BTreeSet::new();
+    for chunk in old_chunks.iter() {
+        let old_piece = Piece { lines: chunk.text(&old_lines), gap: chunk.gap, new_chunk: None };
+        let edited = !translated.contains(chunk, &old_lines);
+        match new_keys.get(chunk.key.as_str()) {
+            Some(&i) if placed.insert(i) => {
+                let new_chunk = &new_chunks[i];
+                let new_piece = Piece { lines: new_chunk.text(&new_lines), gap: new_chunk.gap, new_chunk: Some(i) };
+                if !edited || old_piece.lines == new_piece.lines {
+                    pieces.push(new_piece);
+                } else if translated.contains(new_chunk, &new_lines) {
+                    pieces.push(old_piece); // the blocks are unchanged, so the edits to their old translation are kept
+                } else {
+                    conflicts.push(match &chunk.def_name {
+                        Some(name) => MergeConflict::Function { role: role.name.clone(), editor: old.name.clone(), name: name.clone() },
+                        None => MergeConflict::Statement { role: role.name.clone(), editor: old.name.clone(), key: chunk.key.clone() },
+                    });
+                    pieces.push(old_piece);
+                }
+            }
+            None if !edited => (), // the blocks were removed
+            _ => pieces.push(old_piece),
+        }
+    }
+
+    // anything new goes after the closest earlier chunk from the new editor, or at the start if there is none
+    for (i, chunk) in new_chunks.iter().enumerate() {
+        if placed.contains(&i) { continue }
+        let pos = (0..i).rev().find_map(|prev| pieces.iter().position(|x| x.new_chunk == Some(prev))).map(|x| x + 1).unwrap_or(0);
+        pieces.insert(pos, Piece { lines: chunk.text(&new_lines), gap: chunk.gap, new_chunk: Some(i) });
+    }
+
+    let mut value = String::new();
+    let mut source_map = vec![];
+    let mut hashes = BTreeMap::new();
+    let mut line = 0;
+    for piece in pieces.iter() {
+        if let Some(i) = piece.new_chunk {
+            let chunk = &new_chunks[i];
+            if let Some(hash) = new.hashes.get(&chunk.key).filter(|_| chunk.def_name.is_none()) {
+                hashes.insert(chunk.key.clone(), hash.clone());
+            }
+            for entry in new.source_map.iter().filter(|x| x.lines.0 > chunk.start && x.lines.1 <= chunk.end) {
+                let mut entry = entry.clone();
+                entry.lines = (entry.lines.0 - chunk.start + line, entry.lines.1 - chunk.start + line);
+                source_map.push(entry);
+            }
+        }
+        for text in piece.lines.iter() {
+            value += text;
+            value.push('\n');
+        }
+        for _ in 0..piece.gap {
+            value.push('\n');
+        }
+        line += piece.lines.len() + piece.gap;
+    }
+
+    Editor { ty: new.ty, name: new.name.clone(), value, source_map, hashes }
+}
+
+/// Checks if an editor has any functions which were not translated from blocks.
+fn has_hand_written(role: &Role, editor: &Editor) -> bool {
+    let lines: Vec<_> = editor.value.lines().collect();
+    let translated = Translated::new(role, editor);
+    split_chunks(&lines).iter().any(|chunk| chunk.def_name.is_some() && !translated.contains(chunk, &lines))
+}
+
+fn merge_role(old: &Role, new: Role, conflicts: &mut Vec<MergeConflict>) -> Role {
+    let mut editors = vec![];
+    for editor in new.editors.iter() {
+        match old.editors.iter().find(|x| x.ty == editor.ty && x.name == editor.name) {
+            Some(old_editor) => editors.push(merge_editor(old, old_editor, editor, conflicts)),
+            None => editors.push(editor.clone()),
+        }
+    }
+    for editor in old.editors.iter() {
+        if new.editors.iter().any(|x| x.ty == editor.ty && x.name == editor.name) || !has_hand_written(old, editor) { continue }
+        conflicts.push(MergeConflict::Editor { role: new.name.clone(), editor: editor.name.clone() });
+        editors.push(editor.clone());
+    }
+
+    // keep any old assets which the merged code still uses
+    let (mut images, mut sounds) = (new.images, new.sounds);
+    for asset in editors.iter().flat_map(|x| asset_refs(&x.value)) {
+        match asset.kind {
+            AssetKind::Image => if let Some(image) = old.images.get(asset.name) {
+                images.entry(asset.name.into()).or_insert_with(|| image.clone());
+            }
+            AssetKind::Sound => if let Some(sound) = old.sounds.get(asset.name) {
+                sounds.entry(asset.name.into()).or_insert_with(|| sound.clone());
+            }
+        }
+    }
+
+    let mut imports = new.imports;
+    for import in old.imports.iter() {
+        if !imports.contains(import) { imports.push(import.clone()) }
+    }
+
+    Role { editors, images, sounds, imports, ..new }
+}
+
+/// Merges a new translation into an old project, returning the merged project and any conflicts.
+pub(crate) fn merge_projects(old: Project, new: Project) -> (Project, Vec<MergeConflict>) {
+    let mut conflicts = vec![];
+    let mut roles = vec![];
+    for role in new.roles.iter() {
+        match old.roles.iter().find(|x| x.name == role.name) {
+            Some(old_role) => roles.push(merge_role(old_role, role.clone(), &mut conflicts)),
+            None => roles.push(role.clone()),
+        }
+    }
+    for role in old.roles {
+        if new.roles.iter().any(|x| x.name == role.name) { continue }
+        conflicts.push(MergeConflict::Role { role: role.name.clone() });
+        roles.push(role);
+    }
+    (Project { roles }, conflicts)
+}
@@ -0,0 +1,200 @@
+//! Translation of Snap! names into python identifiers.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+
+use compact_str::{CompactString, format_compact};
+use netsblox_ast::Function;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::project::{GlobalNames, SpriteNames};
+
+/// The reserved words of python, which can never be used as identifiers (or attribute names).
+pub(crate) const KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+/// Builtins and PyBlox names which the generated code refers to directly, and so must not be shadowed by user-defined names.
+pub(crate) const RESERVED: &[&str] = &[
+    "Exception", "NoYield", "RuntimeError", "_e", "_fields", "_k", "_sender", "_v", "_x", "_y", "abs", "bool",
+    "chr", "dict", "float", "get_error", "globals", "images", "input", "int", "isinstance", "len", "list", "math",
+    "max", "min", "nb", "nothrow", "onkey", "onmouse", "onstart", "ord", "print", "random", "range", "round",
+    "self", "snap", "sounds", "str", "sum", "sys", "time", "traceback", "tuple",
+];
+
+/// Checks if a name is a python keyword.
+pub(crate) fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+/// Converts a Snap! name into a valid python identifier which does not collide with any keyword or name used by the generated code.
+/// Such names are given a trailing underscore, following the usual python convention (e.g., `class_`).
+pub(crate) fn py_ident(raw: &str) -> Result<CompactString, ()> {
+    Ok(avoid_reserved(netsblox_ast::util::c_ident(raw)?))
+}
+
+/// Equivalent to [`py_ident`], but keeps any characters which python accepts in identifiers rather than only ASCII ones.
+///
+/// Python compares identifiers after NFKC normalization, so the name is normalized first and then checked against the XID rules.
+/// Runs of rejected characters become a single underscore, and names which cannot start an identifier are given a `var_` prefix.
+pub(crate) fn py_unicode_ident(raw: &str) -> Result<CompactString, ()> {
+    let cleaned: String = raw.nfkc().map(|ch| if ch == '_' || unicode_ident::is_xid_continue(ch) { ch } else { ' ' }).collect();
+    let res = cleaned.split_whitespace().collect::<Vec<_>>().join("_");
+    let first = res.chars().next().ok_or(())?;
+    Ok(avoid_reserved(match first == '_' || unicode_ident::is_xid_start(first) {
+        true => res.into(),
+        false => format_compact!("var_{res}"),
+    }))
+}
+
+/// Checks if a name is a valid python identifier, allowing any unicode characters which python accepts.
+pub(crate) fn is_unicode_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    let start = chars.next().is_some_and(|ch| ch == '_' || unicode_ident::is_xid_start(ch));
+    start && chars.all(unicode_ident::is_xid_continue) && !is_keyword(name)
+}
+
+/// Wraps a name transformer (such as [`py_ident`]) so that distinct Snap! names never become the same identifier, such as `print` and `print_`.
+/// Like script names, a name which is already taken is numbered from 2 (e.g., `print__2`), and the same Snap! name always gets the same result.
+pub(crate) fn unique_names(transform: fn(&str) -> Result<CompactString, ()>) -> impl Fn(&str) -> Result<CompactString, ()> {
+    let names: RefCell<(BTreeMap<CompactString, CompactString>, BTreeSet<CompactString>)> = Default::default();
+    move |raw| {
+        let (done, used) = &mut *names.borrow_mut();
+        if let Some(res) = done.get(raw) { return Ok(res.clone()) }
+        let base = transform(raw)?;
+        let res = iter::once(base.clone()).chain((2..).map(|i| format_compact!("{base}_{i}"))).find(|x| !used.contains(x)).unwrap();
+        used.insert(res.clone());
+        done.insert(raw.into(), res.clone());
+        Ok(res)
+    }
+}
+
+fn avoid_reserved(name: CompactString) -> CompactString {
+    match is_keyword(&name) || RESERVED.contains(&name.as_str()) {
+        true => format_compact!("{name}_"),
+        false => name,
+    }
+}
+
+/// Converts text from a hat block (such as a key or message type) into a lowercase suffix for the name of a script, if possible.
+/// Unlike a whole identifier, the suffix may start with a digit (e.g., `on_key_1`).
+pub(crate) fn script_suffix(text: &str, unicode: bool) -> Option<CompactString> {
+    let valid = |ch: char| ch.is_ascii_alphanumeric() || (unicode && unicode_ident::is_xid_continue(ch));
+    let cleaned: String = text.nfkc().flat_map(char::to_lowercase).map(|ch| if valid(ch) { ch } else { ' ' }).collect();
+    let res = cleaned.split_whitespace().collect::<Vec<_>>().join("_");
+    (!res.is_empty()).then(|| res.into())
+}
+
+/// Gets the spec of a custom block as it appears in Snap!, such as `double %'x'`.
+pub(crate) fn block_spec(func: &Function) -> CompactString {
+    let mut params = func.params.iter();
+    let mut res = CompactString::default();
+    for ch in func.name.chars() {
+        match ch {
+            '\t' => match params.next() { // the parser marks each parameter with a tab
+                Some(param) => res.push_str(&format_compact!("%'{}'", param.name)),
+                None => res.push(ch),
+            }
+            _ => res.push(ch),
+        }
+    }
+    res
+}
+
+/// The python names of everything owned by a sprite (or by the role itself, for globals), keyed by their original names.
+#[derive(Debug, Default)]
+pub(crate) struct NameMap {
+    /// Sprite fields, or global variables.
+    pub vars: BTreeMap<CompactString, CompactString>,
+    /// Script variables and custom block parameters, which are named the same way in every script.
+    pub locals: BTreeMap<CompactString, CompactString>,
+    /// Custom blocks, keyed by their spec.
+    pub blocks: BTreeMap<CompactString, CompactString>,
+    /// Costumes, whose python name is the key of their image in the project.
+    pub costumes: BTreeMap<CompactString, CompactString>,
+    /// Sounds, whose python name is the key of their audio in the project.
+    pub sounds: BTreeMap<CompactString, CompactString>,
+}
+impl NameMap {
+    pub(crate) fn to_global_names(&self) -> GlobalNames {
+        GlobalNames { variables: self.vars.clone(), locals: self.locals.clone(), blocks: self.blocks.clone() }
+    }
+    pub(crate) fn to_sprite_names(&self, name: &str) -> SpriteNames {
+        SpriteNames {
+            name: name.into(),
+            variables: self.vars.clone(),
+            locals: self.locals.clone(),
+            blocks: self.blocks.clone(),
+            costumes: self.costumes.clone(),
+            sounds: self.sounds.clone(),
+        }
+    }
+}
+
+#[test]
+fn test_py_ident() {
+    for keyword in KEYWORDS {
+        let res = py_ident(keyword).unwrap();
+        assert_eq!(res, format_compact!("{keyword}_"));
+        assert!(!is_keyword(&res));
+    }
+    for name in RESERVED {
+        assert_eq!(py_ident(name).unwrap(), format_compact!("{name}_"));
+    }
+    assert_eq!(py_ident("my var").unwrap(), "my_var");
+    assert_eq!(py_ident("is it?").unwrap(), "is_it");
+    assert_eq!(py_ident("(print)").unwrap(), "print_");
+    assert_eq!(py_ident("none").unwrap(), "none");
+    assert_eq!(py_ident("match").unwrap(), "match"); // soft keywords are valid identifiers
+    assert!(py_ident("?!").is_err());
+}
+
+#[test]
+fn test_unique_names() {
+    let transform = unique_names(py_ident);
+    assert_eq!(transform("print").unwrap(), "print_");
+    assert_eq!(transform("print_").unwrap(), "print__2");
+    assert_eq!(transform("print").unwrap(), "print_");
+    assert_eq!(transform("my var").unwrap(), "my_var");
+    assert_eq!(transform("my_var").unwrap(), "my_var_2");
+    assert!(transform("?!").is_err());
+}
+
+#[test]
+fn test_py_unicode_ident() {
+    assert_eq!(py_unicode_ident("my var").unwrap(), "my_var");
+    assert_eq!(py_unicode_ident("número de vidas").unwrap(), "número_de_vidas");
+    assert_eq!(py_unicode_ident("分数").unwrap(), "分数");
+    assert_eq!(py_unicode_ident("счёт!").unwrap(), "счёт");
+    assert_eq!(py_unicode_ident("ﬁle").unwrap(), "file"); // NFKC folds the ligature
+    assert_eq!(py_unicode_ident("x²").unwrap(), "x2");
+    assert_eq!(py_unicode_ident("café's price").unwrap(), "café_s_price");
+    assert_eq!(py_unicode_ident("2分").unwrap(), "var_2分");
+    assert_eq!(py_unicode_ident("★ score ★").unwrap(), "score");
+    assert_eq!(py_unicode_ident("print").unwrap(), "print_");
+    assert_eq!(py_unicode_ident("ｐａｓｓ").unwrap(), "pass_"); // full-width letters normalize to a keyword
+    assert!(py_unicode_ident("★").is_err());
+
+    assert!(is_unicode_ident("分数"));
+    assert!(is_unicode_ident("_x2"));
+    assert!(!is_unicode_ident("2x"));
+    assert!(!is_unicode_ident("my var"));
+    assert!(!is_unicode_ident("class"));
+    assert!(!is_unicode_ident(""));
+}
+
+#[test]
+fn test_script_suffix() {
+    assert_eq!(script_suffix("space", false).unwrap(), "space");
+    assert_eq!(script_suffix("up arrow", false).unwrap(), "up_arrow");
+    assert_eq!(script_suffix("Game Over!", false).unwrap(), "game_over");
+    assert_eq!(script_suffix("pass", false).unwrap(), "pass");
+    assert_eq!(script_suffix("fin del juego", true).unwrap(), "fin_del_juego");
+    assert_eq!(script_suffix("1", false).unwrap(), "1");
+    assert_eq!(script_suffix("número", false).unwrap(), "n_mero");
+    assert_eq!(script_suffix("+", false), None);
+}
@@ -0,0 +1,273 @@
+//! Optimisation passes over the generated Python code.
+//!
+//! The translators emit code which is faithful to each block in isolation, which often leaves constant sub-expressions
+//! (e.g., one-based list indices such as `x[1 - 1]`) and blocks of code which can never run.
+//! These passes clean that up without changing the behavior of the program.
+
+use compact_str::{CompactString, format_compact};
+
+use crate::OptLevel;
+use crate::ir::{Arg, BinOp, CmpOp, Expr, FStrPart, LogicOp, Source, Stmt, StmtKind, UnaryOp};
+
+/// Optimises a sequence of statements to the given level.
+pub(crate) fn optimize(stmts: Vec<Stmt>, level: OptLevel) -> Vec<Stmt> {
+    match level {
+        OptLevel::None => stmts,
+        OptLevel::Fold => block(stmts, false),
+        OptLevel::Full => block(stmts, true),
+    }
+}
+
+fn block(stmts: Vec<Stmt>, dce: bool) -> Vec<Stmt> {
+    let mut res = vec![];
+    for stmt in stmts {
+        let Stmt { kind, comment, source } = stmt;
+        let kind = match kind {
+            StmtKind::Expr(x) => StmtKind::Expr(fold(x)),
+            StmtKind::Assign(t, v) => StmtKind::Assign(fold(t), fold(v)),
+            StmtKind::AugAssign(t, op, v) => StmtKind::AugAssign(fold(t), op, fold(v)),
+            StmtKind::Del(x) => StmtKind::Del(fold(x)),
+            StmtKind::Raise(x) => StmtKind::Raise(fold(x)),
+            StmtKind::Return(x) => StmtKind::Return(fold(x)),
+            StmtKind::If { condition, then, otherwise } => match (fold(condition), dce) {
+                (Expr::Bool(condition), true) => {
+                    let taken = block(if condition { then } else { otherwise }, dce);
+                    let terminated = taken.last().is_some_and(terminates);
+                    inline(&mut res, taken, comment, source);
+                    if terminated { break } else { continue }
+                }
+                (condition, _) => StmtKind::If { condition, then: block(then, dce), otherwise: block(otherwise, dce) },
+            }
+            StmtKind::While { condition, body } => match (fold(condition), dce) {
+                (Expr::Bool(false), true) => continue,
+                (condition, _) => StmtKind::While { condition, body: block(body, dce) },
+            }
+            StmtKind::For { target, iter, body } => StmtKind::For { target, iter: fold(iter), body: block(body, dce) },
+            StmtKind::With { context, body } => StmtKind::With { context: fold(context), body: block(body, dce) },
+            StmtKind::Try { body, var, handler } => StmtKind::Try { body: block(body, dce), var, handler: block(handler, dce) },
+            StmtKind::FunctionDef(mut def) => {
+                def.decorators = def.decorators.into_iter().map(fold).collect();
+                def.body = block(def.body, dce);
+                StmtKind::FunctionDef(def)
+            }
+            x @ (StmtKind::Import(_) | StmtKind::Pass | StmtKind::Blank | StmtKind::Docstring(_)) => x,
+        };
+        let stmt = Stmt { kind, comment, source };
+        let terminated = terminates(&stmt);
+        res.push(stmt);
+        if dce && terminated { break } // anything after this point is unreachable
+    }
+    res
+}
+
+/// Appends the statements of an inlined block, moving the comment of the removed compound statement onto the first of them.
+/// Statements with no source of their own are attributed to the removed statement's block.
+/// Placeholder `pass` statements are dropped, since the enclosing block gets one anyway if it ends up empty.
+fn inline(res: &mut Vec<Stmt>, stmts: Vec<Stmt>, comment: Option<CompactString>, source: Option<Source>) {
+    let mut stmts: Vec<Stmt> = stmts.into_iter().filter(|x| x.kind != StmtKind::Pass || x.comment.is_some()).collect();
+    for stmt in stmts.iter_mut() {
+        if stmt.source.is_none() { stmt.source = source.clone() }
+    }
+    if let (Some(first), Some(comment)) = (stmts.first_mut(), comment) {
+        first.comment = Some(match first.comment.take() {
+            Some(x) => format_compact!("{comment}\n{x}"),
+            None => comment,
+        });
+    }
+    res.extend(stmts);
+}
+
+fn terminates(stmt: &Stmt) -> bool {
+    matches!(stmt.kind, StmtKind::Return(_) | StmtKind::Raise(_))
+}
+
+/// Gets the value of a number literal, along with whether or not it is wrapped in a `snap.wrap` call.
+fn num_value(expr: &Expr) -> Option<(f64, bool)> {
+    match expr {
+        Expr::Num(x) => Some((*x, false)),
+        Expr::Call(f, args) if matches!(&**f, Expr::Name(x) if x == "snap.wrap") => match args.as_slice() {
+            [Arg::Pos(Expr::Num(x))] => Some((*x, true)),
+            _ => None,
+        }
+        _ => None,
+    }
+}
+
+/// Formats a number literal the same way python's `str` would, if that can be done exactly.
+fn num_str(value: f64) -> Option<CompactString> {
+    if value == 0.0 { return Some("0".into()) }
+    if !value.is_finite() { return None }
+    // integers are printed as int literals; other values switch to exponential notation outside this range
+    match value.fract() == 0.0 || (1e-4..1e16).contains(&value.abs()) {
+        true => Some(format_compact!("{value}")),
+        false => None,
+    }
+}
+
+fn eval_bin(op: BinOp, a: f64, b: f64) -> Option<f64> {
+    let res = match op {
+        BinOp::Add => a + b,
+        BinOp::Sub => a - b,
+        BinOp::Mul => a * b,
+        BinOp::Div if b != 0.0 => a / b,
+        BinOp::Mod if b != 0.0 => a - b * (a / b).floor(),
+        BinOp::Pow if a != 0.0 || b >= 0.0 => a.powf(b),
+        BinOp::Div | BinOp::Mod | BinOp::Pow => return None, // python raises an error here, so keep the code as-is
+    };
+    res.is_finite().then_some(res)
+}
+fn eval_cmp(op: CmpOp, a: f64, b: f64) -> Option<bool> {
+    Some(match op {
+        CmpOp::Less => a < b,
+        CmpOp::LessEq => a <= b,
+        CmpOp::Eq => a == b,
+        CmpOp::Neq => a != b,
+        CmpOp::Greater => a > b,
+        CmpOp::GreaterEq => a >= b,
+        CmpOp::In => return None,
+    })
+}
+
+/// Folds the constant sub-expressions of an expression.
+pub(crate) fn fold(expr: Expr) -> Expr {
+    match expr {
+        Expr::FStr(parts) => fold_fstr(parts),
+        Expr::List(x) => Expr::List(x.into_iter().map(fold).collect()),
+        Expr::Tuple(x) => Expr::Tuple(x.into_iter().map(fold).collect()),
+        Expr::Dict(x) => Expr::Dict(x.into_iter().map(|(k, v)| (fold(k), fold(v))).collect()),
+        Expr::Starred(x) => Expr::Starred(Box::new(fold(*x))),
+        Expr::Attr(x, attr) => Expr::Attr(Box::new(fold(*x)), attr),
+        Expr::Call(f, args) => fold_call(fold(*f), args.into_iter().map(fold_arg).collect()),
+        Expr::Index(x, index) => {
+            let index = fold(*index);
+            let index = match num_value(&index) {
+                Some((x, true)) => Expr::Num(x), // plain and wrapped indices are equivalent
+                _ => index,
+            };
+            Expr::Index(Box::new(fold(*x)), Box::new(index))
+        }
+        Expr::Slice(a, b, c) => {
+            let f = |x: Option<Box<Expr>>| x.map(|x| Box::new(fold(*x)));
+            Expr::Slice(f(a), f(b), f(c))
+        }
+        Expr::Unary(op, x) => match (op, fold(*x)) {
+            (UnaryOp::Neg, Expr::Num(x)) => Expr::Num(-x),
+            (UnaryOp::Not, Expr::Bool(x)) => Expr::Bool(!x),
+            (op, x) => Expr::unary(op, x),
+        }
+        Expr::Bin(op, a, b) => {
+            let (a, b) = (fold(*a), fold(*b));
+            match (num_value(&a), num_value(&b)) {
+                (Some((x, wx)), Some((y, wy))) => match eval_bin(op, x, y) {
+                    Some(res) if wx || wy => Expr::name("snap.wrap").call(vec![Expr::Num(res)]),
+                    Some(res) => Expr::Num(res),
+                    None => Expr::bin(op, a, b),
+                }
+                _ => Expr::bin(op, a, b),
+            }
+        }
+        Expr::Cmp(op, a, b) => {
+            let (a, b) = (fold(*a), fold(*b));
+            match (num_value(&a), num_value(&b)) {
+                (Some((x, _)), Some((y, _))) => match eval_cmp(op, x, y) {
+                    Some(res) => Expr::Bool(res),
+                    None => Expr::cmp(op, a, b),
+                }
+                _ => Expr::cmp(op, a, b),
+            }
+        }
+        Expr::Logic(op, a, b) => match (op, fold(*a), fold(*b)) {
+            (LogicOp::And, Expr::Bool(true), b) | (LogicOp::Or, Expr::Bool(false), b) => b,
+            (LogicOp::And, Expr::Bool(false), _) => Expr::Bool(false),
+            (LogicOp::Or, Expr::Bool(true), _) => Expr::Bool(true),
+            (op, a, b) => Expr::logic(op, a, b),
+        }
+        Expr::IfElse { then, condition, otherwise } => match fold(*condition) {
+            Expr::Bool(true) => fold(*then),
+            Expr::Bool(false) => fold(*otherwise),
+            condition => Expr::IfElse { then: Box::new(fold(*then)), condition: Box::new(condition), otherwise: Box::new(fold(*otherwise)) },
+        }
+        Expr::Lambda(params, body) => Expr::Lambda(params, Box::new(fold(*body))),
+        Expr::Comp { kind, item, clauses, condition } => Expr::Comp {
+            kind,
+            item: Box::new(fold(*item)),
+            clauses: clauses.into_iter().map(|(t, x)| (t, fold(x))).collect(),
+            condition: condition.map(|x| Box::new(fold(*x))),
+        },
+        x @ (Expr::Name(_) | Expr::Num(_) | Expr::Str(_) | Expr::Bool(_) | Expr::None) => x,
+    }
+}
+fn fold_arg(arg: Arg) -> Arg {
+    match arg {
+        Arg::Pos(x) => Arg::Pos(fold(x)),
+        Arg::Kw(k, x) => Arg::Kw(k, fold(x)),
+        Arg::Star(x) => Arg::Star(fold(x)),
+        Arg::DoubleStar(x) => Arg::DoubleStar(fold(x)),
+    }
+}
+fn fold_call(func: Expr, args: Vec<Arg>) -> Expr {
+    if let (Expr::Name(name), [Arg::Pos(Expr::Num(x))]) = (&func, args.as_slice()) {
+        match name.as_str() {
+            "abs" => return Expr::Num(x.abs()),
+            "math.floor" if x.is_finite() => return Expr::Num(x.floor()),
+            "math.ceil" if x.is_finite() => return Expr::Num(x.ceil()),
+            _ => (),
+        }
+    }
+    Expr::Call(Box::new(func), args)
+}
+/// Folds the interpolated constants of an f-string into its literal text, producing a plain string if nothing else is left.
+fn fold_fstr(parts: Vec<FStrPart>) -> Expr {
+    let mut res: Vec<FStrPart> = vec![];
+    for part in parts {
+        let part = match part {
+            FStrPart::Expr(x) => match fold(x) {
+                Expr::Str(x) => FStrPart::Lit(x),
+                Expr::Num(x) => match num_str(x) {
+                    Some(x) => FStrPart::Lit(x),
+                    None => FStrPart::Expr(Expr::Num(x)),
+                }
+                x => FStrPart::Expr(x),
+            }
+            x => x,
+        };
+        match (res.last_mut(), part) {
+            (Some(FStrPart::Lit(prev)), FStrPart::Lit(x)) => prev.push_str(&x),
+            (_, part) => res.push(part),
+        }
+    }
+    match res.as_slice() {
+        [] => Expr::str(""),
+        [FStrPart::Lit(x)] => Expr::Str(x.clone()),
+        _ => Expr::FStr(res),
+    }
+}
+
+#[test]
+fn test_fold() {
+    let wrap = |x: f64| Expr::name("snap.wrap").call(vec![Expr::Num(x)]);
+    let x = Expr::name("x");
+    assert_eq!(fold(x.clone().index(Expr::bin(BinOp::Sub, wrap(1.0), wrap(1.0)))).to_string(), "x[0]");
+    assert_eq!(fold(Expr::bin(BinOp::Add, Expr::bin(BinOp::Mul, Expr::Num(2.0), Expr::Num(3.0)), x.clone())).to_string(), "6 + x");
+    assert_eq!(fold(Expr::bin(BinOp::Div, Expr::Num(1.0), Expr::Num(0.0))).to_string(), "1 / 0");
+    assert_eq!(fold(Expr::bin(BinOp::Mod, Expr::Num(-7.0), Expr::Num(3.0))).to_string(), "2");
+    assert_eq!(fold(Expr::cmp(CmpOp::Less, wrap(6.0), Expr::Num(3.0))).to_string(), "False");
+    assert_eq!(fold(Expr::logic(LogicOp::And, Expr::Bool(true), x.clone())).to_string(), "x");
+    assert_eq!(fold(Expr::FStr(vec![FStrPart::Lit("a".into()), FStrPart::Expr(Expr::bin(BinOp::Add, Expr::Num(3.0), Expr::Num(5.0))), FStrPart::Lit("b".into())])).to_string(), "'a8b'");
+    assert_eq!(fold(Expr::FStr(vec![FStrPart::Expr(Expr::Num(0.00001)), FStrPart::Expr(x)])).to_string(), "f'{0.00001}{x}'");
+}
+#[test]
+fn test_dead_code() {
+    let ret = || Stmt::from(StmtKind::Return(Expr::None));
+    let call = |x: &str| Stmt::from(Expr::name(x).call(vec![]));
+    let stmts = vec![
+        StmtKind::If { condition: Expr::unary(UnaryOp::Not, Expr::Bool(true)), then: vec![call("a")], otherwise: vec![call("b")] }.into(),
+        StmtKind::While { condition: Expr::Bool(false), body: vec![call("c")] }.into(),
+        StmtKind::If { condition: Expr::name("x"), then: vec![ret(), call("d")], otherwise: vec![] }.into(),
+        ret(),
+        call("e"),
+    ];
+    assert_eq!(crate::ir::print_stmts(&optimize(stmts.clone(), OptLevel::Full), 88).0, "b()\nif x:\n    return None\nreturn None");
+    assert_eq!(optimize(stmts.clone(), OptLevel::Fold).len(), 5);
+    assert_eq!(optimize(stmts.clone(), OptLevel::None), stmts);
+}
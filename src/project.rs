@@ -0,0 +1,192 @@
+//! A typed model of the PyBlox project format.
+//!
+//! [`translate_project`](crate::translate_project) produces a [`Project`], which serializes to the same JSON as [`translate`](crate::translate).
+//! Existing projects can be read back with [`Project::parse`], which also checks them with [`Project::validate`].
+//! Projects can also be written as a directory of python files and assets with [`Project::write_dir`],
+//! and each role can be assembled into a standalone python script with [`Role::to_script`] or a Jupyter notebook with [`Role::to_notebook`].
+
+use std::collections::BTreeMap;
+
+use compact_str::CompactString;
+use serde::{Serialize, Deserialize};
+
+mod validate;
+mod dir;
+mod script;
+mod notebook;
+
+pub use validate::{ProjectError, ValidationError};
+pub(crate) use validate::{AssetKind, asset_refs};
+
+/// A PyBlox project, which holds one or more roles.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub roles: Vec<Role>,
+}
+
+/// A single role of a PyBlox project.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Role {
+    pub name: CompactString,
+    /// The project notes written by the author, which PyBlox does not use.
+    #[serde(default, skip_serializing_if = "CompactString::is_empty")]
+    pub notes: CompactString,
+    /// The width and height of the stage.
+    pub stage_size: (usize, usize),
+    /// URLs of the block libraries which PyBlox loads for this role.
+    pub block_sources: Vec<CompactString>,
+    /// Custom blocks to show in the PyBlox block palette, starting with the global blocks.
+    #[serde(default)]
+    pub blocks: Vec<Block>,
+    /// Python modules which PyBlox imports into every editor.
+    pub imports: Vec<CompactString>,
+    /// The code of the role, starting with the globals editor, then the stage, then each sprite.
+    pub editors: Vec<Editor>,
+    /// The costumes of every sprite, keyed by the name the code uses to refer to them (e.g., `images.Sprite_cst_untitled`).
+    pub images: BTreeMap<CompactString, Image>,
+    /// The sounds of every sprite, keyed by the name the code uses to refer to them (e.g., `sounds.Sprite_snd_Meow`).
+    pub sounds: BTreeMap<CompactString, Sound>,
+    /// The python names of everything in the role, keyed by their original names.
+    /// These are only known for projects translated by nb2pb, so they are empty for projects saved by PyBlox itself.
+    #[serde(default)]
+    pub names: Names,
+}
+
+/// The kind of code held by an [`Editor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditorType {
+    Globals,
+    Stage,
+    Sprite,
+}
+
+/// The code of the globals, the stage, or a single sprite.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Editor {
+    #[serde(rename = "type")]
+    pub ty: EditorType,
+    pub name: CompactString,
+    /// The python code in the editor.
+    pub value: String,
+    /// The blocks which each range of lines in [`Editor::value`] was translated from.
+    #[serde(default)]
+    pub source_map: Vec<SourceMapEntry>,
+    /// Hashes of the translated top-level statements which are not functions (such as global variables and sprite fields),
+    /// keyed by their first line up to the assigned name (or first argument). Functions have their hashes in the [`Editor::source_map`] instead.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<CompactString, CompactString>,
+}
+
+/// A range of lines in an [`Editor`] and the block they were translated from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceMapEntry {
+    /// The first and last line of the range (one-based and inclusive).
+    pub lines: (usize, usize),
+    pub role: CompactString,
+    /// The original name of the sprite which owns the block, or `None` for global custom blocks.
+    pub entity: Option<CompactString>,
+    /// The function name of a hat block script, or the spec of a custom block (e.g., `double %'x'`).
+    pub script: CompactString,
+    /// The collaboration id of the block, if it has one.
+    pub block: Option<CompactString>,
+    /// The position of the block in its script, which alternates between the index of a block in its sequence and the index of the slot holding the next sequence.
+    /// This is empty for the hat block itself, or for the definition of a custom block.
+    pub path: Vec<usize>,
+    /// A hash of the generated function, which is only present when the path is empty.
+    /// This lets [`merge`](crate::merge()) tell if the function has been edited by hand since it was translated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<CompactString>,
+}
+
+/// The shape of a [`Block`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockType {
+    Command,
+    Reporter,
+    Predicate,
+}
+
+/// A custom block in the PyBlox block palette, which calls a python function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    /// The original spec of the block, with each parameter written as `%'name'` (e.g., `double %'x'`).
+    pub name: CompactString,
+    /// The text of the block with an input slot for each parameter, written as the Snap slot type (e.g., `double %n`).
+    pub spec: CompactString,
+    /// The palette category, such as `motion` or `operators`.
+    pub category: CompactString,
+    #[serde(rename = "type")]
+    pub ty: BlockType,
+    /// The python name of the sprite which owns the block (where it is a method), or `None` for global blocks.
+    pub entity: Option<CompactString>,
+    /// The name of the python function which the block calls.
+    pub function: CompactString,
+}
+
+/// A costume image, which is stored as base64 in the JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Image {
+    /// The raw content of the image file.
+    #[serde(with = "base64_bytes")]
+    pub img: Vec<u8>,
+    /// The offset of the rotation center from the center of the image, with y pointing up.
+    pub center: (f64, f64),
+}
+
+/// A sound, which is stored as base64 in the JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sound {
+    /// The raw content of the audio file.
+    #[serde(with = "base64_bytes")]
+    pub snd: Vec<u8>,
+}
+
+/// The python names of everything in a role, keyed by their original names.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Names {
+    pub globals: GlobalNames,
+    /// The names of each sprite (including the stage), keyed by the original name of the sprite.
+    pub sprites: BTreeMap<CompactString, SpriteNames>,
+}
+
+/// The python names of global variables and custom blocks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GlobalNames {
+    pub variables: BTreeMap<CompactString, CompactString>,
+    /// Parameters of global custom blocks.
+    pub locals: BTreeMap<CompactString, CompactString>,
+    /// Global custom blocks, keyed by their spec.
+    pub blocks: BTreeMap<CompactString, CompactString>,
+}
+
+/// The python names of a sprite and everything it owns.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpriteNames {
+    /// The python name of the sprite itself.
+    pub name: CompactString,
+    /// Sprite fields.
+    pub variables: BTreeMap<CompactString, CompactString>,
+    /// Script variables, custom block parameters, and message fields, which are named the same way in every script.
+    pub locals: BTreeMap<CompactString, CompactString>,
+    /// Sprite-local custom blocks, keyed by their spec.
+    pub blocks: BTreeMap<CompactString, CompactString>,
+    /// Costumes, whose python name is the key of their image in [`Role::images`].
+    pub costumes: BTreeMap<CompactString, CompactString>,
+    /// Sounds, whose python name is the key of their audio in [`Role::sounds`].
+    pub sounds: BTreeMap<CompactString, CompactString>,
+}
+
+mod base64_bytes {
+    use base64::engine::{Engine as _, general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        STANDARD.decode(text).map_err(D::Error::custom)
+    }
+}
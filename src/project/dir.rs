@@ -0,0 +1,129 @@
+//! Writing a PyBlox project as a directory of python files and assets.
+//!
+//! Each role gets its own directory, holding one python file per editor, the costumes in `images/`, and the sounds in `sounds/`.
+//! A `manifest.json` at the root lists everything else needed to rebuild the project, with paths relative to the root.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::*;
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    roles: Vec<RoleManifest<'a>>,
+}
+
+#[derive(Serialize)]
+struct RoleManifest<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    notes: &'a str,
+    /// The directory holding the files of the role.
+    path: String,
+    stage_size: (usize, usize),
+    block_sources: &'a [CompactString],
+    blocks: &'a [Block],
+    imports: &'a [CompactString],
+    editors: Vec<EditorManifest<'a>>,
+    images: BTreeMap<&'a str, ImageManifest>,
+    sounds: BTreeMap<&'a str, SoundManifest>,
+    names: &'a Names,
+}
+
+#[derive(Serialize)]
+struct EditorManifest<'a> {
+    #[serde(rename = "type")]
+    ty: EditorType,
+    name: &'a str,
+    file: String,
+    source_map: &'a [SourceMapEntry],
+}
+
+#[derive(Serialize)]
+struct ImageManifest {
+    file: String,
+    center: (f64, f64),
+}
+
+#[derive(Serialize)]
+struct SoundManifest {
+    file: String,
+}
+
+/// Gets a name for a directory which is safe on every platform, replacing any characters which may not appear in file names.
+fn dir_name(name: &str) -> String {
+    let res: String = name.chars().map(|ch| if ch.is_control() || "/\\:*?\"<>|".contains(ch) { '_' } else { ch }).collect();
+    match res.trim_matches('.').trim().is_empty() {
+        true => "role".into(),
+        false => res,
+    }
+}
+
+/// Gets the file extension of an image from its content.
+pub(super) fn image_extension(img: &[u8]) -> &'static str {
+    image::guess_format(img).ok().and_then(|x| x.extensions_str().first().copied()).unwrap_or("bin")
+}
+
+/// Gets the file extension of a sound from its content.
+fn sound_extension(snd: &[u8]) -> &'static str {
+    match snd {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "wav",
+        [b'O', b'g', b'g', b'S', ..] => "ogg",
+        [b'f', b'L', b'a', b'C', ..] => "flac",
+        [b'I', b'D', b'3', ..] | [0xff, 0xe0..=0xff, ..] => "mp3",
+        _ => "bin",
+    }
+}
+
+impl Project {
+    /// Writes the project to a directory (which is created if needed), overwriting any existing files with the same names.
+    pub fn write_dir(&self, root: &Path) -> io::Result<()> {
+        let mut manifest = Manifest { roles: vec![] };
+        let mut used_dirs = BTreeSet::new();
+        for role in self.roles.iter() {
+            let base = dir_name(&role.name);
+            let dir = (1..).map(|i| if i == 1 { base.clone() } else { format!("{base}_{i}") }).find(|x| used_dirs.insert(x.to_lowercase())).unwrap();
+            fs::create_dir_all(root.join(&dir))?;
+
+            let mut editors = vec![];
+            for editor in role.editors.iter() {
+                let file = format!("{dir}/{}.py", editor.name);
+                fs::write(root.join(&file), &editor.value)?;
+                editors.push(EditorManifest { ty: editor.ty, name: &editor.name, file, source_map: &editor.source_map });
+            }
+
+            let mut images = BTreeMap::new();
+            if !role.images.is_empty() { fs::create_dir_all(root.join(&dir).join("images"))? }
+            for (name, image) in role.images.iter() {
+                let file = format!("{dir}/images/{name}.{}", image_extension(&image.img));
+                fs::write(root.join(&file), &image.img)?;
+                images.insert(name.as_str(), ImageManifest { file, center: image.center });
+            }
+
+            let mut sounds = BTreeMap::new();
+            if !role.sounds.is_empty() { fs::create_dir_all(root.join(&dir).join("sounds"))? }
+            for (name, sound) in role.sounds.iter() {
+                let file = format!("{dir}/sounds/{name}.{}", sound_extension(&sound.snd));
+                fs::write(root.join(&file), &sound.snd)?;
+                sounds.insert(name.as_str(), SoundManifest { file });
+            }
+
+            manifest.roles.push(RoleManifest {
+                name: &role.name,
+                notes: &role.notes,
+                path: dir,
+                stage_size: role.stage_size,
+                block_sources: &role.block_sources,
+                blocks: &role.blocks,
+                imports: &role.imports,
+                editors,
+                images,
+                sounds,
+                names: &role.names,
+            });
+        }
+        fs::write(root.join("manifest.json"), serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?)
+    }
+}
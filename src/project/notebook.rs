@@ -0,0 +1,149 @@
+//! Assembling a role into a Jupyter notebook.
+//!
+//! The notebook starts with the project notes, then a cell which sets up the same environment as [`Role::to_script`].
+//! Each editor is split into its top-level chunks, so that every custom block and hat block script gets a code cell of its own.
+//! Python classes cannot be spread over several cells, so each stage or sprite cell extends the class from the cell before it,
+//! and the final class is decorated and instantiated once all of its cells have run.
+
+use base64::engine::{Engine as _, general_purpose::STANDARD};
+use serde_json::{Value, json};
+
+use super::*;
+use super::script::indent;
+use super::dir::image_extension;
+use crate::chunks::{Chunk, split_chunks};
+
+/// Splits text into the list of lines stored as the source of a cell, where every line but the last keeps its newline.
+fn cell_source(text: &str) -> Vec<&str> {
+    text.trim_end().split_inclusive('\n').collect()
+}
+
+fn code_cell(code: &str) -> Value {
+    json!({
+        "cell_type": "code",
+        "execution_count": null,
+        "metadata": {},
+        "outputs": [],
+        "source": cell_source(code),
+    })
+}
+
+fn markdown_cell(text: &str, attachments: serde_json::Map<String, Value>) -> Value {
+    let mut cell = json!({
+        "cell_type": "markdown",
+        "metadata": {},
+        "source": cell_source(text),
+    });
+    if !attachments.is_empty() { cell["attachments"] = Value::Object(attachments) }
+    cell
+}
+
+/// Turns a chunk which is only comments into markdown text.
+fn comment_text(lines: &[&str]) -> String {
+    let text: Vec<_> = lines.iter().map(|x| {
+        let x = x.trim_start_matches('#');
+        x.strip_prefix(' ').unwrap_or(x)
+    }).collect();
+    text.join("\n")
+}
+
+/// Gets the cells for the globals editor, which has a cell for each definition and each run of other statements.
+fn globals_cells(editor: &Editor, cells: &mut Vec<Value>) {
+    let lines: Vec<_> = editor.value.lines().collect();
+    let mut run: Option<(usize, usize)> = None; // the lines of consecutive statements which go in the same cell
+    let flush = |run: &mut Option<(usize, usize)>, cells: &mut Vec<Value>| if let Some((start, end)) = run.take() {
+        cells.push(code_cell(&lines[start..end].join("\n")));
+    };
+    for chunk in split_chunks(&lines) {
+        if chunk.key.starts_with('#') {
+            flush(&mut run, cells);
+            cells.push(markdown_cell(&comment_text(&chunk.text(&lines)), Default::default()));
+        } else if chunk.def_name.is_some() {
+            flush(&mut run, cells);
+            cells.push(code_cell(&chunk.text(&lines).join("\n")));
+        } else {
+            run.get_or_insert((chunk.start, chunk.end)).1 = chunk.end;
+        }
+    }
+    flush(&mut run, cells);
+}
+
+/// Gets the cells for a stage or sprite editor.
+/// The first cell defines the class with its fields and `__init__`, and every other definition gets a cell which extends the class.
+fn entity_cells(editor: &Editor, cells: &mut Vec<Value>) {
+    let lines: Vec<_> = editor.value.lines().collect();
+    let name = &editor.name;
+    let (decorator, base) = if editor.ty == EditorType::Stage { ("stage", "StageBase") } else { ("sprite", "SpriteBase") };
+
+    let is_member = |chunk: &Chunk| !chunk.key.starts_with('#') && chunk.def_name.as_deref().is_none_or(|x| x == "__init__");
+    let chunks = split_chunks(&lines);
+    let members: Vec<_> = chunks.iter().filter(|x| is_member(x)).map(|x| indent(&x.text(&lines).join("\n"))).collect();
+    let members = if members.is_empty() { "    pass\n".into() } else { members.join("\n") };
+    cells.push(code_cell(&format!("class {name}({base}):\n{members}")));
+
+    for chunk in chunks.iter().filter(|x| !is_member(x)) {
+        let text = chunk.text(&lines);
+        match chunk.key.starts_with('#') {
+            true => cells.push(markdown_cell(&comment_text(&text), Default::default())),
+            false => cells.push(code_cell(&format!("class {name}({name}):\n{}", indent(&text.join("\n"))))),
+        }
+    }
+
+    cells.push(code_cell(&format!("{name} = {decorator}({name})()")));
+}
+
+impl Role {
+    /// Gets a markdown cell which introduces a stage or sprite and previews its costumes.
+    fn costumes_cell(&self, editor: &Editor) -> Value {
+        let mut text = format!("## {}\n", editor.name);
+        let mut attachments = serde_json::Map::new();
+        let costumes = self.names.sprites.values().find(|x| x.name == editor.name).map(|x| &x.costumes);
+        for (costume, key) in costumes.into_iter().flatten() {
+            let Some(image) = self.images.get(key) else { continue };
+            let file = format!("{key}.{}", image_extension(&image.img));
+            let mime = image::guess_format(&image.img).map(|x| x.to_mime_type()).unwrap_or("application/octet-stream");
+            text += &format!("\n![{0}](attachment:{file} \"{0}\")", costume.replace(['[', ']', '"'], ""));
+            attachments.insert(file, json!({ mime: STANDARD.encode(&image.img) }));
+        }
+        markdown_cell(&text, attachments)
+    }
+
+    /// Assembles the role into a Jupyter notebook (nbformat 4), which runs the role with the `netsblox` python package.
+    /// Costumes are previewed in a markdown cell for each sprite, and every custom block and hat block script has its own code cell.
+    pub fn to_notebook(&self) -> String {
+        let mut cells = vec![];
+        let intro = match self.notes.trim() {
+            "" => format!("# {}", self.name),
+            notes => format!("# {}\n\n{notes}", self.name),
+        };
+        cells.push(markdown_cell(&intro, Default::default()));
+        cells.push(code_cell(&self.setup_code()));
+
+        for editor in self.editors.iter() {
+            match editor.ty {
+                EditorType::Globals => {
+                    cells.push(markdown_cell("## Globals", Default::default()));
+                    globals_cells(editor, &mut cells);
+                }
+                EditorType::Stage | EditorType::Sprite => {
+                    cells.push(self.costumes_cell(editor));
+                    entity_cells(editor, &mut cells);
+                }
+            }
+        }
+
+        cells.push(markdown_cell("## Run", Default::default()));
+        cells.push(code_cell("start_project()"));
+
+        let notebook = json!({
+            "cells": cells,
+            "metadata": {
+                "kernelspec": { "display_name": "Python 3", "language": "python", "name": "python3" },
+                "language_info": { "name": "python" },
+            },
+            "nbformat": 4,
+            "nbformat_minor": 4,
+        });
+        serde_json::to_string_pretty(&notebook).unwrap()
+    }
+}
@@ -0,0 +1,96 @@
+//! Assembling a role into a single python script which runs outside of the PyBlox IDE.
+//!
+//! PyBlox runs the code of each editor with some names already defined (`nb`, `snap`, `onstart`, `nothrow`, `NoYield`, `globals`, `images`, `sounds`, etc.).
+//! The script defines these explicitly using the `netsblox` python package, turns the stage and each sprite into a class, and starts the project from `main`.
+
+use std::fmt::Write;
+
+use base64::engine::{Engine as _, general_purpose::STANDARD};
+
+use super::*;
+
+const PRELUDE: &str = r#"import base64
+import io
+import sys
+import types
+
+import PIL.Image
+
+import netsblox
+from netsblox import get_location, get_error, nothrow, Namespace
+from netsblox.turtle import *
+from netsblox.concurrency import *
+
+nb = netsblox.Client()
+
+# global variables are accessed as attributes of this module (e.g., globals.score)
+globals = sys.modules[__name__]
+
+def _image(data, center):
+    img = PIL.Image.open(io.BytesIO(base64.b64decode(data)))
+    img.info['center'] = center
+    return img
+
+def _sound(data):
+    return io.BytesIO(base64.b64decode(data))
+"#;
+
+/// Indents every non-blank line of some code by one level.
+pub(super) fn indent(code: &str) -> String {
+    let mut res = String::new();
+    for line in code.trim_end().lines() {
+        if !line.trim().is_empty() { res += "    " }
+        res += line;
+        res.push('\n');
+    }
+    res
+}
+
+impl Role {
+    /// Gets the code which defines everything the editors expect to exist before they run, including the imports and assets of the role.
+    pub(super) fn setup_code(&self) -> String {
+        let mut res = String::from(PRELUDE);
+        if !self.imports.is_empty() { res.push('\n') }
+        for import in self.imports.iter() {
+            writeln!(res, "import {import}").unwrap();
+        }
+
+        res += "\nimages = types.SimpleNamespace(\n";
+        for (name, image) in self.images.iter() {
+            writeln!(res, "    {name}=_image('{}', ({:?}, {:?})),", STANDARD.encode(&image.img), image.center.0, image.center.1).unwrap();
+        }
+        res += ")\nsounds = types.SimpleNamespace(\n";
+        for (name, sound) in self.sounds.iter() {
+            writeln!(res, "    {name}=_sound('{}'),", STANDARD.encode(&sound.snd)).unwrap();
+        }
+        res += ")\n";
+        res
+    }
+
+    /// Assembles the role into a single python script, which can be run with the `netsblox` python package installed.
+    /// Costumes and sounds are embedded in the script, so it has no other dependencies.
+    pub fn to_script(&self) -> String {
+        let mut res = String::new();
+        writeln!(res, "#!/usr/bin/env python").unwrap();
+        writeln!(res, "# the NetsBlox role '{}', translated by nb2pb\n", self.name.replace('\n', " ")).unwrap();
+        res += &self.setup_code();
+
+        for editor in self.editors.iter() {
+            res += "\n# ------------------------------------------------\n\n";
+            match editor.ty {
+                EditorType::Globals => res += editor.value.trim_end(),
+                EditorType::Stage | EditorType::Sprite => {
+                    let (decorator, base) = if editor.ty == EditorType::Stage { ("stage", "StageBase") } else { ("sprite", "SpriteBase") };
+                    writeln!(res, "@{decorator}\nclass {}({base}):", editor.name).unwrap();
+                    res += indent(&editor.value).trim_end();
+                    write!(res, "\n\n{0} = {0}()", editor.name).unwrap();
+                }
+            }
+            res.push('\n');
+        }
+
+        res += "\n# ------------------------------------------------\n\n";
+        res += "def main():\n    start_project()\n\nif __name__ == '__main__':\n    main()\n";
+        res
+    }
+}
@@ -0,0 +1,133 @@
+//! Checks for PyBlox projects which go beyond the JSON schema, such as references to missing assets.
+
+use std::collections::BTreeSet;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use super::*;
+
+/// Matches a reference to an asset in python code, such as `images.Sprite_cst_untitled`.
+/// Attribute accesses like `self.sounds.add` are not asset references, so the name must not follow a dot.
+static ASSET_REF_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|[^\w.])(images|sounds)\.(\w+)").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AssetKind {
+    Image,
+    Sound,
+}
+
+/// A reference to an asset in python code.
+pub(crate) struct AssetRef<'a> {
+    pub kind: AssetKind,
+    pub name: &'a str,
+    /// The line of the reference (one-based).
+    pub line: usize,
+}
+
+/// Finds every reference to an image or sound in some python code.
+pub(crate) fn asset_refs(code: &str) -> impl Iterator<Item = AssetRef<'_>> {
+    code.lines().enumerate().flat_map(|(i, line)| ASSET_REF_REGEX.captures_iter(line).map(move |refs| AssetRef {
+        kind: if &refs[1] == "images" { AssetKind::Image } else { AssetKind::Sound },
+        name: refs.get(2).unwrap().as_str(),
+        line: i + 1,
+    }))
+}
+
+/// A problem found by [`Project::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    NoRoles,
+    DuplicateRole { role: CompactString },
+    /// The stage has a zero width or height.
+    EmptyStage { role: CompactString },
+    /// The editors must be the globals, then the stage, then any number of sprites.
+    EditorOrder { role: CompactString },
+    /// A stage or sprite editor whose name is not a valid python identifier.
+    InvalidEditorName { role: CompactString, editor: CompactString },
+    DuplicateEditor { role: CompactString, editor: CompactString },
+    /// The code of an editor refers to `images.{name}`, but the role has no such image.
+    MissingImage { role: CompactString, editor: CompactString, line: usize, name: CompactString },
+    /// The code of an editor refers to `sounds.{name}`, but the role has no such sound.
+    MissingSound { role: CompactString, editor: CompactString, line: usize, name: CompactString },
+    /// A source map entry refers to lines which are not in the editor.
+    SourceMapRange { role: CompactString, editor: CompactString, lines: (usize, usize) },
+}
+
+/// An error from [`Project::parse`].
+#[derive(Debug)]
+pub enum ProjectError {
+    /// The JSON is malformed or does not match the PyBlox schema.
+    Json(serde_json::Error),
+    Invalid(Vec<ValidationError>),
+}
+impl From<serde_json::Error> for ProjectError { fn from(e: serde_json::Error) -> Self { Self::Json(e) } }
+
+impl Project {
+    /// Parses a PyBlox project from JSON and checks it with [`Project::validate`].
+    pub fn parse(source: &str) -> Result<Self, ProjectError> {
+        let project: Self = serde_json::from_str(source)?;
+        match project.validate().as_slice() {
+            [] => Ok(project),
+            errors => Err(ProjectError::Invalid(errors.to_vec())),
+        }
+    }
+    /// Checks the project for problems which the JSON schema cannot express, returning every problem found.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = vec![];
+        if self.roles.is_empty() { errors.push(ValidationError::NoRoles) }
+
+        let mut role_names = BTreeSet::new();
+        for role in self.roles.iter() {
+            if !role_names.insert(&role.name) {
+                errors.push(ValidationError::DuplicateRole { role: role.name.clone() });
+            }
+            role.validate(&mut errors);
+        }
+
+        errors
+    }
+}
+
+impl Role {
+    fn validate(&self, errors: &mut Vec<ValidationError>) {
+        let role = &self.name;
+        if self.stage_size.0 == 0 || self.stage_size.1 == 0 {
+            errors.push(ValidationError::EmptyStage { role: role.clone() });
+        }
+
+        let types: Vec<_> = self.editors.iter().map(|x| x.ty).collect();
+        if !matches!(types.as_slice(), [EditorType::Globals, EditorType::Stage, rest @ ..] if rest.iter().all(|x| *x == EditorType::Sprite)) {
+            errors.push(ValidationError::EditorOrder { role: role.clone() });
+        }
+
+        let mut editor_names = BTreeSet::new();
+        for editor in self.editors.iter() {
+            if editor.ty != EditorType::Globals && !crate::names::is_unicode_ident(&editor.name) {
+                errors.push(ValidationError::InvalidEditorName { role: role.clone(), editor: editor.name.clone() });
+            }
+            if !editor_names.insert(&editor.name) {
+                errors.push(ValidationError::DuplicateEditor { role: role.clone(), editor: editor.name.clone() });
+            }
+
+            for asset in asset_refs(&editor.value) {
+                let name = CompactString::from(asset.name);
+                match asset.kind {
+                    AssetKind::Image => if !self.images.contains_key(&name) {
+                        errors.push(ValidationError::MissingImage { role: role.clone(), editor: editor.name.clone(), line: asset.line, name });
+                    }
+                    AssetKind::Sound => if !self.sounds.contains_key(&name) {
+                        errors.push(ValidationError::MissingSound { role: role.clone(), editor: editor.name.clone(), line: asset.line, name });
+                    }
+                }
+            }
+
+            let line_count = editor.value.lines().count();
+            for entry in editor.source_map.iter() {
+                if entry.lines.0 == 0 || entry.lines.0 > entry.lines.1 || entry.lines.1 > line_count {
+                    errors.push(ValidationError::SourceMapRange { role: role.clone(), editor: editor.name.clone(), lines: entry.lines });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,658 @@
+//! A static HTML report which shows every script of a project as Snap-style pseudo code next to its python translation.
+//!
+//! A single unsupported block would normally fail the whole translation, so the report translates each script and custom block on its own
+//! to find the ones which fail, and then translates the project without them. Failed scripts are shown with the reason they failed and the offending block highlighted,
+//! and warning comments in the generated code (such as those added by [`TranslateOptions::idiomatic`]) are highlighted as well.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use base64::engine::{Engine as _, general_purpose::STANDARD};
+use compact_str::CompactString;
+use netsblox_ast::*;
+
+use crate::{TranslateError, TranslateOptions, names, project};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h2 { border-bottom: 2px solid #ccc; }
+.costumes img { max-height: 64px; max-width: 96px; margin: 4px; border: 1px solid #ccc; background: #f8f8f8; }
+.script { margin: 1em 0 2em; }
+.columns { display: flex; gap: 1em; }
+.columns pre { flex: 1; margin: 0; padding: 0.75em; border-radius: 4px; overflow-x: auto; }
+.blocks { background: #f4f0fa; }
+.python { background: #f4f4f4; }
+.blocks img { max-height: 1.5em; vertical-align: middle; }
+.comment { color: #777; }
+.error { background: #fde2e2; border-left: 4px solid #d33; padding: 0.5em; }
+mark.unsupported { background: #f8b4b4; }
+mark.warning { background: #fff0a8; }
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Gets an inline thumbnail of an image.
+fn thumbnail(img: &[u8], name: &str) -> String {
+    let mime = image::guess_format(img).map(|x| x.to_mime_type()).unwrap_or("application/octet-stream");
+    format!("<img src=\"data:{mime};base64,{}\" title=\"{1}\" alt=\"{1}\">", STANDARD.encode(img), escape(name))
+}
+
+/// Explains why a script could not be translated.
+fn describe(error: &TranslateError) -> String {
+    match error {
+        TranslateError::Parse(e) => format!("The project could not be parsed: {e:?}"),
+        TranslateError::NoRoles => "The project has no roles.".into(),
+        TranslateError::UnsupportedExpr(_) | TranslateError::UnsupportedStmt(_) => "The highlighted block is not supported.".into(),
+        TranslateError::UnsupportedHat(_) => "This hat block is not supported.".into(),
+        TranslateError::UnknownImageFormat => "A costume has an unknown image format.".into(),
+        TranslateError::Upvars => "Custom blocks with upvar inputs are not supported.".into(),
+        TranslateError::AnyMessage => "Receiving any message is not supported.".into(),
+        TranslateError::RingTypeQuery => "Checking if a value is a ring is not supported.".into(),
+        TranslateError::CommandRing => "Rings are only supported if they report a single value.".into(),
+        TranslateError::TellAskClosure => "The tell and ask blocks are not supported.".into(),
+        TranslateError::ReplyOutsideMessageHandler => "Replies can only be sent from a script which receives a network message.".into(),
+    }
+}
+
+/// A script or custom block, which is translated (or fails to translate) as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Unit {
+    Script { role: usize, entity: usize, script: usize },
+    /// A custom block, which is global if it has no entity.
+    Func { role: usize, entity: Option<usize>, func: usize },
+}
+
+fn units(project: &Project) -> Vec<Unit> {
+    let mut res = vec![];
+    for (role, src) in project.roles.iter().enumerate() {
+        for (entity, sprite) in src.entities.iter().enumerate() {
+            res.extend((0..sprite.funcs.len()).map(|func| Unit::Func { role, entity: Some(entity), func }));
+            res.extend(sprite.scripts.iter().enumerate().filter(|x| x.1.hat.is_some()).map(|(script, _)| Unit::Script { role, entity, script }));
+        }
+        res.extend((0..src.funcs.len()).map(|func| Unit::Func { role, entity: None, func }));
+    }
+    res
+}
+
+/// Translates a project with only some of its scripts and custom blocks.
+/// Scripts which are left out are not translated at all, while custom blocks which are left out are translated with an empty body so that calls to them still work.
+fn translate_units(project: &Project, source: &str, options: &TranslateOptions, units: &[Unit], keep: impl Fn(Unit) -> bool) -> Result<project::Project, TranslateError> {
+    let mut project = project.clone();
+    for &unit in units.iter().filter(|x| !keep(**x)) {
+        match unit {
+            Unit::Script { role, entity, script } => project.roles[role].entities[entity].scripts[script].hat = None,
+            Unit::Func { role, entity: Some(entity), func } => project.roles[role].entities[entity].funcs[func].stmts.clear(),
+            Unit::Func { role, entity: None, func } => project.roles[role].funcs[func].stmts.clear(),
+        }
+    }
+    crate::translate_ast(&project, source, options)
+}
+
+/// Gets the code of every script in an editor, keyed by its name in the source map, in the order they appear.
+/// Each script also has the collaboration id of its hat block, if it has one (custom block definitions do not).
+fn editor_scripts(editor: &project::Editor) -> Vec<(&str, Option<&str>, String)> {
+    let mut ranges: Vec<(&str, Option<&str>, usize, usize)> = vec![];
+    for entry in editor.source_map.iter() {
+        let hat = entry.block.as_deref().filter(|_| entry.path.is_empty());
+        match ranges.iter_mut().find(|x| x.0 == entry.script) {
+            Some(range) => *range = (range.0, range.1.or(hat), range.2.min(entry.lines.0), range.3.max(entry.lines.1)),
+            None => ranges.push((&entry.script, hat, entry.lines.0, entry.lines.1)),
+        }
+    }
+    let lines: Vec<_> = editor.value.lines().collect();
+    ranges.into_iter().map(|(script, hat, start, end)| (script, hat, lines[start - 1..end].join("\n"))).collect()
+}
+
+/// Renders python code, highlighting any warning comments.
+fn python_html(code: &str) -> String {
+    let lines: Vec<_> = code.lines().map(|line| match line.trim_start().starts_with("# warning:") {
+        true => format!("<mark class=\"warning\">{}</mark>", escape(line)),
+        false => escape(line),
+    }).collect();
+    lines.join("\n")
+}
+
+/// Renders blocks as Snap-style pseudo code, where reporters are in `()`, predicates in `<>`, and text in `[]`.
+struct Blocks<'a> {
+    lines: Vec<String>,
+    /// The reason the script could not be translated, which is used to highlight the offending block.
+    error: Option<&'a TranslateError>,
+    /// The debug form of the block named by [`Blocks::error`], if it names one.
+    culprit: Option<String>,
+    /// If true, the script receives network messages, so it may send replies.
+    msg_handler: bool,
+}
+impl<'a> Blocks<'a> {
+    fn new(error: Option<&'a TranslateError>) -> Self {
+        let culprit = match error {
+            Some(TranslateError::UnsupportedExpr(x)) => Some(format!("{x:?}")),
+            Some(TranslateError::UnsupportedStmt(x)) => Some(format!("{x:?}")),
+            Some(TranslateError::UnsupportedHat(x)) => Some(format!("{x:?}")),
+            _ => None,
+        };
+        Self { lines: vec![], error, culprit, msg_handler: false }
+    }
+    fn is_culprit(&self, block: &impl std::fmt::Debug) -> bool {
+        self.culprit.as_ref().is_some_and(|x| *x == format!("{block:?}"))
+    }
+    fn highlight(&self, html: String, flagged: bool) -> String {
+        if flagged { format!("<mark class=\"unsupported\">{html}</mark>") } else { html }
+    }
+    fn push(&mut self, depth: usize, html: String, info: &BlockInfo) {
+        let mut line = "    ".repeat(depth) + &html;
+        if let Some(comment) = &info.comment {
+            write!(line, " <span class=\"comment\">// {}</span>", escape(&comment.replace('\n', " "))).unwrap();
+        }
+        self.lines.push(line);
+    }
+
+    fn hat(&mut self, hat: &Hat) {
+        let text = match &hat.kind {
+            HatKind::OnFlag => "when green flag clicked".into(),
+            HatKind::OnClone => "when I start as a clone".into(),
+            HatKind::OnKey { key } => format!("when [{}] key pressed", escape(key)),
+            HatKind::MouseDown => "when mouse is pressed".into(),
+            HatKind::MouseUp => "when mouse is released".into(),
+            HatKind::MouseEnter => "when mouse enters".into(),
+            HatKind::MouseLeave => "when mouse leaves".into(),
+            HatKind::ScrollUp => "when scrolled up".into(),
+            HatKind::ScrollDown => "when scrolled down".into(),
+            HatKind::Dropped => "when I am dropped".into(),
+            HatKind::Stopped => "when stopped".into(),
+            HatKind::When { condition } => format!("when {}", self.expr(condition)),
+            HatKind::LocalMessage { msg_type } => format!("when I receive [{}]", escape(msg_type.as_deref().unwrap_or("any message"))),
+            HatKind::NetworkMessage { msg_type, fields } => {
+                self.msg_handler = true;
+                let fields: String = fields.iter().map(|x| format!(" ({})", escape(&x.name))).collect();
+                format!("when I receive [{}]{fields}", escape(msg_type))
+            }
+            HatKind::Unknown { name, .. } => escape(name),
+        };
+        let flagged = self.is_culprit(hat) || matches!(hat.kind, HatKind::Unknown { .. })
+            || (matches!(self.error, Some(TranslateError::AnyMessage)) && matches!(hat.kind, HatKind::LocalMessage { msg_type: None }));
+        let html = self.highlight(text, flagged);
+        self.push(0, html, &hat.info);
+    }
+
+    /// Renders the header of a custom block definition, with each input in `()`.
+    fn define(&mut self, func: &Function) {
+        let mut params = func.params.iter();
+        let spec: String = func.name.chars().map(|ch| match (ch, ch == '\t') {
+            (_, true) => params.next().map(|x| format!("({})", escape(&x.name))).unwrap_or_default(),
+            (ch, false) => escape(&ch.to_string()),
+        }).collect();
+        self.lines.push(format!("define {spec}"));
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt], depth: usize) {
+        for stmt in stmts {
+            self.stmt(stmt, depth);
+        }
+    }
+    /// Renders a block with one or more slots for other blocks, such as `if` and `else`.
+    fn c_block(&mut self, depth: usize, header: String, stmt: &Stmt, parts: &[(&str, &[Stmt])]) {
+        let flagged = self.is_culprit(stmt);
+        let html = self.highlight(header, flagged);
+        self.push(depth, html, &stmt.info);
+        for (i, (label, stmts)) in parts.iter().enumerate() {
+            if i != 0 { self.lines.push("    ".repeat(depth) + label) }
+            self.stmts(stmts, depth + 1);
+        }
+        self.lines.push("    ".repeat(depth) + "end");
+    }
+    fn stmt(&mut self, stmt: &Stmt, depth: usize) {
+        let text = match &stmt.kind {
+            StmtKind::Warp { stmts } => return self.c_block(depth, "warp".into(), stmt, &[("", stmts)]),
+            StmtKind::InfLoop { stmts } => return self.c_block(depth, "forever".into(), stmt, &[("", stmts)]),
+            StmtKind::ForeachLoop { var, items, stmts } => {
+                let header = format!("for each ({}) in {}", escape(&var.name), self.expr(items));
+                return self.c_block(depth, header, stmt, &[("", stmts)]);
+            }
+            StmtKind::ForLoop { var, start, stop, stmts } => {
+                let header = format!("for ({}) = {} to {}", escape(&var.name), self.expr(start), self.expr(stop));
+                return self.c_block(depth, header, stmt, &[("", stmts)]);
+            }
+            StmtKind::UntilLoop { condition, stmts } => {
+                let header = format!("repeat until {}", self.expr(condition));
+                return self.c_block(depth, header, stmt, &[("", stmts)]);
+            }
+            StmtKind::Repeat { times, stmts } => {
+                let header = format!("repeat {}", self.expr(times));
+                return self.c_block(depth, header, stmt, &[("", stmts)]);
+            }
+            StmtKind::If { condition, then } => {
+                let header = format!("if {}", self.expr(condition));
+                return self.c_block(depth, header, stmt, &[("", then)]);
+            }
+            StmtKind::IfElse { condition, then, otherwise } => {
+                let header = format!("if {}", self.expr(condition));
+                return self.c_block(depth, header, stmt, &[("", then), ("else", otherwise)]);
+            }
+            StmtKind::TryCatch { code, var, handler } => {
+                let catch = format!("catch ({})", escape(&var.name));
+                return self.c_block(depth, "try".into(), stmt, &[("", code), (&catch, handler)]);
+            }
+
+            StmtKind::DeclareLocals { vars } => format!("script variables{}", vars.iter().map(|x| format!(" ({})", escape(&x.name))).collect::<String>()),
+            StmtKind::Assign { var, value } => format!("set [{}] to {}", escape(&var.name), self.expr(value)),
+            StmtKind::AddAssign { var, value } => format!("change [{}] by {}", escape(&var.name), self.expr(value)),
+            StmtKind::ShowVar { var } => format!("show variable [{}]", escape(&var.name)),
+            StmtKind::HideVar { var } => format!("hide variable [{}]", escape(&var.name)),
+            StmtKind::Throw { error } => format!("error {}", self.expr(error)),
+
+            StmtKind::ListInsert { list, value, index } => format!("insert {} at {} of {}", self.expr(value), self.expr(index), self.expr(list)),
+            StmtKind::ListInsertLast { list, value } => format!("add {} to {}", self.expr(value), self.expr(list)),
+            StmtKind::ListInsertRandom { list, value } => format!("insert {} at (random) of {}", self.expr(value), self.expr(list)),
+            StmtKind::ListRemove { list, index } => format!("delete {} of {}", self.expr(index), self.expr(list)),
+            StmtKind::ListRemoveLast { list } => format!("delete (last) of {}", self.expr(list)),
+            StmtKind::ListRemoveAll { list } => format!("delete (all) of {}", self.expr(list)),
+            StmtKind::ListAssign { list, value, index } => format!("replace item {} of {} with {}", self.expr(index), self.expr(list), self.expr(value)),
+            StmtKind::ListAssignLast { list, value } => format!("replace item (last) of {} with {}", self.expr(list), self.expr(value)),
+            StmtKind::ListAssignRandom { list, value } => format!("replace item (random) of {} with {}", self.expr(list), self.expr(value)),
+
+            StmtKind::Return { value } => format!("report {}", self.expr(value)),
+            StmtKind::Sleep { seconds } => format!("wait {} secs", self.expr(seconds)),
+            StmtKind::WaitUntil { condition } => format!("wait until {}", self.expr(condition)),
+
+            StmtKind::SetCostume { costume } => format!("switch to costume {}", self.expr(costume)),
+            StmtKind::NextCostume => "next costume".into(),
+            StmtKind::PlaySound { sound, blocking } => format!("play sound {}{}", self.expr(sound), if *blocking { " until done" } else { "" }),
+            StmtKind::PlayNotes { notes, beats, blocking } => format!("play note {} for {} beats{}", self.expr(notes), self.expr(beats), if *blocking { "" } else { " (async)" }),
+            StmtKind::Rest { beats } => format!("rest for {} beats", self.expr(beats)),
+            StmtKind::StopSounds => "stop all sounds".into(),
+
+            StmtKind::Forward { distance } => format!("move {} steps", self.expr(distance)),
+            StmtKind::SetX { value } => format!("set x to {}", self.expr(value)),
+            StmtKind::ChangeX { delta } => format!("change x by {}", self.expr(delta)),
+            StmtKind::SetY { value } => format!("set y to {}", self.expr(value)),
+            StmtKind::ChangeY { delta } => format!("change y by {}", self.expr(delta)),
+            StmtKind::GotoXY { x, y } => format!("go to x: {} y: {}", self.expr(x), self.expr(y)),
+            StmtKind::GotoMouse => "go to [mouse-pointer]".into(),
+            StmtKind::GotoRandom => "go to [random position]".into(),
+            StmtKind::Goto { target } => format!("go to {}", self.expr(target)),
+            StmtKind::PointTowards { target } => format!("point towards {}", self.expr(target)),
+            StmtKind::PointTowardsXY { x, y } => format!("point towards x: {} y: {}", self.expr(x), self.expr(y)),
+            StmtKind::TurnRight { angle } => format!("turn right {} degrees", self.expr(angle)),
+            StmtKind::TurnLeft { angle } => format!("turn left {} degrees", self.expr(angle)),
+            StmtKind::SetHeading { value } => format!("point in direction {}", self.expr(value)),
+            StmtKind::SetHeadingRandom => "point in direction (random)".into(),
+            StmtKind::BounceOffEdge => "if on edge, bounce".into(),
+
+            StmtKind::SetPenDown { value } => if *value { "pen down" } else { "pen up" }.into(),
+            StmtKind::PenClear => "clear".into(),
+            StmtKind::Stamp => "stamp".into(),
+            StmtKind::Write { content, font_size } => format!("write {} size {}", self.expr(content), self.expr(font_size)),
+            StmtKind::SetPenColor { color } => format!("set pen color to [#{:02x}{:02x}{:02x}]", color.0, color.1, color.2),
+            StmtKind::ChangePenSize { delta } => format!("change pen size by {}", self.expr(delta)),
+            StmtKind::SetPenSize { value } => format!("set pen size to {}", self.expr(value)),
+            StmtKind::SetPenAttr { attr, value } => format!("set pen [{}] to {}", lower(attr), self.expr(value)),
+            StmtKind::ChangePenAttr { attr, delta } => format!("change pen [{}] by {}", lower(attr), self.expr(delta)),
+
+            StmtKind::Say { content, duration } => match duration {
+                Some(duration) => format!("say {} for {} secs", self.expr(content), self.expr(duration)),
+                None => format!("say {}", self.expr(content)),
+            }
+            StmtKind::Think { content, duration } => match duration {
+                Some(duration) => format!("think {} for {} secs", self.expr(content), self.expr(duration)),
+                None => format!("think {}", self.expr(content)),
+            }
+            StmtKind::SetVisible { value } => if *value { "show" } else { "hide" }.into(),
+            StmtKind::ChangeSize { delta } => format!("change size by {}", self.expr(delta)),
+            StmtKind::SetSize { value } => format!("set size to {} %", self.expr(value)),
+            StmtKind::SetEffect { kind, value } => format!("set [{}] effect to {}", lower(kind), self.expr(value)),
+            StmtKind::ChangeEffect { kind, delta } => format!("change [{}] effect by {}", lower(kind), self.expr(delta)),
+            StmtKind::ClearEffects => "clear graphic effects".into(),
+
+            StmtKind::CallRpc { service, rpc, args, .. } => format!("call {}", self.rpc(service, rpc, args)),
+            StmtKind::CallFn { function, args, upvars } => self.fn_call(function, args, upvars),
+            StmtKind::CallClosure { new_entity: Some(entity), closure, args } => format!("tell {} to {}{}", self.expr(entity), self.expr(closure), self.inputs(args)),
+            StmtKind::CallClosure { new_entity: None, closure, args } => format!("run {}{}", self.expr(closure), self.inputs(args)),
+            StmtKind::ForkClosure { closure, args } => format!("launch {}{}", self.expr(closure), self.inputs(args)),
+
+            StmtKind::Clone { target } => format!("create a clone of {}", self.expr(target)),
+            StmtKind::DeleteClone => "delete this clone".into(),
+            StmtKind::SendLocalMessage { target, msg_type, wait } => format!("broadcast {}{}{}",
+                self.expr(msg_type), target.as_ref().map(|x| format!(" to {}", self.expr(x))).unwrap_or_default(), if *wait { " and wait" } else { "" }),
+            StmtKind::SendNetworkMessage { target, msg_type, values } => format!("send msg [{}]{} to {}", escape(msg_type), self.fields(values), self.expr(target)),
+            StmtKind::SendNetworkReply { value } => format!("send reply {}", self.expr(value)),
+            StmtKind::Ask { prompt } => format!("ask {} and wait", self.expr(prompt)),
+            StmtKind::ResetTimer => "reset timer".into(),
+            StmtKind::Pause => "pause all".into(),
+            StmtKind::Stop { mode } => format!("stop [{}]", lower(mode)),
+            StmtKind::UnknownBlock { name, args } => format!("{}{}", escape(name), self.inputs(args)),
+        };
+        let flagged = self.is_culprit(stmt) || match &stmt.kind {
+            StmtKind::CallFn { upvars, .. } => !upvars.is_empty() && matches!(self.error, Some(TranslateError::Upvars)),
+            StmtKind::CallClosure { new_entity, .. } => new_entity.is_some() && matches!(self.error, Some(TranslateError::TellAskClosure)),
+            StmtKind::SendNetworkReply { .. } => !self.msg_handler && matches!(self.error, Some(TranslateError::ReplyOutsideMessageHandler)),
+            _ => false,
+        };
+        let html = self.highlight(text, flagged);
+        self.push(depth, html, &stmt.info);
+    }
+
+    fn inputs(&mut self, args: &[Expr]) -> String {
+        match args {
+            [] => String::new(),
+            _ => format!(" with inputs {}", args.iter().map(|x| self.expr(x)).collect::<Vec<_>>().join(" ")),
+        }
+    }
+    fn fields(&mut self, values: &[(CompactString, Expr)]) -> String {
+        values.iter().map(|(name, value)| format!(" {}: {}", escape(name), self.expr(value))).collect()
+    }
+    fn rpc(&mut self, service: &str, rpc: &str, args: &[(CompactString, Expr)]) -> String {
+        format!("[{}] [{}]{}", escape(service), escape(rpc), self.fields(args))
+    }
+    /// Renders a call to a custom block, with its inputs in place of its parameters.
+    fn fn_call(&mut self, function: &FnRef, args: &[Expr], upvars: &[VariableRef]) -> String {
+        let mut args = args.iter();
+        let mut upvars = upvars.iter();
+        let mut res = String::new();
+        for ch in function.name.chars() {
+            match ch {
+                '\t' => match args.next() {
+                    Some(arg) => res += &self.expr(arg),
+                    None => res += &upvars.next().map(|x| format!("({})", escape(&x.name))).unwrap_or_default(),
+                }
+                _ => res += &escape(&ch.to_string()),
+            }
+        }
+        res
+    }
+    /// Renders the operands of an operator which takes any number of inputs, such as `+`.
+    fn variadic(&mut self, values: &Expr, op: &str) -> String {
+        match &values.kind {
+            ExprKind::MakeList { values } => format!("({})", values.iter().map(|x| self.expr(x)).collect::<Vec<_>>().join(&format!(" {} ", escape(op)))),
+            _ => format!("({} input list: {})", escape(op), self.expr(values)),
+        }
+    }
+    fn value(&mut self, value: &Value) -> String {
+        match value {
+            Value::Bool(x) => format!("&lt;{x}&gt;"),
+            Value::Number(x) => format!("({x})"),
+            Value::Constant(Constant::E) => "(e)".into(),
+            Value::Constant(Constant::Pi) => "(π)".into(),
+            Value::String(x) if crate::numeric_literal(x).is_some() => format!("({})", escape(x)),
+            Value::String(x) => format!("[{}]", escape(x)),
+            Value::Image(x) => thumbnail(&x.0, &x.2),
+            Value::Audio(x) => format!("[{}]", escape(&x.1)),
+            Value::List(items, _) => format!("(list{})", items.iter().map(|x| format!(" {}", self.value(x))).collect::<String>()),
+            Value::Ref(_) => "(ref)".into(),
+        }
+    }
+    fn expr(&mut self, expr: &Expr) -> String {
+        let unary = |this: &mut Self, name: &str, value: &Expr| format!("({name} {})", this.expr(value));
+        let binary = |this: &mut Self, left: &Expr, op: &str, right: &Expr| format!("({} {} {})", this.expr(left), escape(op), this.expr(right));
+        let pred = |this: &mut Self, left: &Expr, op: &str, right: &Expr| format!("&lt;{} {} {}&gt;", this.expr(left), escape(op), this.expr(right));
+        let text = match &expr.kind {
+            ExprKind::Value(value) => self.value(value),
+            ExprKind::Variable { var } => format!("({})", escape(&var.name)),
+
+            ExprKind::Add { values } => self.variadic(values, "+"),
+            ExprKind::Mul { values } => self.variadic(values, "×"),
+            ExprKind::Min { values } => self.variadic(values, "min"),
+            ExprKind::Max { values } => self.variadic(values, "max"),
+            ExprKind::StrCat { values } => match &values.kind {
+                ExprKind::MakeList { values } => format!("(join{})", values.iter().map(|x| format!(" {}", self.expr(x))).collect::<String>()),
+                _ => format!("(join input list: {})", self.expr(values)),
+            }
+            ExprKind::Sub { left, right } => binary(self, left, "−", right),
+            ExprKind::Div { left, right } => binary(self, left, "/", right),
+            ExprKind::Mod { left, right } => binary(self, left, "mod", right),
+            ExprKind::Pow { base, power } => binary(self, base, "^", power),
+            ExprKind::Log { value, base } => format!("(log {} of {})", self.expr(base), self.expr(value)),
+            ExprKind::Atan2 { y, x } => format!("(atan2 {} ÷ {})", self.expr(y), self.expr(x)),
+
+            ExprKind::And { left, right } => pred(self, left, "and", right),
+            ExprKind::Or { left, right } => pred(self, left, "or", right),
+            ExprKind::Not { value } => format!("&lt;not {}&gt;", self.expr(value)),
+            ExprKind::Conditional { condition, then, otherwise } => format!("(if {} then {} else {})", self.expr(condition), self.expr(then), self.expr(otherwise)),
+            ExprKind::Identical { left, right } => format!("&lt;is {} identical to {}&gt;", self.expr(left), self.expr(right)),
+            ExprKind::Eq { left, right } => pred(self, left, "=", right),
+            ExprKind::Neq { left, right } => pred(self, left, "≠", right),
+            ExprKind::Less { left, right } => pred(self, left, "<", right),
+            ExprKind::LessEq { left, right } => pred(self, left, "≤", right),
+            ExprKind::Greater { left, right } => pred(self, left, ">", right),
+            ExprKind::GreaterEq { left, right } => pred(self, left, "≥", right),
+
+            ExprKind::Random { a, b } => format!("(pick random {} to {})", self.expr(a), self.expr(b)),
+            ExprKind::Range { start, stop } => format!("(numbers from {} to {})", self.expr(start), self.expr(stop)),
+            ExprKind::Neg { value } => unary(self, "neg of", value),
+            ExprKind::Abs { value } => unary(self, "abs of", value),
+            ExprKind::Sign { value } => unary(self, "sign of", value),
+            ExprKind::Sqrt { value } => unary(self, "sqrt of", value),
+            ExprKind::Floor { value } => unary(self, "floor of", value),
+            ExprKind::Ceil { value } => unary(self, "ceiling of", value),
+            ExprKind::Round { value } => unary(self, "round", value),
+            ExprKind::Sin { value } => unary(self, "sin of", value),
+            ExprKind::Cos { value } => unary(self, "cos of", value),
+            ExprKind::Tan { value } => unary(self, "tan of", value),
+            ExprKind::Asin { value } => unary(self, "asin of", value),
+            ExprKind::Acos { value } => unary(self, "acos of", value),
+            ExprKind::Atan { value } => unary(self, "atan of", value),
+
+            ExprKind::MakeList { values } => format!("(list{})", values.iter().map(|x| format!(" {}", self.expr(x))).collect::<String>()),
+            ExprKind::CopyList { list } => unary(self, "copy of", list),
+            ExprKind::ListCat { lists } => unary(self, "append", lists),
+            ExprKind::ListLen { value } => unary(self, "length of", value),
+            ExprKind::ListRank { value } => unary(self, "rank of", value),
+            ExprKind::ListDims { value } => unary(self, "dimensions of", value),
+            ExprKind::ListFlatten { value } => unary(self, "flatten of", value),
+            ExprKind::ListColumns { value } => unary(self, "columns of", value),
+            ExprKind::ListRev { value } => unary(self, "reverse of", value),
+            ExprKind::ListLines { value } => unary(self, "lines of", value),
+            ExprKind::ListCsv { value } => unary(self, "csv of", value),
+            ExprKind::ListJson { value } => unary(self, "json of", value),
+            ExprKind::ListReshape { value, dims } => format!("(reshape {} to {})", self.expr(value), self.expr(dims)),
+            ExprKind::ListCombinations { sources } => unary(self, "combinations", sources),
+            ExprKind::ListIsEmpty { value } => format!("&lt;is {} empty?&gt;", self.expr(value)),
+            ExprKind::ListCdr { value } => unary(self, "all but first of", value),
+            ExprKind::ListCons { item, list } => format!("({} in front of {})", self.expr(item), self.expr(list)),
+            ExprKind::ListFind { list, value } => format!("(index of {} in {})", self.expr(value), self.expr(list)),
+            ExprKind::ListContains { list, value } => format!("&lt;{} contains {}&gt;", self.expr(list), self.expr(value)),
+            ExprKind::ListGet { list, index } => format!("(item {} of {})", self.expr(index), self.expr(list)),
+            ExprKind::ListGetLast { list } => format!("(item (last) of {})", self.expr(list)),
+            ExprKind::ListGetRandom { list } => format!("(item (random) of {})", self.expr(list)),
+
+            ExprKind::StrGet { string, index } => format!("(letter {} of {})", self.expr(index), self.expr(string)),
+            ExprKind::StrGetLast { string } => format!("(letter (last) of {})", self.expr(string)),
+            ExprKind::StrGetRandom { string } => format!("(letter (random) of {})", self.expr(string)),
+            ExprKind::StrLen { value } => unary(self, "length of text", value),
+            ExprKind::UnicodeToChar { value } => format!("(unicode {} as letter)", self.expr(value)),
+            ExprKind::CharToUnicode { value } => unary(self, "unicode of", value),
+            ExprKind::TextSplit { text, mode } => match mode {
+                TextSplitMode::Custom(x) => format!("(split {} by {})", self.expr(text), self.expr(x)),
+                mode => format!("(split {} by [{}])", self.expr(text), lower(mode)),
+            }
+
+            ExprKind::CallRpc { service, rpc, args, .. } => format!("(call {})", self.rpc(service, rpc, args)),
+            ExprKind::CallFn { function, args, upvars } => format!("({})", self.fn_call(function, args, upvars)),
+            ExprKind::CallClosure { new_entity: Some(entity), closure, args } => format!("(ask {} for {}{})", self.expr(entity), self.expr(closure), self.inputs(args)),
+            ExprKind::CallClosure { new_entity: None, closure, args } => format!("(call {}{})", self.expr(closure), self.inputs(args)),
+            ExprKind::Closure { params, stmts, .. } => {
+                let mut inner = Blocks { lines: vec![], error: self.error, culprit: self.culprit.clone(), msg_handler: self.msg_handler };
+                let body = match stmts.as_slice() {
+                    [Stmt { kind: StmtKind::Return { value }, .. }] => inner.expr(value),
+                    _ => {
+                        inner.stmts(stmts, 0);
+                        inner.lines.iter().map(|x| x.trim()).collect::<Vec<_>>().join("; ")
+                    }
+                };
+                let params: String = params.iter().map(|x| format!(" ({})", escape(&x.name))).collect();
+                format!("{{ {body} }}{}", if params.is_empty() { String::new() } else { format!(" input names:{params}") })
+            }
+            ExprKind::Map { f, list } => format!("(map {} over {})", self.expr(f), self.expr(list)),
+            ExprKind::Keep { f, list } => format!("(keep items {} from {})", self.expr(f), self.expr(list)),
+            ExprKind::FindFirst { f, list } => format!("(find first item {} in {})", self.expr(f), self.expr(list)),
+            ExprKind::Combine { f, list } => format!("(combine {} using {})", self.expr(list), self.expr(f)),
+
+            ExprKind::StageWidth => "(stage width)".into(),
+            ExprKind::StageHeight => "(stage height)".into(),
+            ExprKind::MouseX => "(mouse x)".into(),
+            ExprKind::MouseY => "(mouse y)".into(),
+            ExprKind::Latitude => "(my latitude)".into(),
+            ExprKind::Longitude => "(my longitude)".into(),
+            ExprKind::KeyDown { key } => format!("&lt;key {} pressed?&gt;", self.expr(key)),
+            ExprKind::XPos => "(x position)".into(),
+            ExprKind::YPos => "(y position)".into(),
+            ExprKind::Heading => "(direction)".into(),
+            ExprKind::PenDown => "&lt;pen down?&gt;".into(),
+            ExprKind::Size => "(size)".into(),
+            ExprKind::IsVisible => "&lt;shown?&gt;".into(),
+            ExprKind::This => "(myself)".into(),
+            ExprKind::Entity { name, .. } => format!("[{}]", escape(name)),
+            ExprKind::ImageOfEntity { entity } => unary(self, "image of", entity),
+            ExprKind::ImageOfDrawings => "(pen trails)".into(),
+            ExprKind::IsTouchingEntity { entity } => format!("&lt;touching {}?&gt;", self.expr(entity)),
+            ExprKind::IsTouchingMouse => "&lt;touching [mouse-pointer]?&gt;".into(),
+            ExprKind::IsTouchingEdge => "&lt;touching [edge]?&gt;".into(),
+            ExprKind::IsTouchingDrawings => "&lt;touching [pen trails]?&gt;".into(),
+            ExprKind::RpcError => "(error)".into(),
+            ExprKind::Answer => "(answer)".into(),
+            ExprKind::Message => "(message)".into(),
+            ExprKind::Timer => "(timer)".into(),
+            ExprKind::NetworkMessageReply { target, msg_type, values } => format!("(send msg [{}]{} to {} and wait for reply)", escape(msg_type), self.fields(values), self.expr(target)),
+            ExprKind::Effect { kind } => format!("([{}] effect)", lower(kind)),
+            ExprKind::PenAttr { attr } => format!("(pen [{}])", lower(attr)),
+
+            ExprKind::CostumeList => "(my costumes)".into(),
+            ExprKind::Costume => "(costume)".into(),
+            ExprKind::CostumeNumber => "(costume #)".into(),
+            ExprKind::CostumeName { costume } => unary(self, "name of costume", costume),
+            ExprKind::CostumeWidth { costume } => unary(self, "width of costume", costume),
+            ExprKind::CostumeHeight { costume } => unary(self, "height of costume", costume),
+            ExprKind::CostumePixels { costume } => unary(self, "pixels of costume", costume),
+            ExprKind::SoundList => "(my sounds)".into(),
+            ExprKind::SoundName { sound } => unary(self, "name of sound", sound),
+            ExprKind::SoundDuration { sound } => unary(self, "duration of sound", sound),
+            ExprKind::SoundSampleRate { sound } => unary(self, "sample rate of sound", sound),
+            ExprKind::SoundSamples { sound } => unary(self, "samples of sound", sound),
+            ExprKind::SoundSamplesLength { sound } => unary(self, "length of sound", sound),
+            ExprKind::SoundChannelCount { sound } => unary(self, "number of channels of sound", sound),
+
+            ExprKind::Clone { target } => unary(self, "a new clone of", target),
+            ExprKind::TypeQuery { value, ty } => format!("&lt;is {} a [{}]?&gt;", self.expr(value), lower(ty)),
+            ExprKind::RealTime { query } => format!("(current [{}])", lower(query)),
+            ExprKind::UnknownBlock { name, args } => format!("({}{})", escape(name), self.inputs(args)),
+        };
+        let flagged = self.is_culprit(expr) || match &expr.kind {
+            ExprKind::CallFn { upvars, .. } => !upvars.is_empty() && matches!(self.error, Some(TranslateError::Upvars)),
+            ExprKind::CallClosure { new_entity, .. } => new_entity.is_some() && matches!(self.error, Some(TranslateError::TellAskClosure)),
+            ExprKind::Closure { stmts, .. } => !matches!(stmts.as_slice(), [Stmt { kind: StmtKind::Return { .. }, .. }]) && matches!(self.error, Some(TranslateError::CommandRing)),
+            ExprKind::TypeQuery { ty, .. } => matches!(ty, ValueType::Command | ValueType::Reporter | ValueType::Predicate) && matches!(self.error, Some(TranslateError::RingTypeQuery)),
+            _ => false,
+        };
+        self.highlight(text, flagged)
+    }
+}
+
+/// Gets the lowercase name of an option, such as a graphic effect.
+fn lower(option: &impl std::fmt::Debug) -> String {
+    format!("{option:?}").to_lowercase()
+}
+
+/// Renders a script or custom block, with its pseudo code next to its python code (or the reason it could not be translated).
+fn script_html(res: &mut String, title: &str, blocks: Blocks, python: Option<&str>) {
+    writeln!(res, "<div class=\"script\">\n<h4>{}</h4>", escape(title)).unwrap();
+    if let Some(error) = blocks.error {
+        writeln!(res, "<p class=\"error\">{}</p>", escape(&describe(error))).unwrap();
+    }
+    let python = match python {
+        Some(code) => python_html(code),
+        None => "<span class=\"comment\"># not translated</span>".into(),
+    };
+    writeln!(res, "<div class=\"columns\">\n<pre class=\"blocks\">{}</pre>\n<pre class=\"python\">{python}</pre>\n</div>\n</div>", blocks.lines.join("\n")).unwrap();
+}
+
+/// Renders the report for a parsed project, whose XML source is needed to translate it.
+pub(crate) fn render(project: &Project, source: &str, options: &TranslateOptions) -> String {
+    let units = units(project);
+    let mut failed = BTreeMap::new();
+    let translated = match translate_units(project, source, options, &units, |_| true) {
+        Ok(x) => Ok(x),
+        // if the project fails even without any scripts, the problem is elsewhere (e.g., in the initial value of a variable)
+        Err(e) => match translate_units(project, source, options, &units, |_| false) {
+            Err(_) => Err(e),
+            Ok(_) => {
+                for &unit in units.iter() {
+                    if let Err(e) = translate_units(project, source, options, &units, |x| x == unit) {
+                        failed.insert(unit, e);
+                    }
+                }
+                translate_units(project, source, options, &units, |x| !failed.contains_key(&x))
+            }
+        }
+    };
+
+    let mut res = String::new();
+    writeln!(res, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>", escape(&project.name)).unwrap();
+    writeln!(res, "<h1>{}</h1>", escape(&project.name)).unwrap();
+    if let Err(e) = &translated {
+        writeln!(res, "<p class=\"error\">The project could not be translated. {}</p>", escape(&describe(e))).unwrap();
+    }
+
+    for (role_idx, role) in project.roles.iter().enumerate() {
+        let trans = translated.as_ref().ok().map(|x| &x.roles[role_idx]);
+        writeln!(res, "<h2>{}</h2>", escape(&role.name)).unwrap();
+        if !role.notes.trim().is_empty() {
+            writeln!(res, "<p>{}</p>", escape(role.notes.trim()).replace('\n', "<br>")).unwrap();
+        }
+
+        for (entity_idx, entity) in role.entities.iter().enumerate() {
+            writeln!(res, "<h3>{}</h3>", escape(&entity.name)).unwrap();
+            let costumes: String = entity.costumes.iter().filter_map(|x| match &x.init {
+                Value::Image(img) => Some(thumbnail(&img.0, &x.def.name)),
+                _ => None,
+            }).collect();
+            if !costumes.is_empty() {
+                writeln!(res, "<div class=\"costumes\">{costumes}</div>").unwrap();
+            }
+
+            let scripts = trans.map(|x| editor_scripts(&x.editors[entity_idx + 1])).unwrap_or_default();
+            let specs: Vec<_> = entity.funcs.iter().map(names::block_spec).collect();
+            let mut hat_scripts: Vec<_> = scripts.iter().filter(|x| !specs.iter().any(|spec| spec == x.0)).collect();
+
+            for (func_idx, func) in entity.funcs.iter().enumerate() {
+                let error = failed.get(&Unit::Func { role: role_idx, entity: Some(entity_idx), func: func_idx });
+                let python = scripts.iter().find(|x| x.0 == specs[func_idx]).filter(|_| error.is_none()).map(|x| x.2.as_str());
+                func_html(&mut res, func, error, python);
+            }
+            for (script_idx, script) in entity.scripts.iter().enumerate() {
+                let Some(hat) = &script.hat else { continue };
+                let error = failed.get(&Unit::Script { role: role_idx, entity: entity_idx, script: script_idx });
+                // scripts are matched by the collaboration id of their hat block, or in order among those without one
+                let python = match error {
+                    Some(_) => None,
+                    None => hat_scripts.iter().position(|x| x.1 == hat.info.location.as_deref()).map(|i| hat_scripts.remove(i).2.as_str()),
+                };
+                let mut blocks = Blocks::new(error);
+                blocks.hat(hat);
+                blocks.stmts(&script.stmts, 0);
+                script_html(&mut res, &format!("Script {}", script_idx + 1), blocks, python);
+            }
+        }
+
+        if !role.funcs.is_empty() {
+            writeln!(res, "<h3>Global blocks</h3>").unwrap();
+            let scripts = trans.map(|x| editor_scripts(&x.editors[0])).unwrap_or_default();
+            for (func_idx, func) in role.funcs.iter().enumerate() {
+                let error = failed.get(&Unit::Func { role: role_idx, entity: None, func: func_idx });
+                let spec = names::block_spec(func);
+                let python = scripts.iter().find(|x| x.0 == spec).filter(|_| error.is_none()).map(|x| x.2.as_str());
+                func_html(&mut res, func, error, python);
+            }
+        }
+    }
+
+    res += "</body>\n</html>\n";
+    res
+}
+
+fn func_html(res: &mut String, func: &Function, error: Option<&TranslateError>, python: Option<&str>) {
+    let mut blocks = Blocks::new(error);
+    blocks.define(func);
+    blocks.stmts(&func.stmts, 1);
+    script_html(res, &format!("Custom block {}", names::block_spec(func)), blocks, python);
+}
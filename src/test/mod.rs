@@ -1,12 +1,13 @@
 use crate::*;
+use serde_json::json;
 
 fn get_code(xml: &str) -> Result<Vec<String>, TranslateError> {
-    let proj = serde_json::from_str::<serde_json::Value>(&translate(xml)?.1).unwrap();
-    let mut res = vec![];
-    for editor in proj.as_object().unwrap()["roles"].as_array().unwrap()[0].as_object().unwrap()["editors"].as_array().unwrap() {
-        res.push(editor.as_object().unwrap()["value"].as_str().unwrap().to_owned());
-    }
-    Ok(res)
+    get_code_with_options(xml, &TranslateOptions::default())
+}
+fn get_code_with_options(xml: &str, options: &TranslateOptions) -> Result<Vec<String>, TranslateError> {
+    let (_, proj) = translate_project(xml, options)?;
+    assert_eq!(proj.validate(), []);
+    Ok(proj.roles[0].editors.iter().map(|x| x.value.clone()).collect())
 }
 
 macro_rules! assert_code_eq {
@@ -44,26 +45,27 @@ fn test_media() {
                     "netsblox://assets/default-blocks.json",
                 ],
                 "blocks": [],
-                "imports": [
-                    "time",
-                    "math",
-                    "random",
-                ],
+                "imports": [],
                 "editors": [
                     {
                         "type": "globals",
                         "name": "globals",
                         "value": "from netsblox import snap\n\n",
+                        "source_map": [],
+                        "hashes": { "from netsblox import snap": "6e581db7d2fea1c9" },
                     },
                     {
                         "type": "stage",
                         "name": "Stage",
                         "value": "last_answer = snap.wrap('')\n\ndef __init__(self):\n    self.costume = None\n\n",
+                        "source_map": [],
+                        "hashes": { "last_answer": "e261edf09206f1b4" },
                     },
                     {
                         "type": "sprite",
                         "name": "Sprite",
                         "value": "def __init__(self):\n    self.pos = (0, 0)\n    self.heading = 90\n    self.pen_color = (80, 80, 80)\n    self.scale = 1\n    self.visible = True\n\n    self.sounds.add('Dog 2', sounds.Sprite_snd_Dog_2)\n    self.sounds.add('Finger Snap', sounds.Sprite_snd_Finger_Snap)\n\n    self.costumes.add('untitled', images.Sprite_cst_untitled)\n    self.costumes.add('untitled(2)', images.Sprite_cst_untitled_2)\n    self.costumes.add('untitled(3)', images.Sprite_cst_untitled_3)\n\n    self.costume = 'untitled(3)'\n\n",
+                        "source_map": [],
                     },
                 ],
                 "images": {
@@ -88,6 +90,38 @@ fn test_media() {
                         "snd": "UklGRqYPAABXQVZFZm10IBAAAAABAAEAESsAACJWAAACABAAZGF0YYIPAACL/z7/Zv+l/+f/rf8o/w7/9P9fADv/YP+z/0X/If/g/ov/xP3l/KH/bP+45NO+qCoEQZrf/uXX68xDRUIC5n/Gb9ZZI5BGPxDK32PLKvuRKp4c/v2m5BbsLQQeG8gdWfUU3ajugxXOH1YOy+c64rcELB17Ea3w9+xc/HgIYgvuAfz7+PqW9YwBvg00BmX7BfQ6+bALZQe9/iX3pvXG/wsIZwjS/cTy6vpIAikJuAME+ML1nP1+CH0FVvxh97gCIv4YBmAEc/NwAi79IQO6Amf9fP90+j4A/QQAAgUCy/em+w8GHAlx/tr4F/n9/9YEhgLrAfr5P/0dBIz+8f7cAJf//wC8/dUC7v6W/0v/Lf9EATgA4QBP/mQCFgAb/2kBz/1ZAJMCuv5g/u4D7/yG/NkD1AJqASX7JQDABr3+PP41/UEDdQHM/CP+4f9/Aev/yP5h/UsCXwCpAPz/cv0ZAEb/hwJ+/rD/pwGA/gf9D/7PA3AAa/1Y/VP/YgEQAVP/xf9R/8AAjAEiAN//Av0YAOABngJAANb80Pz1BPECvfqc/WwAiAPu/4b+W/4A/+cDuAL0/fn/HgIjAF8CkAMz/tH93gBNAmwAWv08ANAAKv+M/j//OgO1/0b+OQLiA3z/TvwGAtADNgB0/1//b/7//88AfgAHAZD/NP5z/TMAfwMqAtP7bvwEA3ED+wHR+QT9KwVhBGsAIvzP/F8BsgF3ASj/qfy4ADsBJwLh/wv+JwB0AFMDEgET/wb/5ACLAgUAQP6n/80BCABm/jb+SgCvAQYBgwDV+yH+5gKFA+n+5Pqt/8QEmf9U/Ef/2gGcApD+qPwNAOv/bQKCACD9f/+G//wAAgC2/kD+lf9XANL/Gf8DAFcAi//P/TICGwMr/OT9dAIuAUT/HQKd/DrlfQerOiwTD8+30i8IwClZETvi4elMBgkW8waf9JX6/f6ZAOwGHAkP+5j0hf6lDbgD3/fi+jMCNgn5BH/6tveHATIJHAKC+6r8Af96BssDd/nP+kED9AM1ArL6c/s2AlgCiwOy/Z77hQDfAz0BM/3x/ZoBQwE2AKcBJ/+E/lz9cACwBCUAdP3c/d//fgL5AAL/V/7w/YQC6gTn/+X4wvujAwEIKQFA+AT7JwR2B9b+q/q+/LYBfgP3AG/+PP4v/ukArgJk/rD94f+zAKj92v5i//H/ZwAR/+b+Ov0PAA0ChwDp/Rv+mwHYAIgB3f4J/5L/I//OAu0ASv2A/QUCJAIgAOH+wv8TAkoFvAs75d3czRuaOWsRPs6V3u7+QSeTHoXtvN0B6r8RSiU0BMnfSebICW4gYQqb77fv3PsABRYJcgMM+r/4hPwnBPIEggLt/Sz6PP++AVYAvwiVAF30rv8TAdEIb/ya+nUBcATxA3T5VvuKBSoHkf4G+IL8lgctCRv89vfjAsgFSgCX/dn+xQOfA07+A/zcAY4F+QHs+v79JQXaB2r9bPXw/r4HeQQJ/BD6nP8oBbgAoP5sALr/Nv8wAIcCT/9b/7UAzQB0/x3+YQCTAIkAd/9j/1EAyAA5AV3+Ev3d/ocCJQElACD/F/6gABr/gQG+/3oAUABS/40AhgDvAEIAAAHl/hb+awFJAjwBxf2//8MCHQDH/b/+GQCYAHwBdAD3/9v9UP2l/5MAfv3ECKYCfPqg+yb8cAdHBKj6U/peAxcAov6I/4b/Rga7/B31fQDRCHcGafj99vMErwa4/pr6nP3sAQUCuQNv/kj+yv8p/WoB+wBeAtX+Tv25AKwBEQOi/iv8+ADqAugBCf8i/scBeAEDALH/3AAmAID/bQD1/ub/fgH4/6r/Uv65/ysAOgArAZkAVf/W/Q/+cAJRAiD9hvqo/uwF1wN6/OH4gwF4BowAx/1h/Xf+2AGbAj7/xPxR/hUE4QJZ/HD6LgBhBZQBhPxo/VIA5gIvAoP9QP1eAAACEQFR/uf9rAECATT+uf/hALMBWgBL/5sAx/+z/y0CdQHj/mX8GgFuBPYApP6T/LT/kgGRAqAAbvzx/q0BMgGuACb+n/xEAZ0EqQBq/Kn8UwK+BA4A/Pxv/Q4BYwQ1ARX+bPy8/oQDaQPN/rz6Hv4nAxMDEgB4/Rz/WQFG/yEAhwFjABQAb/4//+0AM/8v/7oACAHy/zv/Q/9SAYUAIwDLANn/Lf9UAMcAIwCU/+T/cf9m/zEBfgGt/uT88v/WAhcAhP7T/goBqANb/0f9of9AAroBT/9L/z//0v+VAFIASQDu/vH/zAA5AqMApv6x/gYByAO+/07+rf/yAfUBcf9G/y3/FQC+AfwACf8z/kn/dAFFAQcBoP7X/QQACgGDAOL+iQCTACz/3v/d/5MASAAvAOf/2f8DALT/o/8m/w0Ay/8bAP//X/5aAN3/ev6I/uX/5QGNAIL9Nv7HAVQCZ/4d/ZgAiwGLABv+gv6QABcCkP9w/n8ABgB+AQwBnf4B/lkB1gJhAEb91P0jAToCZgBS/Y7+7gCoADcAif/0/zEBngBCAKr+Jv4kAfYBMQFi/uD8lgFGAtj/GP6e/hcBdgFqAFX/Vv8PAAwAIwIpAZf+Bv/Q/6sAzQD8/+P/RQD9/z0AuwBdANX/p/8FAfYA9v9h/gz/tQEJAQz/Av6j//QBnAFs/x7/rv9eAfMBcP8q/7X/4gDJAHH/9v8w/2cA1P/I/xgBJf+W/sL/YgDlAAv/+f3o/+YAnP+N/87+ZP/z/4P/QAAPAFL/XP+6/zMBNgFc/0b+xP7mAGMCuf+5/Wf/uwBVAKP/Y/8J/3UAvgFhAAH/fP/kAL0AHwBE/8z/DgHLAIn/Rf9wADkA6v9TAE8Alv+i/0MAeQAlAID/Zf+A/9UAIwEAADL+4P61AD8B7QAP/8L+cf8uAV8B4f9B/jf/nP8uAKEBGwAD/qP9lwD3AXEAjP6N/uj/iADXANj/NwBq/2L+swBfArMB//4T/qj/qAGTAcn/dP/5/1wATgAZAPUA7ADU/7b/yv/y/+gA3f+p/lT/5v8oAB0Ay/8wAEsAjP9BALEAwQAkAML/EAAsAF0AWADg/2j/CQA8AG0AHwDk//z/3v9y/w8A2ACy/2H/AwAyAG4AIwCKAMH/U//fACABoABPAH//HgBvAQUBCwCS/z8AtQHaAMf/BQBb/yAADQGJAMf+jf4nAHkBfgDk/m7+i/8FATgBiP9P/rH+LwADAdf/G/8E//X/BwBQ/9v/bv81/yYAbwCk//T+L/+3/zwAEwB4/z7/Wf/r/woASwCBADf/i//u/7YAAwFn/6z+lP/IAMz/D/9MAN7/tf+Y/+7/pgBXABb/Cv9GAAcB4wAW/+D+OQBiARoBeP8k//f/fAAMASUABf/3/88ApgCt/zv/2/9bAEMAnf/q/kr/agBAAGMA7/9a/+//pQC5AMr/jf8TAIEA7wByAJf/bf8LAAcByADm/9H/WAC2AM8AGwCj/6sAmgDM/3r/UgD0AEIAov/l/1kAVQD2AI8Aqf/6/3IAyQCUAB4Ap//c/4AArwBaAG7/Fv+n//0AUQDk/j3/lQDEAOX//v6E//AAawB2/1P/lf9ZAAEAjP8R//T+gv83AHgAu/9k/x0A5P9f/97/EwBI/6H+xv/7ALcAWf9j/ur+qwAcAcj/gf+u/+3/eQCmAL3//v6f/9cA3QCl/4j/l/88ALUAxP8q/7H/cgCGAM3/lf8HAJMAfwD0/7D/FACXAIMANQBH/5j/hADTAHkAvf+l//D/cwBmACMAqP9k/5n/CgBdAAYAjf9D/7T/swAjAYEAZ/+e/9oABgFmAPX/EQBhAG0AbADv/yoAcwAoAML/qP9XAO0AegC4/z7/w//CAGQAhv83/+D/VQDZ/5T/BgBSAN3/iv+9/+//FQARAND/Tf/i/00AGQBA/0D/QQCnAJj/7P5B/97/SADe/7z/4v/j/97/yP/1/+P/uf/2/xQA3/++/5j/EABMACcA1v9n/xAApwBSAHP///7n/58AbgCY/wX/x/8sAGwAdwBYAOr/t/9+AM4AkADI/6P/agDQAF8AVP86/xAA3gCaAJn/M//d/0UAnQA+AIX/BgBeALkAaQCh/8j/bAC9AGIArP90/+T/VgBdANv/eP+/APoAGQC4/7X/GABJAD0AMAAcAOT/tP/t/wwAEAAvAFUAGQAaABYADABCAD8A0//E/9b/BQAxAOD/YP9b/9r/cwAQAH7/sP/X//j/5v+//xQA3f9k/5T/VQA8ALP/0/+x/6X/+/8yAEoA2f9u/5f/JwA9ANz/uP+1//r/IgAQANf/2P/t/9r/cQCtAD0Aov+9/30AhAAgALT/xP8KADAAKwDv/6r/1v8lAE0ADgDl/zsAPADr////KADV/4z///9pAOb/f/9+/97/EADl/6X/uf8VAC4AGwDd/73/1v8tAF0ANADY/6T/3f97AG0ApP+N/w8AVAAJAMj/FAASAKz/2/87AFIAIgCr/9P/VwB/AEgACgAKAEUAXAB9AD4AIwAwAP//AQD7/wwACgDP/6b/2f8SABsA3f/E/xQAQQAuAOT/zf8HACIAFgDa/6b/0/8vAAQAlP+T/8X/CwAHAK//pP+s/9//HgDm/63/oP/p/x0A/v/Q//z/PAAWAOT/+P8RABwAHAAdACcA+f/3/x0AHgA0ACUACgDu/+j/JwBcAFAADAAIAEYAcAByADwAAgAoAD0ALgAeAAYA6//7//3/6v/y/wwABQD//xMA/P/9/x8AJwAKAPH/3//5/xMACQDe/7X/1/8CABQA3P+r/7n///8AANz/wf+y//b/DQD6//7/8/8MADIAFgDj/83/BwAuABEA8//t//f/9/8LABQAAADl/9z/9v/5/+j/6//2/wgA9f8UAD0AGADo/+f/IwAiAOD/u//b//H/4//j/+f/yP/G//L/IwAwAOv/0P/z/x0AMwAGAPX//f8RAB0ACQACAAoAEgAPAAAADAAKAPf/8P/v//X//P/p/+D/6P/+/xEABgAFAAgAHQAjAB0AIAAfAC8AOQAtAP3/8/8hACgAFwDv/+L/+v8JAP//8f/t//b/DgAZAAoAAgAOAA0ACQAOAAUAAAABAAEABQD+//D/7P/u//3/8f/h//L/8//y//T/+P8EAPz/9//2//j//f8HAA4AAADv//L/AgAFAP//8P/n/+7//P8EAPf/6f/w/wAABgD9//b/+f8AAAYACQAEAAEA//8DAAYABgACAPf/+P8AAAYA///6////AQAFAAcABgADAAIAAwADAAIAAQAAAAIAAgD+//7/AQAAAAAAAgAAAAAAAgACAAAA//8BAAEAAQAAAAAA",
                     },
                 },
+                "names": {
+                    "globals": {
+                        "variables": {},
+                        "locals": {},
+                        "blocks": {},
+                    },
+                    "sprites": {
+                        "Stage": {
+                            "name": "Stage",
+                            "variables": {},
+                            "locals": {},
+                            "blocks": {},
+                            "costumes": {},
+                            "sounds": {},
+                        },
+                        "Sprite": {
+                            "name": "Sprite",
+                            "variables": {},
+                            "locals": {},
+                            "blocks": {},
+                            "costumes": {
+                                "untitled": "Sprite_cst_untitled",
+                                "untitled(2)": "Sprite_cst_untitled_2",
+                                "untitled(3)": "Sprite_cst_untitled_3",
+                            },
+                            "sounds": {
+                                "Dog 2": "Sprite_snd_Dog_2",
+                                "Finger Snap": "Sprite_snd_Finger_Snap",
+                            },
+                        },
+                    },
+                },
             },
         ]
     });
@@ -95,31 +129,344 @@ fn test_media() {
     assert_eq!(got, expected);
 }
 
+#[test]
+fn test_project() {
+    let (name, proj) = translate_project(include_str!("projects/media.xml"), &TranslateOptions::default()).unwrap();
+    assert_eq!(name, "untitled");
+    let role = &proj.roles[0];
+    assert_eq!(role.stage_size, (480, 360));
+    assert_eq!(role.editors.iter().map(|x| (x.ty, x.name.as_str())).collect::<Vec<_>>(), [
+        (project::EditorType::Globals, "globals"),
+        (project::EditorType::Stage, "Stage"),
+        (project::EditorType::Sprite, "Sprite"),
+    ]);
+    assert_eq!(role.images.keys().collect::<Vec<_>>(), ["Sprite_cst_untitled", "Sprite_cst_untitled_2", "Sprite_cst_untitled_3"]);
+    assert!(role.images["Sprite_cst_untitled"].img.starts_with(b"\x89PNG"));
+    assert!(role.sounds["Sprite_snd_Dog_2"].snd.starts_with(b"RIFF"));
+    assert_eq!(role.names.sprites["Sprite"].costumes["untitled(2)"], "Sprite_cst_untitled_2");
+
+    let json = serde_json::to_string(&proj).unwrap();
+    assert_eq!(json, translate(include_str!("projects/media.xml")).unwrap().1);
+    assert_eq!(serde_json::from_str::<project::Project>(&json).unwrap(), proj);
+}
+
+#[test]
+fn test_validate() {
+    let json = translate(include_str!("projects/media.xml")).unwrap().1;
+    assert_eq!(project::Project::parse(&json).unwrap(), translate_project(include_str!("projects/media.xml"), &TranslateOptions::default()).unwrap().1);
+    assert!(matches!(project::Project::parse("{\"roles\": 5}"), Err(project::ProjectError::Json(_))));
+
+    // projects saved by PyBlox itself have no names or blocks
+    let mut saved: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let saved_role = saved["roles"][0].as_object_mut().unwrap();
+    saved_role.remove("names");
+    saved_role.remove("blocks");
+    let saved = project::Project::parse(&saved.to_string()).unwrap();
+    assert_eq!(saved.roles[0].names, project::Names::default());
+    assert!(saved.roles[0].blocks.is_empty());
+
+    let mut proj = project::Project::parse(&json).unwrap();
+    let role = &mut proj.roles[0];
+    role.images.remove("Sprite_cst_untitled_2");
+    role.editors[2].value += "x = images.Sprite_cst_untitled\nself.sounds.add('a', sounds.nope)\n";
+    role.editors[2].source_map.push(project::SourceMapEntry {
+        lines: (3, 400), role: "myRole".into(), entity: Some("Sprite".into()), script: "on_start".into(), block: None, path: vec![], hash: None,
+    });
+    role.editors.push(role.editors[2].clone());
+    role.editors[1].name = "my stage".into();
+    role.stage_size = (0, 360);
+    proj.roles.push(proj.roles[0].clone());
+    proj.roles[1].editors.swap(0, 1);
+
+    let errors = proj.validate();
+    let role: CompactString = "myRole".into();
+    let sprite: CompactString = "Sprite".into();
+    let sprite_errors = [
+        project::ValidationError::MissingImage { role: role.clone(), editor: sprite.clone(), line: 12, name: "Sprite_cst_untitled_2".into() },
+        project::ValidationError::MissingSound { role: role.clone(), editor: sprite.clone(), line: 18, name: "nope".into() },
+        project::ValidationError::SourceMapRange { role: role.clone(), editor: sprite.clone(), lines: (3, 400) },
+    ];
+    let role_errors = |order: bool| {
+        let mut res = vec![project::ValidationError::EmptyStage { role: role.clone() }];
+        if order { res.push(project::ValidationError::EditorOrder { role: role.clone() }) }
+        res.push(project::ValidationError::InvalidEditorName { role: role.clone(), editor: "my stage".into() });
+        res.extend(sprite_errors.iter().cloned());
+        res.push(project::ValidationError::DuplicateEditor { role: role.clone(), editor: sprite.clone() });
+        res.extend(sprite_errors.iter().cloned());
+        res
+    };
+    let mut expected = role_errors(false);
+    expected.push(project::ValidationError::DuplicateRole { role: role.clone() });
+    expected.extend(role_errors(true));
+    assert_eq!(errors, expected);
+    assert!(matches!(project::Project::parse(&serde_json::to_string(&proj).unwrap()), Err(project::ProjectError::Invalid(x)) if x == expected));
+}
+
+#[test]
+fn test_write_dir() {
+    let (_, proj) = translate_project(include_str!("projects/media.xml"), &TranslateOptions::default()).unwrap();
+    let root = std::env::temp_dir().join(format!("nb2pb-test-write-dir-{}", std::process::id()));
+    proj.write_dir(&root).unwrap();
+
+    let role = &proj.roles[0];
+    for editor in role.editors.iter() {
+        assert_eq!(std::fs::read_to_string(root.join(format!("myRole/{}.py", editor.name))).unwrap(), editor.value);
+    }
+    assert_eq!(std::fs::read(root.join("myRole/images/Sprite_cst_untitled.png")).unwrap(), role.images["Sprite_cst_untitled"].img);
+    assert_eq!(std::fs::read(root.join("myRole/sounds/Sprite_snd_Dog_2.wav")).unwrap(), role.sounds["Sprite_snd_Dog_2"].snd);
+
+    let manifest: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(root.join("manifest.json")).unwrap()).unwrap();
+    let role_manifest = &manifest["roles"][0];
+    assert_eq!(role_manifest["name"], "myRole");
+    assert_eq!(role_manifest["path"], "myRole");
+    assert_eq!(role_manifest["stage_size"], json!([480, 360]));
+    assert_eq!(role_manifest["editors"][2], json!({ "type": "sprite", "name": "Sprite", "file": "myRole/Sprite.py", "source_map": [] }));
+    assert_eq!(role_manifest["images"]["Sprite_cst_untitled_2"], json!({ "file": "myRole/images/Sprite_cst_untitled_2.png", "center": [13.0, 38.125] }));
+    assert_eq!(role_manifest["sounds"]["Sprite_snd_Finger_Snap"], json!({ "file": "myRole/sounds/Sprite_snd_Finger_Snap.wav" }));
+    assert_eq!(role_manifest["names"]["sprites"]["Sprite"]["name"], "Sprite");
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_script() {
+    let (_, proj) = translate_project(include_str!("projects/sourcemap.xml"), &TranslateOptions::default()).unwrap();
+    assert_code_eq!(proj.roles[0].to_script(), r#"
+#!/usr/bin/env python
+# the NetsBlox role 'myRole', translated by nb2pb
+
+import base64
+import io
+import sys
+import types
+
+import PIL.Image
+
+import netsblox
+from netsblox import get_location, get_error, nothrow, Namespace
+from netsblox.turtle import *
+from netsblox.concurrency import *
+
+nb = netsblox.Client()
+
+# global variables are accessed as attributes of this module (e.g., globals.score)
+globals = sys.modules[__name__]
+
+def _image(data, center):
+    img = PIL.Image.open(io.BytesIO(base64.b64decode(data)))
+    img.info['center'] = center
+    return img
+
+def _sound(data):
+    return io.BytesIO(base64.b64decode(data))
+
+images = types.SimpleNamespace(
+)
+sounds = types.SimpleNamespace(
+)
+
+# ------------------------------------------------
+
+from netsblox import snap
+
+fr = snap.wrap('0')
+
+def twice(x):
+    return x * snap.wrap(2)
+
+# ------------------------------------------------
+
+@stage
+class Stage(StageBase):
+    last_answer = snap.wrap('')
+
+    def __init__(self):
+        self.costume = None
+
+Stage = Stage()
+
+# ------------------------------------------------
+
+@sprite
+class Sprite(SpriteBase):
+    def __init__(self):
+        self.pos = (0, 0)
+        self.heading = 90
+        self.pen_color = (80, 80, 80)
+        self.scale = 1
+        self.visible = True
+        self.costume = None
+
+    def jump(self):
+        self.forward(10)
+
+    @onstart()
+    def on_start(self):
+        globals.fr = snap.wrap('0')
+        if globals.fr < snap.wrap(5):
+            globals.fr += snap.wrap('1')
+        else:
+            self.say('done')
+        for _ in range(3):
+            self.forward(10)
+            self.jump()
+
+Sprite = Sprite()
+
+# ------------------------------------------------
+
+def main():
+    start_project()
+
+if __name__ == '__main__':
+    main()
+"#.trim_start());
+
+    let (_, proj) = translate_project(include_str!("projects/media.xml"), &TranslateOptions::default()).unwrap();
+    let script = proj.roles[0].to_script();
+    assert!(script.contains("\nimages = types.SimpleNamespace(\n    Sprite_cst_untitled=_image('iVBORw0KGgo"));
+    assert!(script.contains("    Sprite_cst_untitled_2=_image('iVBORw0KGgoAAAANSUhEUgAAABIAAAAOCAYAAAAi2ky3"));
+    assert!(script.contains("', (13.0, 38.125)),\n"));
+    assert!(script.contains("\nsounds = types.SimpleNamespace(\n    Sprite_snd_Dog_2=_sound('UklGR"));
+    assert!(script.contains("\n@sprite\nclass Sprite(SpriteBase):\n    def __init__(self):\n        self.pos = (0, 0)\n"));
+    assert!(script.contains("\n        self.costumes.add('untitled', images.Sprite_cst_untitled)\n"));
+}
+
+#[test]
+fn test_notebook() {
+    let cells = |notebook: &str| -> Vec<(String, String)> {
+        let notebook: serde_json::Value = serde_json::from_str(notebook).unwrap();
+        assert_eq!((&notebook["nbformat"], &notebook["nbformat_minor"]), (&json!(4), &json!(4)));
+        notebook["cells"].as_array().unwrap().iter().map(|cell| {
+            let source = cell["source"].as_array().unwrap().iter().map(|x| x.as_str().unwrap()).collect();
+            (cell["cell_type"].as_str().unwrap().into(), source)
+        }).collect()
+    };
+
+    let (_, proj) = translate_project(include_str!("projects/notebook.xml"), &TranslateOptions::default()).unwrap();
+    assert_eq!(proj.roles[0].notes, "Counts to five.");
+    let mut cells = cells(&proj.roles[0].to_notebook());
+    assert!(cells[1].1.starts_with("import base64\n") && cells[1].1.contains("\nimages = types.SimpleNamespace(\n"));
+    cells.remove(1);
+    let expected = [
+        ("markdown", "# myRole\n\nCounts to five."),
+        ("markdown", "## Globals"),
+        ("code", "from netsblox import snap\n\nfr = snap.wrap('0')"),
+        ("code", "def twice(x):\n    return x * snap.wrap(2)"),
+        ("markdown", "## Stage"),
+        ("code", "class Stage(StageBase):\n    last_answer = snap.wrap('')\n\n    def __init__(self):\n        self.costume = None"),
+        ("code", "Stage = stage(Stage)()"),
+        ("markdown", "## Sprite"),
+        ("code", "class Sprite(SpriteBase):\n    def __init__(self):\n        self.pos = (0, 0)\n        self.heading = 90\n        self.pen_color = (80, 80, 80)\n        self.scale = 1\n        self.visible = True\n        self.costume = None"),
+        ("code", "class Sprite(Sprite):\n    def jump(self):\n        self.forward(10)"),
+        ("code", r#"
+class Sprite(Sprite):
+    @onstart()
+    def on_start(self):
+        globals.fr = snap.wrap('0')
+        if globals.fr < snap.wrap(5):
+            globals.fr += snap.wrap('1')
+        else:
+            self.say('done')
+        for _ in range(3):
+            self.forward(10)
+            self.jump()"#.trim_start()),
+        ("code", "Sprite = sprite(Sprite)()"),
+        ("markdown", "## Run"),
+        ("code", "start_project()"),
+    ];
+    assert_eq!(cells, expected.map(|(a, b)| (a.to_owned(), b.to_owned())));
+
+    let (_, proj) = translate_project(include_str!("projects/media.xml"), &TranslateOptions::default()).unwrap();
+    let notebook: serde_json::Value = serde_json::from_str(&proj.roles[0].to_notebook()).unwrap();
+    let sprite = notebook["cells"].as_array().unwrap().iter().find(|x| x["source"][0] == "## Sprite\n").unwrap();
+    assert_eq!(sprite["source"][2], "![untitled](attachment:Sprite_cst_untitled.png \"untitled\")\n");
+    assert!(sprite["attachments"]["Sprite_cst_untitled.png"]["image/png"].as_str().unwrap().starts_with("iVBORw0KGgo"));
+    assert_eq!(sprite["attachments"].as_object().unwrap().len(), proj.roles[0].names.sprites["Sprite"].costumes.len());
+}
+
+#[test]
+fn test_report() {
+    let html = report(include_str!("projects/sourcemap.xml"), &TranslateOptions::default()).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>\n"));
+    assert!(html.contains("<h1>sourcemap</h1>\n<h2>myRole</h2>\n<h3>"));
+    let notes = report(include_str!("projects/notebook.xml"), &TranslateOptions::default()).unwrap();
+    assert!(notes.contains("<h1>notebook</h1>\n<h2>myRole</h2>\n<p>Counts to five.</p>\n"));
+    assert!(html.contains(r#"
+<pre class="blocks">when green flag clicked
+set [fr] to (0)
+if &lt;(fr) &lt; (5)&gt;
+    change [fr] by (1)
+else
+    say [done]
+end
+repeat (3)
+    move (10) steps
+    jump
+end</pre>
+<pre class="python">@onstart()
+def on_start(self):
+    globals.fr = snap.wrap('0')
+    if globals.fr &lt; snap.wrap(5):
+"#));
+    assert!(html.contains("<h4>Custom block twice %'x'</h4>\n<div class=\"columns\">\n<pre class=\"blocks\">define twice (x)\n    report ((x) × (2))</pre>\n<pre class=\"python\">def twice(x):\n    return x * snap.wrap(2)</pre>\n"));
+    assert!(!html.contains("class=\"error\""));
+
+    // a script which cannot be translated does not stop the others from being translated
+    let html = report(include_str!("projects/async-rpcs.xml"), &TranslateOptions::default()).unwrap();
+    assert!(html.contains("<h4>Script 1</h4>\n<p class=\"error\">The highlighted block is not supported.</p>\n"));
+    assert!(html.contains("\n<mark class=\"unsupported\">launch { call [Weather] [temperature] lat: (12) lng: (34) }</mark>\n"));
+    assert!(html.contains("<pre class=\"python\"><span class=\"comment\"># not translated</span></pre>"));
+    assert!(html.contains("<pre class=\"python\">@onkey('space')\ndef on_key_space(self):\n"));
+    assert_eq!(html.matches("class=\"error\"").count(), 1);
+
+    let xml = include_str!("projects/sourcemap.xml").replace(r#"<block collabId="item_5" s="bubble"><l>done</l></block>"#, r#"<block collabId="item_5" s="doBroadcastAndWait"><l>done</l><l></l></block>"#);
+    let html = report(&xml, &TranslateOptions::default()).unwrap();
+    assert!(html.contains("\n    <mark class=\"unsupported\">broadcast [done] and wait</mark>\n"));
+    assert!(html.contains("<pre class=\"python\">def jump(self):\n"));
+    assert_eq!(html.matches("class=\"error\"").count(), 1);
+
+    let html = report(include_str!("projects/variables.xml"), &TranslateOptions { idiomatic: true, ..Default::default() }).unwrap();
+    assert!(html.contains("<mark class=\"warning\">    # warning: no plain python equivalent for .shape</mark>\n"));
+
+    let html = report(include_str!("projects/media.xml"), &TranslateOptions::default()).unwrap();
+    assert!(html.contains("<h3>Sprite</h3>\n<div class=\"costumes\"><img src=\"data:image/png;base64,iVBORw0KGgo"));
+    assert_eq!(html.matches("<img ").count(), 3);
+
+    // scripts without collaboration ids are matched in order, and unknown blocks are only flagged if they cannot be translated
+    let html = report(include_str!("projects/scripts.xml"), &TranslateOptions::default()).unwrap();
+    assert!(html.contains("<pre class=\"blocks\">when [up arrow] key pressed\nmove (10) steps</pre>\n<pre class=\"python\">@onkey('up arrow')\ndef on_key_up_arrow(self):\n"));
+    assert!(html.contains("<pre class=\"blocks\">when [up arrow] key pressed\nturn right (15) degrees</pre>\n<pre class=\"python\">@onkey('up arrow')\ndef on_key_up_arrow_2(self):\n"));
+    let html = report(include_str!("projects/messages.xml"), &TranslateOptions::default()).unwrap();
+    assert!(html.contains("self.say(_sender)"));
+    assert!(!html.contains("<mark class=\"unsupported\">"));
+}
+
 #[test]
 fn test_operators() {
-    let code = get_code(include_str!("projects/operators.xml")).unwrap();
+    let code = get_code_with_options(include_str!("projects/operators.xml"), &TranslateOptions { opt_level: OptLevel::None, ..Default::default() }).unwrap();
     assert_eq!(code.len(), 3);
     assert_code_eq!(code[0].trim(), r#"
 from netsblox import snap
 
 def foo():
     bar = snap.wrap(0)
-    bar = (snap.wrap('1') + snap.wrap('4'))
-    bar = (snap.wrap('1') + snap.wrap('4') + snap.wrap('7'))
+    bar = snap.wrap(1 + 4)
+    bar = snap.wrap(1 + 4 + 7)
     bar = snap.wrap(sum(baz()))
-    bar = (snap.wrap('6') - snap.wrap('3'))
-    bar = (snap.wrap('6') * snap.wrap('2'))
-    bar = (snap.wrap('6') * snap.wrap('2') * snap.wrap('8'))
+    bar = snap.wrap(6 - 3)
+    bar = snap.wrap(6 * 2)
+    bar = snap.wrap(6 * 2 * 8)
     bar = snap.prod(baz())
-    bar = (snap.wrap('8') / snap.wrap('3'))
-    bar = (snap.wrap('2') ** snap.wrap('4'))
-    bar = (snap.wrap('3') % snap.wrap('2'))
+    bar = snap.wrap(8 / 3)
+    bar = snap.wrap(2 ** 4)
+    bar = snap.wrap(3 % 2)
     bar = round(snap.wrap('6.4'))
-    bar = abs(snap.wrap('10'))
-    bar = -snap.wrap('10')
+    bar = snap.wrap(abs(10))
+    bar = snap.wrap(-10)
     bar = snap.sign('10')
-    bar = math.ceil(snap.wrap('10'))
-    bar = math.floor(snap.wrap('10'))
+    bar = snap.wrap(math.ceil(10))
+    bar = snap.wrap(math.floor(10))
     bar = snap.sqrt('10')
     bar = snap.sin('10')
     bar = snap.cos('10')
@@ -130,9 +477,9 @@ def foo():
     bar = snap.log('10', math.e)
     bar = snap.log('10', 10)
     bar = snap.log('10', 2)
-    bar = (snap.wrap(math.e) ** snap.wrap('10'))
-    bar = (snap.wrap(10) ** snap.wrap('10'))
-    bar = (snap.wrap(2) ** snap.wrap('10'))
+    bar = snap.wrap(math.e ** 10)
+    bar = snap.wrap(10 ** 10)
+    bar = snap.wrap(2 ** 10)
     bar = snap.wrap('10')
     bar = snap.atan2('6', '5')
     bar = min(snap.wrap(['2', '4']))
@@ -142,22 +489,22 @@ def foo():
     bar = max(snap.wrap(['5', '2', '98']))
     bar = max(baz())
     bar = snap.rand('1', '10')
-    bar = (snap.wrap('6') < snap.wrap('3'))
-    bar = (snap.wrap('6') <= snap.wrap('3'))
-    bar = (snap.wrap('6') == snap.wrap('3'))
-    bar = (snap.wrap('6') != snap.wrap('3'))
+    bar = 6 < 3
+    bar = 6 <= 3
+    bar = 6 == 3
+    bar = 6 != 3
     bar = snap.identical('6', '3')
-    bar = (snap.wrap('6') > snap.wrap('3'))
-    bar = (snap.wrap('6') >= snap.wrap('3'))
-    bar = (True and False)
-    bar = (False or True)
-    bar = snap.lnot(True)
-    bar = snap.lnot(False)
+    bar = 6 > 3
+    bar = 6 >= 3
+    bar = True and False
+    bar = False or True
+    bar = not True
+    bar = not False
     bar = False
     bar = True
     bar = snap.wrap('hello world')
     bar = snap.wrap('hello worldagain')
-    bar = snap.wrap(''.join(str(x) for x in baz()))
+    bar = snap.wrap(''.join(str(_x) for _x in baz()))
     bar = snap.wrap(len('world'))
     bar = snap.split('hello world', ' ')
     bar = snap.split('hello world', '')
@@ -210,53 +557,53 @@ from netsblox import snap
 def foobar():
     a = snap.wrap(0)
     a = snap.wrap([])
-    a = snap.wrap(['4', '1', ['2', (snap.wrap('3') + snap.wrap('1'))]])
+    a = snap.wrap(['4', '1', ['2', 4]])
     a = snap.wrap([*a])
     a += snap.wrap('1')
     a = snap.srange('1', '10')
     a = snap.wrap(['23', *a])
     a = a[1:]
-    a = a[snap.wrap('1') - snap.wrap(1)]
-    a = a[snap.wrap('4') - snap.wrap(1)]
-    a = a[(snap.wrap('2') + snap.wrap('3')) - snap.wrap(1)]
+    a = a[0]
+    a = a[3]
+    a = a[4]
     a = a.last
     a = a.rand
     a = a[snap.wrap(['1', '3', '2']) - snap.wrap(1)]
-    a = (a.index('thing') + snap.wrap(1))
-    a = (snap.wrap('thing') in a)
-    a = (len(a) == 0)
+    a = a.index('thing') + snap.wrap(1)
+    a = snap.wrap('thing') in a
+    a = len(a) == 0
     a = snap.wrap(len(a))
     a = snap.wrap(len(a.shape))
     a = a.shape
     a = a.flat
     a = a.T
     a = a[::-1]
-    a = '\n'.join(str(x) for x in a)
+    a = snap.wrap('\n'.join(str(_x) for _x in a))
     a = a.csv
     a = a.json
     for item in a:
         a.append(item)
         a.append(snap.wrap('abc'))
         a.pop()
-        del a[snap.wrap('1') - snap.wrap(1)]
-        del a[snap.wrap('7') - snap.wrap(1)]
-        del a[(snap.wrap('4') + snap.wrap('1')) - snap.wrap(1)]
+        del a[0]
+        del a[6]
+        del a[4]
         a.clear()
         a.insert('1', 'abc')
         a.insert('16', 'abc')
-        a.insert((snap.wrap('1') + snap.wrap('3')), 'abc')
+        a.insert(4, 'abc')
         a.append(snap.wrap('abc'))
         a.insert_rand('abc')
-        a[snap.wrap('1') - snap.wrap(1)] = 'zyx'
-        a[snap.wrap('6') - snap.wrap(1)] = 'zyx'
-        a[(snap.wrap('2') + snap.wrap('5')) - snap.wrap(1)] = 'zyx'
+        a[0] = 'zyx'
+        a[5] = 'zyx'
+        a[6] = 'zyx'
         a.last = 'zyx'
         a.rand = 'zyx'
     a = snap.wrap([])
     a = snap.wrap([*snap.wrap([])])
     a = snap.wrap([*snap.wrap([]), *snap.wrap([])])
     a = snap.wrap([*snap.wrap([]), *snap.wrap([]), *snap.wrap([])])
-    a = snap.wrap([y for x in a for y in x])
+    a = snap.wrap([_y for _x in a for _y in _x])
     a = snap.wrap([]).reshaped([])
     a = snap.wrap([]).reshaped(['5'])
     a = snap.wrap([]).reshaped(['5', '3'])
@@ -266,8 +613,8 @@ def foobar():
     a = snap.combinations([], [])
     a = snap.combinations([], [], [])
     a = snap.combinations(*a)
-    a = snap.wrap('world')[snap.wrap('1') - snap.wrap(1)]
-    a = snap.wrap('world')[snap.wrap('5') - snap.wrap(1)]
+    a = snap.wrap('world')[0]
+    a = snap.wrap('world')[4]
     a = snap.wrap('world').last
     a = snap.wrap('world').rand
     a = snap.wrap(len('hello world'))
@@ -315,7 +662,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     my_variable_name = snap.wrap(0)
     some_blocks_stuff()
 "#.trim());
@@ -351,7 +698,7 @@ def __init__(self):
     self.costume = 'john cena'
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     self.costume = ''
     self.costume = ''
     self.costume = ''
@@ -362,13 +709,13 @@ def my_onstart_1(self):
     self.costume = 'john cena'
     self.costume = 'kevin ()'
     self.costume = (self.costumes.index(self.costume, -1) + 1) % len(self.costumes)
-    self.say((self.costumes.index(self.costume, -1) + 1))
-    self.say((self.costumes.index(self.costume, -1) + 1), duration = '2')
-    self.scale += 12 / 100
-    self.scale = 165 / 100
+    self.say(self.costumes.index(self.costume, -1) + 1)
+    self.say(self.costumes.index(self.costume, -1) + 1, duration = '2')
+    self.scale += 0.12
+    self.scale = 1.65
     self.scale += snap.wrap('gferg') / 100
     self.scale = snap.wrap('fgnrt') / 100
-    self.say((self.scale * 100))
+    self.say(self.scale * 100)
     self.say(self.visible, duration = '2')
     self.visible = True
     self.visible = False
@@ -404,7 +751,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     self.play_sound('Cat')
     self.play_sound('Dog 1')
     self.play_sound(globals.gf)
@@ -443,7 +790,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     Stage.last_answer = snap.wrap(input('hello world?'))
     globals.something = Stage.last_answer
     globals.something = snap.wrap(Stage.mouse_pos[0])
@@ -482,7 +829,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     self.forward(7)
     self.turn_right(21)
     self.turn_left(6)
@@ -533,7 +880,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     Stage.clear_drawings()
     self.drawing = True
     self.drawing = False
@@ -574,32 +921,42 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     globals.something = snap.wrap('')
     globals.something = snap.wrap('hello')
     globals.something = snap.wrap('helloworld')
     globals.something = snap.wrap(f'hello{globals.something}world')
-    globals.something = snap.wrap(f"hello{(snap.wrap('3') * snap.wrap('5'))}world")
+    globals.something = snap.wrap('hello15world')
     globals.something = snap.wrap('helloworld')
     globals.something = snap.wrap('hellohelpworld')
     globals.something = snap.wrap('hellohelpmeworld')
     globals.something = snap.wrap(f'hellohelp{globals.something}meworld')
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('{}') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"h{{el}}lohelp{(snap.wrap('3') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('5'))}me}}wor{{ld")
-    globals.something = snap.wrap(f"helloh{{elp}}{(snap.wrap('3') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('5'))}m}}{{eworld")
-    globals.something = snap.wrap(f"he{{l}}loh{{el}}p{(snap.wrap('3') + snap.wrap('{}') + snap.wrap('5'))}{{me}}wo}}{{rld")
+    globals.something = snap.wrap('hellohelp8meworld')
+    globals.something = snap.wrap(
+        f"hellohelp{snap.wrap(3) + snap.wrap('{}') + snap.wrap(5)}meworld"
+    )
+    globals.something = snap.wrap('h{el}lohelp8meworld')
+    globals.something = snap.wrap('hellohelp8me}wor{ld')
+    globals.something = snap.wrap('helloh{elp}8meworld')
+    globals.something = snap.wrap('hellohelp8m}{eworld')
+    globals.something = snap.wrap(
+        f"he{{l}}loh{{el}}p{snap.wrap(3) + snap.wrap('{}') + snap.wrap(5)}{{me}}wo}}{{rld"
+    )
     globals.something = snap.wrap('he{l}loh{el}p{}{me}wo}{rld')
-    globals.something = snap.wrap(f'hellohelp{globals.something}mehelp{globals.something}meworld')
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('5'))}mehelp{(snap.wrap('3') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('{}') + snap.wrap('5'))}mehelp{(snap.wrap('3') + snap.wrap('{}') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"h{{el}}lohelp{(snap.wrap('3') + snap.wrap('5'))}mehelp{(snap.wrap('3') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('5'))}mehelp{(snap.wrap('3') + snap.wrap('5'))}me}}wor{{ld")
-    globals.something = snap.wrap(f"helloh{{elp}}{(snap.wrap('3') + snap.wrap('5'))}meh{{elp}}{(snap.wrap('3') + snap.wrap('5'))}meworld")
-    globals.something = snap.wrap(f"hellohelp{(snap.wrap('3') + snap.wrap('5'))}m}}{{ehelp{(snap.wrap('3') + snap.wrap('5'))}m}}{{eworld")
-    globals.something = snap.wrap(f"he{{l}}loh{{el}}p{(snap.wrap('3') + snap.wrap('{}') + snap.wrap('5'))}{{me}}h{{el}}p{(snap.wrap('3') + snap.wrap('{}') + snap.wrap('5'))}{{me}}wo}}{{rld")
+    globals.something = snap.wrap(
+        f'hellohelp{globals.something}mehelp{globals.something}meworld'
+    )
+    globals.something = snap.wrap('hellohelp8mehelp8meworld')
+    globals.something = snap.wrap(
+        f"hellohelp{snap.wrap(3) + snap.wrap('{}') + snap.wrap(5)}mehelp{snap.wrap(3) + snap.wrap('{}') + snap.wrap(5)}meworld"
+    )
+    globals.something = snap.wrap('h{el}lohelp8mehelp8meworld')
+    globals.something = snap.wrap('hellohelp8mehelp8me}wor{ld')
+    globals.something = snap.wrap('helloh{elp}8meh{elp}8meworld')
+    globals.something = snap.wrap('hellohelp8m}{ehelp8m}{eworld')
+    globals.something = snap.wrap(
+        f"he{{l}}loh{{el}}p{snap.wrap(3) + snap.wrap('{}') + snap.wrap(5)}{{me}}h{{el}}p{snap.wrap(3) + snap.wrap('{}') + snap.wrap(5)}{{me}}wo}}{{rld"
+    )
     globals.something = snap.wrap('he{l}loh{el}p{}{me}h{el}p{}{me}wo}{rld')
 "#.trim());
 }
@@ -630,51 +987,65 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     time.sleep(2.4)
     time.sleep(+snap.wrap('merp'))
     nb.send_message('local::my msg thing')
     return snap.wrap('765')
 
 @onkey('space')
-def my_onkey_2(self):
-    while not ((globals.foo + snap.wrap('2')) == snap.wrap('7')):
+def on_key_space(self):
+    while not globals.foo + snap.wrap(2) == snap.wrap(7):
         time.sleep(0.05)
     raise RuntimeError(str(snap.wrap('oopsie!')))
 
 @onmouse('up')
-def my_onmouse_3(self, x, y):
+def on_mouse_up(self, _x, _y):
     globals.foo = snap.wrap('Mouse Up!')
     while not globals.foo:
         try:
             for item in globals.bar:
-                globals.foo = item[snap.wrap('1') - snap.wrap(1)]
+                globals.foo = item[0]
                 globals.bar = item.last
         except Exception as err:
             globals.bar.append(err)
             globals.foo.append(snap.wrap(f'got error: {err}'))
 
 @onmouse('down')
-def my_onmouse_4(self, x, y):
+def on_mouse_down(self, _x, _y):
     with NoYield():
         globals.foo = snap.wrap('Mouse Down!')
         globals.foo = snap.wrap('more stuff')
 
 @onmouse('scroll-up')
-def my_onmouse_5(self, x, y):
+def on_scroll_up(self, _x, _y):
     globals.foo = snap.wrap('Scroll Up!')
     for _ in range(6):
         globals.foo = snap.wrap('starting...')
-        nothrow(nb.call)('Chart', 'draw', lines = nothrow(nb.call)('MaunaLoaCO2Data', 'getCO2Trend', startyear = '', endyear = ''), options = '')
+        nothrow(nb.call)(
+            'Chart',
+            'draw',
+            lines = nothrow(nb.call)(
+                'MaunaLoaCO2Data', 'getCO2Trend', startyear = '', endyear = ''
+            ),
+            options = '',
+        )
         globals.foo = snap.wrap('done!')
     for _ in range(+snap.wrap('seven')):
         globals.foo = snap.wrap('starting...')
-        nothrow(nb.call)('Chart', 'draw', lines = nothrow(nb.call)('MaunaLoaCO2Data', 'getCO2Trend', startyear = '', endyear = ''), options = '')
+        nothrow(nb.call)(
+            'Chart',
+            'draw',
+            lines = nothrow(nb.call)(
+                'MaunaLoaCO2Data', 'getCO2Trend', startyear = '', endyear = ''
+            ),
+            options = '',
+        )
         globals.foo = snap.wrap('done!')
 
 @onmouse('scroll-down')
-def my_onmouse_6(self, x, y):
-    if (globals.bar or globals.foo):
+def on_scroll_down(self, _x, _y):
+    if globals.bar or globals.foo:
         globals.foo = snap.wrap('Scroll Down!')
         globals.bar = snap.wrap('more')
     else:
@@ -682,15 +1053,15 @@ def my_onmouse_6(self, x, y):
         self.clone()
 
 @nb.on_message('local::my msg thing')
-def my_on_message_7(self):
+def on_local_message_my_msg_thing(self):
     while True:
-        globals.foo = (globals.foo if (globals.foo > globals.bar) else globals.bar)
+        globals.foo = globals.foo if globals.foo > globals.bar else globals.bar
         globals.bar = self.clone()
 
 @onstart('clone')
-def my_onstart_8(self):
+def on_clone(self):
     for xyz in snap.sxrange(4, 8):
-        if (snap.sqrt(xyz) < snap.wrap('9')):
+        if snap.sqrt(xyz) < snap.wrap(9):
             globals.foo = snap.wrap('agony!!')
             globals.bar = snap.wrap('pain!!')
 "#.trim());
@@ -705,19 +1076,30 @@ from netsblox import snap
 
 def barkbark():
     a = snap.wrap(0)
-    (lambda: (snap.wrap('6') + snap.wrap('3')))()
-    (lambda _1: (_1 + snap.wrap('3')))(snap.wrap('12'))
-    (lambda _1, _2: (_1 * _2))(snap.wrap('31'), snap.wrap('8'))
-    a = (lambda: (snap.wrap('6') + snap.wrap('3')))()
+    (lambda: snap.wrap(9))()
+    (lambda _1: _1 + snap.wrap(3))(snap.wrap('12'))
+    (lambda _1, _2: _1 * _2)(snap.wrap('31'), snap.wrap('8'))
+    a = (lambda: snap.wrap(9))()
     a = (lambda _1: snap.combinations(_1, ['6', '9']))(snap.wrap('12'))
     a = (lambda _1, _2: snap.combinations(_1, _2))(snap.wrap('31'), snap.wrap('8'))
-    a = (lambda: (True and False))()
-    a = (lambda _1: (_1 and True))(False)
-    a = (lambda _1, _2: (_1 and _2))(True, False)
-    a = snap.wrap([(lambda _1: (_1 ** snap.wrap('2')))(x) for x in snap.srange('1', '10')])
-    a = snap.wrap([x for x in snap.srange('1', '10') if (lambda _1: ((_1 % snap.wrap('2')) == snap.wrap('0')))(x)])
-    a = snap.srange('1', '100').index_where((lambda xc: (((xc % snap.wrap('7')) == snap.wrap('0')) and ((xc % snap.wrap('5')) == snap.wrap('0')))))
-    a = snap.srange('1', '100').fold((lambda _1, _2: (_1 + _2)))
+    a = (lambda: False)()
+    a = (lambda _1: _1 and True)(False)
+    a = (lambda _1, _2: _1 and _2)(True, False)
+    a = snap.wrap(
+        [(lambda _1: _1 ** snap.wrap(2))(_x) for _x in snap.srange('1', '10')]
+    )
+    a = snap.wrap(
+        [
+            _x
+            for _x in snap.srange('1', '10')
+            if (lambda _1: _1 % snap.wrap(2) == snap.wrap(0))(_x)
+        ]
+    )
+    a = snap.srange('1', '100').index_where(
+        lambda xc: xc % snap.wrap(7) == snap.wrap(0)
+        and xc % snap.wrap(5) == snap.wrap(0)
+    )
+    a = snap.srange('1', '100').fold(lambda _1, _2: _1 + _2)
 "#.trim());
     assert_code_eq!(code[1].trim(), r#"
 last_answer = snap.wrap('')
@@ -738,7 +1120,7 @@ def __init__(self):
 
 #[test]
 fn test_empty_blocks() {
-    let code = get_code(include_str!("projects/empty-blocks.xml")).unwrap();
+    let code = get_code_with_options(include_str!("projects/empty-blocks.xml"), &TranslateOptions { opt_level: OptLevel::None, ..Default::default() }).unwrap();
     assert_eq!(code.len(), 3);
     assert_code_eq!(code[0].trim(), r#"
 from netsblox import snap
@@ -759,7 +1141,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     with NoYield():
         pass
     for _ in range(10):
@@ -785,6 +1167,287 @@ def my_onstart_1(self):
 "#.trim());
 }
 
+#[test]
+fn test_opt_levels() {
+    let code = get_code_with_options(include_str!("projects/operators.xml"), &TranslateOptions { opt_level: OptLevel::Fold, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+def foo():
+    bar = snap.wrap(0)
+    bar = snap.wrap(5)
+    bar = snap.wrap(12)
+    bar = snap.wrap(sum(baz()))
+    bar = snap.wrap(3)
+    bar = snap.wrap(12)
+    bar = snap.wrap(96)
+    bar = snap.prod(baz())
+    bar = snap.wrap(2.6666666666666665)
+    bar = snap.wrap(16)
+    bar = snap.wrap(1)
+    bar = round(snap.wrap('6.4'))
+    bar = snap.wrap(10)
+    bar = snap.wrap(-10)
+    bar = snap.sign('10')
+    bar = snap.wrap(10)
+    bar = snap.wrap(10)
+    bar = snap.sqrt('10')
+    bar = snap.sin('10')
+    bar = snap.cos('10')
+    bar = snap.tan('10')
+    bar = snap.asin('10')
+    bar = snap.acos('10')
+    bar = snap.atan('10')
+    bar = snap.log('10', math.e)
+    bar = snap.log('10', 10)
+    bar = snap.log('10', 2)
+    bar = snap.wrap(math.e ** 10)
+    bar = snap.wrap(10000000000)
+    bar = snap.wrap(1024)
+    bar = snap.wrap('10')
+    bar = snap.atan2('6', '5')
+    bar = min(snap.wrap(['2', '4']))
+    bar = min(snap.wrap(['2', '4', '7']))
+    bar = min(baz())
+    bar = max(snap.wrap(['5', '2']))
+    bar = max(snap.wrap(['5', '2', '98']))
+    bar = max(baz())
+    bar = snap.rand('1', '10')
+    bar = False
+    bar = False
+    bar = False
+    bar = True
+    bar = snap.identical('6', '3')
+    bar = True
+    bar = True
+    bar = False
+    bar = True
+    bar = False
+    bar = True
+    bar = False
+    bar = True
+    bar = snap.wrap('hello world')
+    bar = snap.wrap('hello worldagain')
+    bar = snap.wrap(''.join(str(_x) for _x in baz()))
+    bar = snap.wrap(len('world'))
+    bar = snap.split('hello world', ' ')
+    bar = snap.split('hello world', '')
+    bar = snap.split_words('hello world')
+    bar = snap.split('hello world', '\n')
+    bar = snap.split('hello world', '\t')
+    bar = snap.split('hello world', '\r')
+    bar = snap.split_csv('hello world')
+    bar = snap.split_json('hello world')
+    bar = snap.get_ord('c')
+    bar = snap.get_chr('87')
+    bar = snap.is_number('5')
+    bar = snap.is_text('5')
+    bar = snap.is_bool('5')
+    bar = snap.is_list('5')
+    bar = snap.is_sprite('5')
+    bar = snap.is_costume('5')
+    bar = snap.is_sound('5')
+
+def baz():
+    return snap.srange('1', '7')
+
+def another():
+    return snap.wrap('hello')
+"#.trim());
+
+    let code = get_code_with_options(include_str!("projects/empty-blocks.xml"), &TranslateOptions { opt_level: OptLevel::Full, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onstart()
+def on_start(self):
+    with NoYield():
+        pass
+    for _ in range(10):
+        pass
+    for i in snap.sxrange(1, 10):
+        pass
+    try:
+        pass
+    except Exception as err:
+        pass
+    for item in i:
+        pass
+    while True:
+        pass
+"#.trim());
+}
+
+#[test]
+fn test_idiomatic() {
+    let code = get_code_with_options(include_str!("projects/variables.xml"), &TranslateOptions { idiomatic: true, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+def foobar():
+    a = 0
+    a = []
+    a = ['4', '1', ['2', 4]]
+    a = [*a]
+    a += 1
+    a = list(range(1, 11))
+    a = ['23', *a]
+    a = a[1:]
+    a = a[0]
+    a = a[3]
+    a = a[4]
+    a = a[-1]
+    a = random.choice(a)
+    # warning: no plain python equivalent for snap.wrap
+    a = a[snap.wrap(['1', '3', '2']) - 1]
+    a = a.index('thing') + 1
+    a = 'thing' in a
+    a = len(a) == 0
+    a = len(a)
+    # warning: no plain python equivalent for .shape
+    a = len(a.shape)
+    # warning: no plain python equivalent for .shape
+    a = a.shape
+    # warning: no plain python equivalent for .flat
+    a = a.flat
+    # warning: no plain python equivalent for .T
+    a = a.T
+    a = a[::-1]
+    a = '\n'.join(str(_x) for _x in a)
+    # warning: no plain python equivalent for .csv
+    a = a.csv
+    # warning: no plain python equivalent for .json
+    a = a.json
+    for item in a:
+        a.append(item)
+        a.append('abc')
+        a.pop()
+        del a[0]
+        del a[6]
+        del a[4]
+        a.clear()
+        a.insert(0, 'abc')
+        a.insert(15, 'abc')
+        a.insert(3, 'abc')
+        a.append('abc')
+        # warning: no plain python equivalent for .insert_rand
+        a.insert_rand('abc')
+        a[0] = 'zyx'
+        a[5] = 'zyx'
+        a[6] = 'zyx'
+        a[-1] = 'zyx'
+        # warning: no plain python equivalent for .rand
+        a.rand = 'zyx'
+    a = []
+    a = [*[]]
+    a = [*[], *[]]
+    a = [*[], *[], *[]]
+    a = [_y for _x in a for _y in _x]
+    # warning: no plain python equivalent for .reshaped
+    a = [].reshaped([])
+    # warning: no plain python equivalent for .reshaped
+    a = [].reshaped(['5'])
+    # warning: no plain python equivalent for .reshaped
+    a = [].reshaped(['5', '3'])
+    # warning: no plain python equivalent for .reshaped
+    a = [].reshaped(a)
+    # warning: no plain python equivalent for snap.combinations
+    a = snap.combinations()
+    # warning: no plain python equivalent for snap.combinations
+    a = snap.combinations([])
+    # warning: no plain python equivalent for snap.combinations
+    a = snap.combinations([], [])
+    # warning: no plain python equivalent for snap.combinations
+    a = snap.combinations([], [], [])
+    # warning: no plain python equivalent for snap.combinations
+    a = snap.combinations(*a)
+    a = 'world'[0]
+    a = 'world'[4]
+    a = 'world'[-1]
+    a = random.choice('world')
+    a = len('hello world')
+"#.trim());
+}
+
+#[test]
+fn test_idiomatic_numeric_text() {
+    // text which looks like a number is only turned into a number where python needs one
+    let code = get_code_with_options(include_str!("projects/numeric-text.xml"), &TranslateOptions { idiomatic: true, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+nb.message_type('data', ['1'])
+"#.trim());
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onkey('1')
+def on_key_1(self):
+    nb.send_message('data', 'everyone in room', **{ '1': '7' })
+
+@nb.on_message('data')
+def on_message_data(self, _sender = None, /, **_fields):
+    var_1 = _fields['1']
+
+    self.say(var_1)
+"#.trim());
+}
+
+#[test]
+fn test_idiomatic_numeric_inputs() {
+    // numeric blocks given text (from literals or inputs) must still do arithmetic
+    let code = get_code_with_options(include_str!("projects/numeric-inputs.xml"), &TranslateOptions { idiomatic: true, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+fr = 0
+
+def double(x):
+    # warning: no plain python equivalent for snap.wrap
+    return snap.wrap(x) * 2
+"#.trim());
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onstart()
+def on_start(self):
+    globals.fr += 1
+    globals.fr = double('3')
+    globals.fr = round(6.4)
+    # warning: no plain python equivalent for snap.wrap
+    globals.fr = round(snap.wrap(globals.fr))
+    globals.fr = max([5, 2, 98])
+    globals.fr = True
+    # warning: no plain python equivalent for snap.is_number
+    globals.fr = snap.is_number(globals.fr)
+    # warning: no plain python equivalent for snap.wrap
+    globals.fr = 10 < snap.wrap(globals.fr)
+"#.trim());
+}
+
 #[test]
 fn test_elif_opt() {
     let code = get_code(include_str!("projects/elif-opt.xml")).unwrap();
@@ -808,7 +1471,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     a = snap.wrap(0)
     b = snap.wrap(0)
     c = snap.wrap(0)
@@ -865,6 +1528,27 @@ def my_onstart_1(self):
 "#.trim());
 }
 
+#[test]
+fn test_return_types() {
+    // a reporter which can fall off the end returns None, so its result is not known to be a bool
+    let code = get_code(include_str!("projects/returns.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+def maybe():
+    if snap.rand('1', '10') < snap.wrap(5):
+        return True
+
+def always():
+    if snap.rand('1', '10') < snap.wrap(5):
+        return True
+    else:
+        return False
+"#.trim());
+    assert!(code[2].contains("    self.say(snap.lnot(maybe()))\n    self.say(not always())\n"), "{}", code[2]);
+}
+
 #[test]
 fn test_timer() {
     let code = get_code(include_str!("projects/timer.xml")).unwrap();
@@ -890,7 +1574,7 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     Stage.timer = 0
     globals.fr = snap.wrap(Stage.timer)
 "#.trim());
@@ -921,14 +1605,14 @@ def __init__(self):
     self.costume = None
 
 @onstart()
-def my_onstart_1(self):
+def on_start(self):
     self.clone()
     Sprite_2.clone()
     globals.fr = self.clone()
     globals.fr = Sprite_2.clone()
 
 @onstart('clone')
-def my_onstart_2(self):
+def on_clone(self):
     self.say('what is my purpose?')
 "#.trim());
 assert_code_eq!(code[3].trim(), r#"
@@ -941,3 +1625,544 @@ def __init__(self):
     self.costume = None
 "#.trim());
 }
+
+#[test]
+fn test_rpcs() {
+    let code = get_code(include_str!("projects/rpcs.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onstart()
+def on_start(self):
+    nothrow(nb.call)('Weather', 'temperature', lat = '12', lng = '34')
+    globals.fr = snap.wrap(
+        nothrow(nb.call)(str(globals.svc), 'temperature', lat = '12', lng = globals.fr)
+    )
+    globals.fr = snap.wrap(nothrow(nb.call)('PublicRoles', str(globals.rpc)))
+    nothrow(nb.call)(
+        str(globals.svc),
+        str(globals.rpc),
+        **dict((str(_k), _v) for _k, _v in globals.args),
+    )
+"#.trim());
+}
+
+#[test]
+fn test_messages() {
+    let code = get_code(include_str!("projects/messages.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+fr = snap.wrap('0')
+
+nb.message_type('ask score', ['round'])
+nb.message_type('chat', ['sender', 'text'])
+nb.message_type('game over', ['score', 'player name'])
+nb.message_type('message', ['msg'])
+nb.message_type('ping', [])
+"#.trim());
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onstart()
+def on_start(self):
+    nb.send_message('message', 'everyone in room', msg = 'hello')
+    nb.send_message(
+        'game over', 'other@room', score = '10', **{ 'player name': globals.fr }
+    )
+    globals.fr = snap.wrap(nb.send_request('ask score', 'other@room', round = '3'))
+
+@nb.on_message('game over')
+def on_message_game_over(self, _sender = None, /, **_fields):
+    score = snap.wrap(_fields['score'])
+    player_name = snap.wrap(_fields['player name'])
+
+    self.say(score)
+
+@nb.on_message('ask score')
+def on_message_ask_score(self, _sender = None, /, **_fields):
+    round_ = snap.wrap(_fields['round'])
+
+    self.say(_sender)
+    nb.send_reply(_sender, globals.fr)
+
+@nb.on_message('chat')
+def on_message_chat(self, _sender = None, /, **_fields):
+    sender = snap.wrap(_fields['sender'])
+    text = snap.wrap(_fields['text'])
+
+    nb.send_reply(_sender, sender)
+"#.trim());
+}
+
+#[test]
+fn test_identity() {
+    let code = get_code(include_str!("projects/identity.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onstart()
+def on_start(self):
+    globals.fr = snap.wrap(nb.public_id)
+    globals.fr = snap.wrap(nb.username)
+    globals.fr = snap.wrap(nb.role_name)
+    globals.fr = snap.wrap(nb.room_name)
+    for role in snap.wrap(nb.room_roles):
+        nb.send_message('message', role, msg = nb.username)
+"#.trim());
+}
+
+#[test]
+fn test_async_rpcs() {
+    match get_code(include_str!("projects/async-rpcs.xml")) {
+        Err(TranslateError::UnsupportedStmt(x)) => assert!(matches!(x.kind, StmtKind::ForkClosure { .. })),
+        x => panic!("{x:?}"),
+    }
+
+    let code = get_code_with_options(include_str!("projects/async-rpcs.xml"), &TranslateOptions { async_rpcs: true, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+@onstart()
+def on_start(self):
+    nothrow(nb.call_async)('Weather', 'temperature', lat = '12', lng = '34')
+    nothrow(nb.call_async)(
+        'Weather', 'temperature', lat = '12', lng = '34'
+    ).add_done_callback(lambda _x: globals.cb(snap.wrap(_x.result())))
+    nothrow(nb.call)('Weather', 'temperature', lat = '12', lng = '34')
+
+@onkey('space')
+def on_key_space(self):
+    nothrow(nb.call_async)('Weather', 'temperature', lat = '12', lng = '34')
+    globals.fr = snap.wrap(
+        nothrow(nb.call)('Weather', 'temperature', lat = '12', lng = '34')
+    )
+"#.trim());
+
+    // the marker must be the whole comment, which is otherwise kept as usual
+    let xml = include_str!("projects/async-rpcs.xml").replace(">@async<", ">not @async<");
+    let code = get_code_with_options(&xml, &TranslateOptions { async_rpcs: true, ..Default::default() }).unwrap();
+    assert!(code[2].contains("# not @async\n@onkey('space')\ndef on_key_space(self):\n    nothrow(nb.call)('Weather'"), "{}", code[2]);
+}
+
+#[test]
+fn test_comments() {
+    let code = get_code(include_str!("projects/comments.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+fr = snap.wrap('0')
+
+def double(x):
+    """Doubles x."""
+    return x * snap.wrap(2)
+"#.trim());
+    assert_code_eq!(code[1].trim(), r#"
+last_answer = snap.wrap('')
+
+def __init__(self):
+    self.costume = None
+
+# stage notes
+# second line
+"#.trim());
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+def greet(self, who):
+    """Says hello.
+
+    Uses "quotes".
+    """
+    self.say(f'hello {who}')
+
+# runs at start
+@onstart()
+def on_start(self):
+    # set up
+    # the counter
+    globals.fr = double(snap.wrap('3'))
+    self.greet(snap.wrap('world'))
+
+# todo: more <stuff>
+
+# another note
+"#.trim());
+}
+
+#[test]
+fn test_keywords() {
+    let proj = serde_json::from_str::<serde_json::Value>(&translate(include_str!("projects/keywords.xml")).unwrap().1).unwrap();
+    assert_eq!(proj["roles"][0]["editors"][2]["name"], "class_");
+
+    let code = get_code(include_str!("projects/keywords.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+lambda_ = snap.wrap('0')
+
+def len_(yield_):
+    return yield_ * snap.wrap(2)
+"#.trim());
+    assert_code_eq!(code[2].trim(), r#"
+None_ = snap.wrap('1')
+
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+def print_(self, async_):
+    self.say(async_)
+
+@onstart()
+def on_start(self):
+    input_ = snap.wrap(0)
+    input_ = len_(self.None_)
+    globals.lambda_ = input_
+    self.print_(globals.lambda_)
+"#.trim());
+}
+
+#[test]
+fn test_unicode_idents() {
+    assert!(get_code(include_str!("projects/unicode.xml")).is_err()); // ascii names cannot represent 分数 at all
+
+    let code = get_code_with_options(include_str!("projects/unicode.xml"), &TranslateOptions { unicode_idents: true, ..Default::default() }).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[0].trim(), r#"
+from netsblox import snap
+
+puntuación = snap.wrap('0')
+
+def doble(x):
+    return x * snap.wrap(2)
+"#.trim());
+    assert_code_eq!(code[2].trim(), r#"
+分数 = snap.wrap('1')
+
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+def decir(self, texto):
+    self.say(texto)
+
+@onstart()
+def on_start(self):
+    número_de_vidas = snap.wrap(0)
+    número_de_vidas = doble(self.分数)
+    globals.puntuación = número_de_vidas
+    self.decir(globals.puntuación)
+"#.trim());
+}
+
+#[test]
+fn test_names() {
+    let got = serde_json::from_str::<serde_json::Value>(&translate(include_str!("projects/keywords.xml")).unwrap().1).unwrap();
+    assert_eq!(got["roles"][0]["names"], json!({
+        "globals": {
+            "variables": { "lambda": "lambda_" },
+            "locals": { "yield": "yield_" },
+            "blocks": { "len %'yield'": "len_" },
+        },
+        "sprites": {
+            "Stage": {
+                "name": "Stage",
+                "variables": {},
+                "locals": {},
+                "blocks": {},
+                "costumes": {},
+                "sounds": {},
+            },
+            "class": {
+                "name": "class_",
+                "variables": { "None": "None_" },
+                "locals": { "async": "async_", "input": "input_" },
+                "blocks": { "print %'async'": "print_" },
+                "costumes": {},
+                "sounds": {},
+            },
+        },
+    }));
+}
+
+#[test]
+fn test_script_names() {
+    let code = get_code(include_str!("projects/scripts.xml")).unwrap();
+    assert_eq!(code.len(), 3);
+    assert_code_eq!(code[2].trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+def on_start(self):
+    pass
+
+@onstart()
+def on_start_2(self):
+    self.on_start()
+
+@onstart()
+def on_start_3(self):
+    self.forward(10)
+
+@onstart()
+def watch_condition(self):
+    while True:
+        try:
+            time.sleep(0.05)
+            if self.x_pos < 0:
+                self.on_condition()
+        except Exception as _e:
+            import traceback, sys
+            print(traceback.format_exc(), file = sys.stderr)
+def on_condition(self):
+    self.x_pos = 0
+
+@onkey('up arrow')
+def on_key_up_arrow(self):
+    self.forward(10)
+
+@onkey('up arrow')
+def on_key_up_arrow_2(self):
+    self.turn_right(15)
+"#.trim());
+}
+
+
+#[test]
+fn test_source_map() {
+    let got = serde_json::from_str::<serde_json::Value>(&translate(include_str!("projects/sourcemap.xml")).unwrap().1).unwrap();
+    let editors = &got["roles"][0]["editors"];
+    assert_eq!(editors[0]["source_map"], json!([
+        { "lines": [6, 6], "role": "myRole", "entity": null, "script": "twice %'x'", "block": "item_12", "path": [0] },
+        { "lines": [5, 6], "role": "myRole", "entity": null, "script": "twice %'x'", "block": null, "path": [], "hash": "4cec3bc6e4bedf12" },
+    ]));
+    assert_eq!(editors[1]["source_map"], json!([]));
+    assert_code_eq!(editors[2]["value"].as_str().unwrap().trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+def jump(self):
+    self.forward(10)
+
+@onstart()
+def on_start(self):
+    globals.fr = snap.wrap('0')
+    if globals.fr < snap.wrap(5):
+        globals.fr += snap.wrap('1')
+    else:
+        self.say('done')
+    for _ in range(3):
+        self.forward(10)
+        self.jump()
+"#.trim());
+    assert_eq!(editors[2]["source_map"], json!([
+        { "lines": [10, 10], "role": "myRole", "entity": "Sprite", "script": "jump", "block": "item_8", "path": [0] },
+        { "lines": [9, 10], "role": "myRole", "entity": "Sprite", "script": "jump", "block": null, "path": [], "hash": "b0d5ced3c82305a4" },
+        { "lines": [14, 14], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_2", "path": [0] },
+        { "lines": [16, 16], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_4", "path": [1, 0, 0] },
+        { "lines": [18, 18], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_5", "path": [1, 1, 0] },
+        { "lines": [15, 18], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_3", "path": [1] },
+        { "lines": [20, 20], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_7", "path": [2, 0, 0] },
+        { "lines": [21, 21], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_11", "path": [2, 0, 1] },
+        { "lines": [19, 21], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_6", "path": [2] },
+        { "lines": [12, 21], "role": "myRole", "entity": "Sprite", "script": "on_start", "block": "item_1", "path": [], "hash": "44427055ad26ab3d" },
+    ]));
+}
+
+#[test]
+fn test_imports() {
+    let imports = |xml: &str, options: &TranslateOptions| translate_project(xml, options).unwrap().1.roles[0].imports.clone();
+    assert_eq!(imports(include_str!("projects/operators.xml"), &TranslateOptions::default()), ["math"]);
+    assert_eq!(imports(include_str!("projects/scripts.xml"), &TranslateOptions::default()), ["time"]);
+    assert!(imports(include_str!("projects/sourcemap.xml"), &TranslateOptions::default()).is_empty());
+
+    let options = TranslateOptions {
+        imports: Some(vec!["math".into(), "json".into()]),
+        block_sources: vec!["https://example.com/blocks.json".into(), DEFAULT_BLOCK_SOURCE.into()],
+        ..Default::default()
+    };
+    let (_, proj) = translate_project(include_str!("projects/scripts.xml"), &options).unwrap();
+    assert_eq!(proj.roles[0].imports, ["math", "json"]);
+    assert_eq!(proj.roles[0].block_sources, ["https://example.com/blocks.json", "netsblox://assets/default-blocks.json"]);
+}
+
+#[test]
+fn test_blocks() {
+    let got = serde_json::from_str::<serde_json::Value>(&translate(include_str!("projects/sourcemap.xml")).unwrap().1).unwrap();
+    assert_eq!(got["roles"][0]["blocks"], json!([
+        { "name": "twice %'x'", "spec": "twice %n", "category": "operators", "type": "reporter", "entity": null, "function": "twice" },
+        { "name": "jump", "spec": "jump", "category": "motion", "type": "command", "entity": "Sprite", "function": "jump" },
+    ]));
+
+    let (_, proj) = translate_project(include_str!("projects/keywords.xml"), &TranslateOptions::default()).unwrap();
+    assert_eq!(proj.roles[0].blocks, [
+        project::Block { name: "len %'yield'".into(), spec: "len %n".into(), category: "operators".into(), ty: project::BlockType::Reporter, entity: None, function: "len_".into() },
+        project::Block { name: "print %'async'".into(), spec: "print %s".into(), category: "looks".into(), ty: project::BlockType::Command, entity: Some("class_".into()), function: "print_".into() },
+    ]);
+}
+
+
+#[test]
+fn test_merge() {
+    let xml = include_str!("projects/sourcemap.xml");
+    let (_, mut old) = translate_project(xml, &TranslateOptions::default()).unwrap();
+    let same = merge(&serde_json::to_string(&old).unwrap(), xml, &TranslateOptions::default()).unwrap();
+    assert_eq!(same.project, old);
+    assert_eq!(same.conflicts, []);
+
+    let role = &mut old.roles[0];
+    role.imports.push("json".into());
+    role.editors[0].value += "def util():\n    return 1\n";
+    role.editors[2].value = role.editors[2].value.replace("    self.forward(10)\n\n", "    self.forward(10)\n    self.turn_right(15)\n\n");
+    role.editors[2].value += "# my helper\ndef helper(self):\n    return [\n1,\n]\n";
+
+    // change the repeat count, the body of the sprite's custom block, and delete the global custom block
+    let new_xml = xml.replace("<l>3</l>", "<l>4</l>").replace(r#"<block collabId="item_8" s="forward"><l>10</l>"#, r#"<block collabId="item_8" s="forward"><l>20</l>"#);
+    let start = new_xml.find(r#"<blocks><block-definition s="twice"#).unwrap();
+    let end = start + new_xml[start..].find("</blocks>").unwrap();
+    let new_xml = format!("{}<blocks>{}", &new_xml[..start], &new_xml[end..]);
+
+    let res = merge(&serde_json::to_string(&old).unwrap(), &new_xml, &TranslateOptions::default()).unwrap();
+    assert_eq!(res.conflicts, [merge::MergeConflict::Function { role: "myRole".into(), editor: "Sprite".into(), name: "jump".into() }]);
+    let role = &res.project.roles[0];
+    assert_eq!(role.imports, ["json"]);
+    assert_code_eq!(role.editors[0].value.trim(), r#"
+from netsblox import snap
+
+fr = snap.wrap('0')
+
+def util():
+    return 1
+"#.trim());
+    assert_eq!(role.editors[0].source_map, []);
+    assert_code_eq!(role.editors[2].value.trim(), r#"
+def __init__(self):
+    self.pos = (0, 0)
+    self.heading = 90
+    self.pen_color = (80, 80, 80)
+    self.scale = 1
+    self.visible = True
+    self.costume = None
+
+def jump(self):
+    self.forward(10)
+    self.turn_right(15)
+
+@onstart()
+def on_start(self):
+    globals.fr = snap.wrap('0')
+    if globals.fr < snap.wrap(5):
+        globals.fr += snap.wrap('1')
+    else:
+        self.say('done')
+    for _ in range(4):
+        self.forward(10)
+        self.jump()
+
+# my helper
+def helper(self):
+    return [
+1,
+]
+"#.trim());
+    let lines: Vec<_> = role.editors[2].source_map.iter().map(|x| (x.lines, x.block.as_deref())).collect();
+    assert_eq!(lines, [
+        ((15, 15), Some("item_2")), ((17, 17), Some("item_4")), ((19, 19), Some("item_5")), ((16, 19), Some("item_3")),
+        ((21, 21), Some("item_7")), ((22, 22), Some("item_11")), ((20, 22), Some("item_6")), ((13, 22), Some("item_1")),
+    ]);
+    assert_eq!(res.project.validate(), []);
+
+    // a sprite which no longer exists is only kept if it has hand-written code
+    let (_, mut old) = translate_project(xml, &TranslateOptions::default()).unwrap();
+    let mut extra = old.roles[0].editors[2].clone();
+    extra.name = "Extra".into();
+    old.roles[0].editors.push(extra.clone());
+    let res = merge(&serde_json::to_string(&old).unwrap(), xml, &TranslateOptions::default()).unwrap();
+    assert_eq!(res.project.roles[0].editors.len(), 3);
+    assert_eq!(res.conflicts, []);
+
+    extra.value += "def helper(self):\n    pass\n";
+    old.roles[0].editors[3] = extra;
+    let res = merge(&serde_json::to_string(&old).unwrap(), xml, &TranslateOptions::default()).unwrap();
+    assert_eq!(res.project.roles[0].editors.len(), 4);
+    assert_eq!(res.conflicts, [merge::MergeConflict::Editor { role: "myRole".into(), editor: "Extra".into() }]);
+
+    // editing a translated line in place makes the function hand-written, which is only a conflict if its blocks changed
+    let (_, mut old) = translate_project(xml, &TranslateOptions::default()).unwrap();
+    old.roles[0].editors[2].value = old.roles[0].editors[2].value.replace("        globals.fr += snap.wrap('1')\n", "        globals.fr += 1\n");
+    let res = merge(&serde_json::to_string(&old).unwrap(), xml, &TranslateOptions::default()).unwrap();
+    assert_eq!(res.conflicts, []);
+    assert!(res.project.roles[0].editors[2].value.contains("        globals.fr += 1\n"));
+    assert!(!res.project.roles[0].editors[2].source_map.iter().any(|x| x.script == "on_start"));
+
+    let res = merge(&serde_json::to_string(&old).unwrap(), &xml.replace("<l>3</l>", "<l>4</l>"), &TranslateOptions::default()).unwrap();
+    assert_eq!(res.conflicts, [merge::MergeConflict::Function { role: "myRole".into(), editor: "Sprite".into(), name: "on_start".into() }]);
+    assert!(res.project.roles[0].editors[2].value.contains("        globals.fr += 1\n    else:\n        self.say('done')\n    for _ in range(3):\n"));
+
+    // the same goes for other statements, such as the initial values of global variables
+    let (_, mut old) = translate_project(xml, &TranslateOptions::default()).unwrap();
+    old.roles[0].editors[0].value = old.roles[0].editors[0].value.replace("fr = snap.wrap('0')\n", "fr = snap.wrap(0)\n");
+    let res = merge(&serde_json::to_string(&old).unwrap(), xml, &TranslateOptions::default()).unwrap();
+    assert_eq!(res.conflicts, []);
+    assert!(res.project.roles[0].editors[0].value.contains("\nfr = snap.wrap(0)\n"));
+    assert!(!res.project.roles[0].editors[0].hashes.contains_key("fr"));
+
+    let res = merge(&serde_json::to_string(&old).unwrap(), &xml.replace(r#"<variable name="fr"><l>0</l>"#, r#"<variable name="fr"><l>7</l>"#), &TranslateOptions::default()).unwrap();
+    assert_eq!(res.conflicts, [merge::MergeConflict::Statement { role: "myRole".into(), editor: "globals".into(), key: "fr".into() }]);
+    assert!(res.project.roles[0].editors[0].value.contains("\nfr = snap.wrap(0)\n"));
+}